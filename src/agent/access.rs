@@ -0,0 +1,338 @@
+//! Per-socket access control for SSH agent protocol messages
+//!
+//! REQUEST_IDENTITIES and SIGN_REQUEST are always handled by the filter.
+//! Every other message is forwarded to upstream only if the corresponding
+//! feature flag allows it; otherwise the proxy answers with FAILURE. Messages
+//! no flag covers (unknown, legacy or future types) are refused as soon as a
+//! socket sets any message flag.
+//! The socket mode additionally restricts listing and signing, and signing
+//! of data that is neither an SSH login nor an SSHSIG signature is refused
+//! unless `allow_unknown_sign` is set.
+
+use crate::protocol::{MessageType, SignPayload};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// Which of listing and signing a socket serves
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SocketMode {
+    /// List filtered keys and sign with them (default)
+    #[default]
+    Full,
+    /// List filtered keys but never sign (inventory/auditing tools)
+    ListOnly,
+    /// Sign with an explicitly requested key but advertise no keys
+    SignOnly,
+}
+
+impl SocketMode {
+    /// All mode names, for CLI value validation and completion
+    pub const NAMES: &'static [&'static str] = &["full", "list-only", "sign-only"];
+
+    /// Get the mode name as used in CLI and config
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SocketMode::Full => "full",
+            SocketMode::ListOnly => "list-only",
+            SocketMode::SignOnly => "sign-only",
+        }
+    }
+
+    /// Check if this is the default mode
+    pub fn is_full(&self) -> bool {
+        *self == SocketMode::Full
+    }
+
+    /// Whether REQUEST_IDENTITIES returns the filtered key list
+    pub fn allows_listing(&self) -> bool {
+        *self != SocketMode::SignOnly
+    }
+
+    /// Whether SIGN_REQUEST is forwarded for allowed keys
+    pub fn allows_signing(&self) -> bool {
+        *self != SocketMode::ListOnly
+    }
+}
+
+impl fmt::Display for SocketMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for SocketMode {
+    type Err = crate::Error;
+
+    fn from_str(s: &str) -> crate::Result<Self> {
+        match s {
+            "full" => Ok(SocketMode::Full),
+            "list-only" => Ok(SocketMode::ListOnly),
+            "sign-only" => Ok(SocketMode::SignOnly),
+            _ => Err(crate::Error::Config(format!(
+                "Unknown socket mode '{}' (expected one of: {})",
+                s,
+                Self::NAMES.join(", ")
+            ))),
+        }
+    }
+}
+
+/// Feature flags controlling which agent operations a socket may perform
+///
+/// Each flag is optional so that socket-level settings can override
+/// upstream-level settings. `None` means "inherit", and an unset flag
+/// ultimately defaults to allowed, except `allow_unknown_sign`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AccessPolicy {
+    /// ADD_IDENTITY / ADD_ID_CONSTRAINED
+    pub allow_add: Option<bool>,
+    /// REMOVE_IDENTITY / REMOVE_ALL_IDENTITIES
+    pub allow_remove: Option<bool>,
+    /// LOCK / UNLOCK
+    pub allow_lock: Option<bool>,
+    /// EXTENSION
+    pub allow_extension: Option<bool>,
+    /// ADD_SMARTCARD_KEY / ADD_SMARTCARD_KEY_CONSTRAINED / REMOVE_SMARTCARD_KEY
+    pub allow_smartcard: Option<bool>,
+    /// SIGN_REQUEST for data that is not SSH userauth or SSHSIG (default: false)
+    pub allow_unknown_sign: Option<bool>,
+}
+
+impl AccessPolicy {
+    /// Fill unset flags from `fallback` (e.g., socket flags over upstream flags)
+    pub fn or(self, fallback: AccessPolicy) -> Self {
+        Self {
+            allow_add: self.allow_add.or(fallback.allow_add),
+            allow_remove: self.allow_remove.or(fallback.allow_remove),
+            allow_lock: self.allow_lock.or(fallback.allow_lock),
+            allow_extension: self.allow_extension.or(fallback.allow_extension),
+            allow_smartcard: self.allow_smartcard.or(fallback.allow_smartcard),
+            allow_unknown_sign: self.allow_unknown_sign.or(fallback.allow_unknown_sign),
+        }
+    }
+
+    /// Combine the flags of several upstreams; a denial by either wins
+    pub fn and(self, other: AccessPolicy) -> Self {
+        let both = |a: Option<bool>, b: Option<bool>| match (a, b) {
+            (Some(a), Some(b)) => Some(a && b),
+            (a, b) => a.or(b),
+        };
+        Self {
+            allow_add: both(self.allow_add, other.allow_add),
+            allow_remove: both(self.allow_remove, other.allow_remove),
+            allow_lock: both(self.allow_lock, other.allow_lock),
+            allow_extension: both(self.allow_extension, other.allow_extension),
+            allow_smartcard: both(self.allow_smartcard, other.allow_smartcard),
+            allow_unknown_sign: both(self.allow_unknown_sign, other.allow_unknown_sign),
+        }
+    }
+
+    /// Check whether no flag has been set
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Set a flag by its option name (`allow-add`, `allow_add`, ...)
+    ///
+    /// Returns `false` if the name is not a known flag.
+    pub fn set(&mut self, name: &str, value: bool) -> bool {
+        let flag = match name.replace('-', "_").as_str() {
+            "allow_add" => &mut self.allow_add,
+            "allow_remove" => &mut self.allow_remove,
+            "allow_lock" => &mut self.allow_lock,
+            "allow_extension" => &mut self.allow_extension,
+            "allow_smartcard" => &mut self.allow_smartcard,
+            "allow_unknown_sign" => &mut self.allow_unknown_sign,
+            _ => return false,
+        };
+        *flag = Some(value);
+        true
+    }
+
+    /// Get all explicitly set flags as (option name, value) pairs
+    pub fn flags(&self) -> Vec<(&'static str, bool)> {
+        [
+            ("allow-add", self.allow_add),
+            ("allow-remove", self.allow_remove),
+            ("allow-lock", self.allow_lock),
+            ("allow-extension", self.allow_extension),
+            ("allow-smartcard", self.allow_smartcard),
+            ("allow-unknown-sign", self.allow_unknown_sign),
+        ]
+        .into_iter()
+        .filter_map(|(name, value)| value.map(|v| (name, v)))
+        .collect()
+    }
+
+    /// Get the flag governing a message type, or `None` if it is not gated
+    pub fn flag_for(msg_type: MessageType) -> Option<&'static str> {
+        match msg_type {
+            MessageType::AddIdentity | MessageType::AddIdConstrained => Some("allow-add"),
+            MessageType::RemoveIdentity | MessageType::RemoveAllIdentities => Some("allow-remove"),
+            MessageType::Lock | MessageType::Unlock => Some("allow-lock"),
+            MessageType::Extension => Some("allow-extension"),
+            MessageType::AddSmartcardKey
+            | MessageType::AddSmartcardKeyConstrained
+            | MessageType::RemoveSmartcardKey => Some("allow-smartcard"),
+            _ => None,
+        }
+    }
+
+    /// Check whether any flag gating messages (all but `allow_unknown_sign`)
+    /// has been set
+    pub fn restricts_messages(&self) -> bool {
+        self.allow_add.is_some()
+            || self.allow_remove.is_some()
+            || self.allow_lock.is_some()
+            || self.allow_extension.is_some()
+            || self.allow_smartcard.is_some()
+    }
+
+    /// Check whether a message of the given type may be forwarded to upstream
+    ///
+    /// A message no flag covers is only forwarded while no message flag is
+    /// set, so that restricting a socket cannot be bypassed with other types.
+    pub fn permits(&self, msg_type: MessageType) -> bool {
+        let flag = match Self::flag_for(msg_type) {
+            Some("allow-add") => self.allow_add,
+            Some("allow-remove") => self.allow_remove,
+            Some("allow-lock") => self.allow_lock,
+            Some("allow-extension") => self.allow_extension,
+            Some("allow-smartcard") => self.allow_smartcard,
+            _ if matches!(
+                msg_type,
+                MessageType::RequestIdentities | MessageType::SignRequest
+            ) =>
+            {
+                return true;
+            }
+            _ => return !self.restricts_messages(),
+        };
+        flag.unwrap_or(true)
+    }
+
+    /// Check whether data of the given kind may be signed
+    ///
+    /// SSH logins and SSHSIG signatures are always permitted (subject to the
+    /// filter); arbitrary data only with `allow_unknown_sign`.
+    pub fn permits_sign(&self, payload: &SignPayload) -> bool {
+        !payload.is_unknown() || self.allow_unknown_sign.unwrap_or(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_permits_everything() {
+        let policy = AccessPolicy::default();
+        assert!(policy.is_empty());
+        assert!(policy.permits(MessageType::AddIdentity));
+        assert!(policy.permits(MessageType::RemoveAllIdentities));
+        assert!(policy.permits(MessageType::Lock));
+        assert!(policy.permits(MessageType::Extension));
+        assert!(policy.permits(MessageType::AddSmartcardKey));
+        assert!(policy.permits(MessageType::Unknown));
+    }
+
+    #[test]
+    fn test_unmapped_types_denied_once_restricted() {
+        // Even a flag that allows its messages restricts unmapped types
+        let policy = AccessPolicy {
+            allow_add: Some(true),
+            ..Default::default()
+        };
+        assert!(policy.permits(MessageType::AddIdentity));
+        assert!(!policy.permits(MessageType::Unknown));
+        assert!(!policy.permits(MessageType::Success));
+        assert!(policy.permits(MessageType::RequestIdentities));
+        assert!(policy.permits(MessageType::SignRequest));
+
+        // allow_unknown_sign is about sign data, not message types
+        let policy = AccessPolicy {
+            allow_unknown_sign: Some(true),
+            ..Default::default()
+        };
+        assert!(policy.permits(MessageType::Unknown));
+    }
+
+    #[test]
+    fn test_denied_flags() {
+        let policy = AccessPolicy {
+            allow_remove: Some(false),
+            allow_lock: Some(false),
+            ..Default::default()
+        };
+        assert!(!policy.permits(MessageType::RemoveIdentity));
+        assert!(!policy.permits(MessageType::RemoveAllIdentities));
+        assert!(!policy.permits(MessageType::Lock));
+        assert!(!policy.permits(MessageType::Unlock));
+        assert!(policy.permits(MessageType::AddIdentity));
+        // Filtered messages are never gated by feature flags
+        assert!(policy.permits(MessageType::RequestIdentities));
+        assert!(policy.permits(MessageType::SignRequest));
+    }
+
+    #[test]
+    fn test_unknown_sign_denied_by_default() {
+        let policy = AccessPolicy::default();
+        assert!(!policy.permits_sign(&SignPayload::Unknown));
+
+        let policy = AccessPolicy {
+            allow_unknown_sign: Some(true),
+            ..Default::default()
+        };
+        assert!(policy.permits_sign(&SignPayload::Unknown));
+    }
+
+    #[test]
+    fn test_or_prefers_self() {
+        let socket = AccessPolicy {
+            allow_add: Some(true),
+            ..Default::default()
+        };
+        let upstream = AccessPolicy {
+            allow_add: Some(false),
+            allow_remove: Some(false),
+            ..Default::default()
+        };
+        let merged = socket.or(upstream);
+        assert_eq!(merged.allow_add, Some(true));
+        assert_eq!(merged.allow_remove, Some(false));
+        assert_eq!(merged.allow_lock, None);
+    }
+
+    #[test]
+    fn test_socket_mode_parse() {
+        assert_eq!("full".parse::<SocketMode>().unwrap(), SocketMode::Full);
+        assert_eq!(
+            "list-only".parse::<SocketMode>().unwrap(),
+            SocketMode::ListOnly
+        );
+        assert_eq!(
+            "sign-only".parse::<SocketMode>().unwrap(),
+            SocketMode::SignOnly
+        );
+        assert!("readonly".parse::<SocketMode>().is_err());
+
+        assert!(SocketMode::ListOnly.allows_listing());
+        assert!(!SocketMode::ListOnly.allows_signing());
+        assert!(!SocketMode::SignOnly.allows_listing());
+        assert!(SocketMode::SignOnly.allows_signing());
+    }
+
+    #[test]
+    fn test_set_by_name() {
+        let mut policy = AccessPolicy::default();
+        assert!(policy.set("allow-add", false));
+        assert!(policy.set("allow_extension", false));
+        assert!(!policy.set("allow-sign", false));
+        assert_eq!(
+            policy.flags(),
+            vec![("allow-add", false), ("allow-extension", false)]
+        );
+    }
+}
@@ -0,0 +1,190 @@
+//! Confirmation of sign requests by the user
+//!
+//! With `confirm`, every sign request that passes the filter is shown to the
+//! user before it is forwarded, like a key added with `ssh-add -c`. This also
+//! works in front of agents that cannot confirm by themselves (1Password,
+//! gpg-agent, ...). An askpass program (`$SSH_ASKPASS`, or `confirm_program`)
+//! is run with a prompt naming the key, the client process and the
+//! destination; the request is signed only if it exits with status 0.
+
+use crate::error::{Error, Result};
+use crate::utils::path::split_command;
+use std::io;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::process::Command;
+
+/// Askpass program used when `SSH_ASKPASS` is not set
+const DEFAULT_ASKPASS: &str = "ssh-askpass";
+
+/// How long the user has to answer before the request is denied
+const CONFIRM_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Details of a sign request shown to the user
+#[derive(Debug, Clone, Default)]
+pub struct ConfirmRequest {
+    /// Socket the request was made on
+    pub socket: String,
+    /// Key fingerprint (SHA256:...)
+    pub fingerprint: String,
+    /// Key comment
+    pub comment: String,
+    /// Connected process, if known (`exe[pid]`)
+    pub client: Option<String>,
+    /// Server the key logs in to, if known
+    pub destination: Option<String>,
+}
+
+impl ConfirmRequest {
+    /// Build the prompt passed as the last argument of the program
+    pub fn prompt(&self) -> String {
+        let mut prompt = format!(
+            "Allow use of key {}?\nKey fingerprint {}.",
+            self.comment, self.fingerprint
+        );
+        if let Some(client) = &self.client {
+            prompt.push_str(&format!("\nClient: {}", client));
+        }
+        if let Some(destination) = &self.destination {
+            prompt.push_str(&format!("\nDestination: {}", destination));
+        }
+        prompt
+    }
+
+    /// Environment variables describing the request to the program
+    fn env(&self) -> Vec<(&'static str, &str)> {
+        vec![
+            ("AUTHSOCK_FILTER_SOCKET", self.socket.as_str()),
+            ("AUTHSOCK_FILTER_FINGERPRINT", self.fingerprint.as_str()),
+            ("AUTHSOCK_FILTER_COMMENT", self.comment.as_str()),
+            (
+                "AUTHSOCK_FILTER_CLIENT",
+                self.client.as_deref().unwrap_or_default(),
+            ),
+            (
+                "AUTHSOCK_FILTER_DESTINATION",
+                self.destination.as_deref().unwrap_or_default(),
+            ),
+        ]
+    }
+}
+
+/// Program asking the user to confirm sign requests
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Confirm {
+    /// Command and arguments (None: `$SSH_ASKPASS`, or ssh-askpass)
+    program: Option<Vec<String>>,
+}
+
+impl Confirm {
+    /// Confirm with `$SSH_ASKPASS` (looked up for each request)
+    pub fn askpass() -> Self {
+        Self::default()
+    }
+
+    /// Confirm with a command (split like a shell would, without a shell)
+    pub fn with_program(command: &str) -> Result<Self> {
+        Ok(Self {
+            program: Some(split_command(command)?),
+        })
+    }
+
+    /// Get the command to run
+    fn command(&self) -> Vec<String> {
+        match &self.program {
+            Some(program) => program.clone(),
+            None => vec![
+                std::env::var("SSH_ASKPASS")
+                    .ok()
+                    .filter(|p| !p.is_empty())
+                    .unwrap_or_else(|| DEFAULT_ASKPASS.to_string()),
+            ],
+        }
+    }
+
+    /// Ask the user to confirm a sign request
+    ///
+    /// Returns `Ok(false)` if the program exits with a non-zero status, and
+    /// an error if it cannot be run or does not answer in time.
+    pub async fn ask(&self, request: &ConfirmRequest) -> Result<bool> {
+        let command = self.command();
+        let mut child = Command::new(&command[0])
+            .args(&command[1..])
+            .arg(request.prompt())
+            // Ask OpenSSH's askpass for yes/no instead of a passphrase
+            .env("SSH_ASKPASS_PROMPT", "confirm")
+            .envs(request.env())
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::inherit())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| {
+                Error::Io(io::Error::new(
+                    e.kind(),
+                    format!("Failed to start confirm program '{}': {}", command[0], e),
+                ))
+            })?;
+        match tokio::time::timeout(CONFIRM_TIMEOUT, child.wait()).await {
+            Ok(status) => Ok(status?.success()),
+            Err(_) => Err(Error::Io(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("Confirm program '{}' did not answer in time", command[0]),
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request() -> ConfirmRequest {
+        ConfirmRequest {
+            socket: "/tmp/prod.sock".to_string(),
+            fingerprint: "SHA256:abc".to_string(),
+            comment: "user@prod".to_string(),
+            client: Some("/usr/bin/ssh[42]".to_string()),
+            destination: None,
+        }
+    }
+
+    #[test]
+    fn test_prompt() {
+        assert_eq!(
+            request().prompt(),
+            "Allow use of key user@prod?\nKey fingerprint SHA256:abc.\nClient: /usr/bin/ssh[42]"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_exit_status_decides() {
+        let confirm = Confirm::with_program("true").unwrap();
+        assert!(confirm.ask(&request()).await.unwrap());
+        let confirm = Confirm::with_program("false").unwrap();
+        assert!(!confirm.ask(&request()).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_program_sees_request() {
+        // The prompt is the last argument
+        let dir = tempfile::TempDir::new().unwrap();
+        let script = dir.path().join("askpass");
+        std::fs::write(
+            &script,
+            "test \"$AUTHSOCK_FILTER_COMMENT\" = user@prod \\\n\
+             && test \"$SSH_ASKPASS_PROMPT\" = confirm \\\n\
+             && test -z \"$AUTHSOCK_FILTER_DESTINATION\" \\\n\
+             && case \"$1\" in *SHA256:abc*) true ;; *) false ;; esac\n",
+        )
+        .unwrap();
+        let confirm = Confirm::with_program(&format!("sh {}", script.display())).unwrap();
+        assert!(confirm.ask(&request()).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_missing_program_is_an_error() {
+        let confirm = Confirm::with_program("/nonexistent/askpass").unwrap();
+        assert!(confirm.ask(&request()).await.is_err());
+    }
+}
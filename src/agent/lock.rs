@@ -0,0 +1,315 @@
+//! Proxy-local locking of a socket
+//!
+//! With `local_lock`, LOCK and UNLOCK (`ssh-add -x` / `ssh-add -X`) lock only
+//! the socket they are sent to; the upstream agent and other sockets stay
+//! usable. While locked, the socket lists no keys and refuses every other
+//! request. The passphrase is only kept as a salted PBKDF2-HMAC-SHA256 hash,
+//! which UNLOCK compares in constant time. Like ssh-agent, each failed UNLOCK
+//! is answered a little later than the previous one, and attempts on a socket
+//! are checked one at a time.
+//!
+//! With `idle_lock`, a socket also locks itself when nothing was signed
+//! through it for a while. The operator resumes every idle-locked socket at
+//! once (SIGUSR1 to the run or stdio command). A single socket is resumed by
+//! UNLOCK with its `idle_unlock_file` passphrase, hashed like a LOCK
+//! passphrase; without one, UNLOCK cannot lift an idle lock.
+
+use sha2::Sha256;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use subtle::ConstantTimeEq;
+
+/// Length of the random salt
+const SALT_LEN: usize = 16;
+
+/// PBKDF2 iterations for the passphrase hash
+const KDF_ROUNDS: u32 = 10_000;
+
+/// Delay added to the answer of each further failed UNLOCK
+const FAILURE_DELAY: Duration = Duration::from_millis(100);
+
+/// Failed UNLOCKs after which the delay stops growing (10 seconds)
+const MAX_FAILURES: u32 = 100;
+
+/// Salted hash of a lock passphrase
+#[derive(Clone)]
+struct PassphraseHash {
+    salt: [u8; SALT_LEN],
+    hash: [u8; 32],
+}
+
+impl PassphraseHash {
+    /// Hash a passphrase with a fresh random salt
+    fn new(passphrase: &[u8]) -> Option<Self> {
+        let mut salt = [0u8; SALT_LEN];
+        getrandom::getrandom(&mut salt).ok()?;
+        Some(Self {
+            salt,
+            hash: derive(passphrase, &salt),
+        })
+    }
+
+    /// Check a passphrase against the hash in constant time
+    fn verify(&self, passphrase: &[u8]) -> bool {
+        derive(passphrase, &self.salt).ct_eq(&self.hash).into()
+    }
+}
+
+/// Derive a hash from a passphrase (PBKDF2-HMAC-SHA256)
+fn derive(passphrase: &[u8], salt: &[u8]) -> [u8; 32] {
+    pbkdf2::pbkdf2_hmac_array::<Sha256, 32>(passphrase, salt, KDF_ROUNDS)
+}
+
+/// Why a socket is locked
+#[derive(Clone)]
+enum LockState {
+    /// Locked by LOCK; UNLOCK needs the same passphrase
+    Passphrase(PassphraseHash),
+    /// Locked after no successful sign for the idle timeout; lifted by a
+    /// resume or UNLOCK with the idle passphrase
+    Idle,
+}
+
+/// Lock state and last use of a socket
+struct LockInner {
+    locked: Option<LockState>,
+    last_used: Instant,
+}
+
+/// Lock state of a socket
+pub struct SocketLock {
+    inner: Mutex<LockInner>,
+    /// Passphrase lifting an idle lock, if any
+    idle_passphrase: Option<PassphraseHash>,
+    /// Failed UNLOCKs since the last successful one; held while an UNLOCK
+    /// is checked
+    failures: tokio::sync::Mutex<u32>,
+}
+
+impl Default for SocketLock {
+    fn default() -> Self {
+        Self {
+            inner: Mutex::new(LockInner {
+                locked: None,
+                last_used: Instant::now(),
+            }),
+            idle_passphrase: None,
+            failures: tokio::sync::Mutex::new(0),
+        }
+    }
+}
+
+impl SocketLock {
+    /// Create a lock whose idle lock UNLOCK lifts with `passphrase`
+    ///
+    /// Returns `None` if no salt could be generated.
+    pub fn with_idle_passphrase(passphrase: &[u8]) -> Option<Self> {
+        Some(Self {
+            idle_passphrase: Some(PassphraseHash::new(passphrase)?),
+            ..Self::default()
+        })
+    }
+
+    /// Check if UNLOCK can lift an idle lock
+    pub fn has_idle_passphrase(&self) -> bool {
+        self.idle_passphrase.is_some()
+    }
+
+    /// Check if the socket is locked
+    pub fn is_locked(&self) -> bool {
+        self.inner.lock().unwrap().locked.is_some()
+    }
+
+    /// Check if the socket is locked because it was idle
+    pub fn is_idle_locked(&self) -> bool {
+        matches!(self.inner.lock().unwrap().locked, Some(LockState::Idle))
+    }
+
+    /// Lock the socket with a passphrase
+    ///
+    /// Returns `false` if it is already locked.
+    pub async fn lock(&self, passphrase: &[u8]) -> bool {
+        if self.is_locked() {
+            return false;
+        }
+        // Hashing takes a few milliseconds, keep it off the runtime
+        let passphrase = passphrase.to_vec();
+        let Ok(Some(hash)) =
+            tokio::task::spawn_blocking(move || PassphraseHash::new(&passphrase)).await
+        else {
+            return false;
+        };
+        let mut inner = self.inner.lock().unwrap();
+        if inner.locked.is_some() {
+            return false;
+        }
+        inner.locked = Some(LockState::Passphrase(hash));
+        true
+    }
+
+    /// Unlock the socket
+    ///
+    /// An idle lock takes the idle passphrase. Returns `false` if it is not
+    /// locked, is locked because it was idle and has no idle passphrase, or
+    /// the passphrase is wrong. A wrong passphrase is only reported after a
+    /// delay that grows with each failure.
+    pub async fn unlock(&self, passphrase: &[u8]) -> bool {
+        let mut failures = self.failures.lock().await;
+        let locked = self.inner.lock().unwrap().locked.clone();
+        let hash = match locked {
+            Some(LockState::Passphrase(hash)) => hash,
+            Some(LockState::Idle) => match &self.idle_passphrase {
+                Some(hash) => hash.clone(),
+                None => return false,
+            },
+            None => return false,
+        };
+        // Hash outside the mutex and off the runtime
+        let salt = hash.salt;
+        let passphrase = passphrase.to_vec();
+        let verified = tokio::task::spawn_blocking(move || hash.verify(&passphrase))
+            .await
+            .unwrap_or(false);
+        if !verified {
+            *failures = (*failures + 1).min(MAX_FAILURES);
+            tokio::time::sleep(FAILURE_DELAY * *failures).await;
+            return false;
+        }
+        *failures = 0;
+        let mut inner = self.inner.lock().unwrap();
+        // The lock may have changed while the passphrase was checked
+        let lifted = match &inner.locked {
+            Some(LockState::Passphrase(current)) => current.salt == salt,
+            Some(LockState::Idle) => self
+                .idle_passphrase
+                .as_ref()
+                .is_some_and(|idle| idle.salt == salt),
+            None => false,
+        };
+        if lifted {
+            inner.locked = None;
+            inner.last_used = Instant::now();
+        }
+        lifted
+    }
+
+    /// Record a successful use (a signature) of the socket
+    pub fn touch(&self) {
+        self.inner.lock().unwrap().last_used = Instant::now();
+    }
+
+    /// Lock the socket if it has not been used for `timeout`
+    ///
+    /// Returns `true` if the socket was locked by this call.
+    pub fn lock_if_idle(&self, timeout: Duration) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.locked.is_some() || inner.last_used.elapsed() < timeout {
+            return false;
+        }
+        inner.locked = Some(LockState::Idle);
+        true
+    }
+
+    /// Lift a lock caused by idleness (a passphrase lock is kept)
+    ///
+    /// Returns `true` if the socket was idle-locked.
+    pub fn resume_idle(&self) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        if !matches!(inner.locked, Some(LockState::Idle)) {
+            return false;
+        }
+        inner.locked = None;
+        inner.last_used = Instant::now();
+        true
+    }
+}
+
+impl std::fmt::Debug for SocketLock {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SocketLock")
+            .field("locked", &self.is_locked())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_lock_and_unlock() {
+        let lock = SocketLock::default();
+        assert!(!lock.is_locked());
+        assert!(
+            !lock.unlock(b"secret").await,
+            "unlocking an unlocked socket fails"
+        );
+
+        assert!(lock.lock(b"secret").await);
+        assert!(lock.is_locked());
+        assert!(!lock.lock(b"other").await, "locking twice fails");
+
+        assert!(!lock.unlock(b"wrong").await);
+        assert!(!lock.unlock(b"").await);
+        assert!(lock.is_locked());
+        assert_eq!(*lock.failures.lock().await, 2);
+
+        assert!(lock.unlock(b"secret").await);
+        assert!(!lock.is_locked());
+        assert_eq!(*lock.failures.lock().await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_idle_lock() {
+        let lock = SocketLock::default();
+        assert!(!lock.lock_if_idle(Duration::from_secs(60)));
+        assert!(!lock.resume_idle());
+
+        assert!(lock.lock_if_idle(Duration::ZERO));
+        assert!(lock.is_locked());
+        assert!(!lock.lock_if_idle(Duration::ZERO), "already locked");
+        assert!(lock.is_idle_locked());
+        // Clients cannot lift an idle lock, whatever the passphrase
+        assert!(!lock.unlock(b"").await);
+        assert!(!lock.unlock(b"anything").await);
+        assert!(lock.is_locked());
+
+        assert!(lock.resume_idle());
+        assert!(!lock.is_locked());
+
+        // Resuming does not lift a passphrase lock
+        assert!(lock.lock(b"secret").await);
+        assert!(!lock.is_idle_locked());
+        assert!(!lock.resume_idle());
+        assert!(!lock.lock_if_idle(Duration::ZERO));
+        assert!(!lock.unlock(b"anything").await);
+        assert!(lock.unlock(b"secret").await);
+    }
+
+    #[tokio::test]
+    async fn test_idle_lock_with_passphrase() {
+        let lock = SocketLock::with_idle_passphrase(b"resume").unwrap();
+        assert!(lock.has_idle_passphrase());
+        assert!(!lock.unlock(b"resume").await, "not locked yet");
+
+        assert!(lock.lock_if_idle(Duration::ZERO));
+        assert!(!lock.unlock(b"wrong").await);
+        assert!(lock.is_idle_locked());
+        assert!(lock.unlock(b"resume").await);
+        assert!(!lock.is_locked());
+
+        // A passphrase lock still needs its own passphrase
+        assert!(lock.lock(b"secret").await);
+        assert!(!lock.unlock(b"resume").await);
+        assert!(lock.unlock(b"secret").await);
+    }
+
+    #[test]
+    fn test_passphrase_hash_is_salted() {
+        let a = PassphraseHash::new(b"secret").unwrap();
+        let b = PassphraseHash::new(b"secret").unwrap();
+        assert_ne!(a.salt, b.salt);
+        assert_ne!(a.hash, b.hash);
+        assert!(a.verify(b"secret") && b.verify(b"secret"));
+    }
+}
@@ -2,13 +2,32 @@
 //!
 //! This module provides components for creating a filtered SSH agent proxy:
 //! - `Upstream`: Connection to the upstream SSH agent
+//! - `UpstreamTarget`, `Preset`: Location of an upstream agent socket (fixed,
+//!   discovered, printed by a command, or a well-known agent) or process
+//!   relaying to it over stdio
 //! - `Server`: Unix socket server for accepting client connections
 //! - `Proxy`: Core proxy logic that filters requests between client and upstream
+//! - `AccessPolicy`, `SocketMode`: Per-socket restrictions on agent messages
+//! - `PeerPolicy`: Per-socket restrictions on the users allowed to connect
+//! - `Confirm`: Per-socket confirmation of sign requests by the user
 
+mod access;
+mod confirm;
+mod lock;
+mod peer;
+mod preset;
 mod proxy;
 mod server;
+mod session;
+mod stream;
+mod target;
 mod upstream;
 
+pub use access::{AccessPolicy, SocketMode};
+pub use confirm::{Confirm, ConfirmRequest};
+pub use peer::{PeerCredentials, PeerPolicy};
+pub use preset::{PRESET_PREFIX, Preset};
 pub use proxy::Proxy;
 pub use server::Server;
-pub use upstream::Upstream;
+pub use target::{COMMAND_PREFIX, DISCOVER_PREFIX, EXEC_PREFIX, UpstreamTarget};
+pub use upstream::{FailoverCondition, PoolConfig, PoolStats, Upstream};
@@ -0,0 +1,219 @@
+//! Peer credential checks for client connections
+//!
+//! The uid, gid, pid and (on Linux) supplementary groups of a connecting
+//! client are read from the socket (SO_PEERCRED and SO_PEERGROUPS on Linux,
+//! getpeereid on macOS) when it connects. By default only the proxy's
+//! own user and root are served; other users must be listed in the socket's
+//! `allowed_uids` or belong to one of its `allowed_groups`.
+
+use std::fmt;
+use std::io;
+use std::path::Path;
+use tokio::net::UnixStream;
+use tracing::warn;
+
+/// Credentials of a connected client
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PeerCredentials {
+    /// Effective user ID
+    pub uid: u32,
+    /// Effective group ID
+    pub gid: u32,
+    /// Process ID (not available on every platform)
+    pub pid: Option<i32>,
+    /// Supplementary groups (Linux only; empty elsewhere)
+    pub supplementary_gids: Vec<u32>,
+}
+
+impl PeerCredentials {
+    /// Read the credentials of the process at the other end of a stream
+    ///
+    /// Like the uid and gid, the supplementary groups are those the client
+    /// had when it connected.
+    pub fn from_stream(stream: &UnixStream) -> io::Result<Self> {
+        let cred = stream.peer_cred()?;
+        Ok(Self {
+            uid: cred.uid(),
+            gid: cred.gid(),
+            pid: cred.pid(),
+            supplementary_gids: supplementary_groups(stream),
+        })
+    }
+
+    /// Groups of the client: its effective gid and supplementary groups
+    pub fn groups(&self) -> Vec<u32> {
+        let mut groups = vec![self.gid];
+        groups.extend(&self.supplementary_gids);
+        groups
+    }
+}
+
+impl fmt::Display for PeerCredentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "uid={} gid={}", self.uid, self.gid)?;
+        if let Some(pid) = self.pid {
+            write!(f, " pid={}", pid)?;
+        }
+        Ok(())
+    }
+}
+
+/// Read the supplementary groups of the peer with SO_PEERGROUPS
+///
+/// Kernels without SO_PEERGROUPS (before 4.13) report no supplementary
+/// groups, so only the effective gid can match `allowed_groups`.
+#[cfg(target_os = "linux")]
+fn supplementary_groups(stream: &UnixStream) -> Vec<u32> {
+    use std::os::fd::AsRawFd;
+
+    const GID_SIZE: usize = std::mem::size_of::<libc::gid_t>();
+    let mut groups: Vec<libc::gid_t> = vec![0; 64];
+    loop {
+        let mut len = (groups.len() * GID_SIZE) as libc::socklen_t;
+        // SAFETY: groups is valid for len bytes for the duration of the call
+        let rc = unsafe {
+            libc::getsockopt(
+                stream.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_PEERGROUPS,
+                groups.as_mut_ptr().cast(),
+                &mut len,
+            )
+        };
+        let count = len as usize / GID_SIZE;
+        if rc == 0 {
+            groups.truncate(count);
+            return groups;
+        }
+        // ERANGE: the buffer is too small and len holds the size needed
+        if io::Error::last_os_error().raw_os_error() == Some(libc::ERANGE) && count > groups.len() {
+            groups.resize(count, 0);
+            continue;
+        }
+        return Vec::new();
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn supplementary_groups(_stream: &UnixStream) -> Vec<u32> {
+    Vec::new()
+}
+
+/// Users allowed to connect to a socket besides the proxy's user and root
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PeerPolicy {
+    /// Additional user IDs
+    pub allowed_uids: Vec<u32>,
+    /// Group IDs whose members are allowed
+    pub allowed_gids: Vec<u32>,
+}
+
+impl PeerPolicy {
+    /// Check if a client may use the socket
+    pub fn allows(&self, peer: &PeerCredentials) -> bool {
+        // SAFETY: geteuid has no preconditions and cannot fail
+        let own_uid = unsafe { libc::geteuid() };
+        if peer.uid == own_uid || peer.uid == 0 || self.allowed_uids.contains(&peer.uid) {
+            return true;
+        }
+        !self.allowed_gids.is_empty()
+            && peer
+                .groups()
+                .iter()
+                .any(|gid| self.allowed_gids.contains(gid))
+    }
+
+    /// Check the client of a newly accepted connection to `socket`
+    ///
+    /// Returns the client's credentials if it may use the socket. Clients
+    /// whose credentials cannot be read are rejected; rejections are logged.
+    pub fn admit(&self, stream: &UnixStream, socket: &Path) -> Option<PeerCredentials> {
+        let peer = match PeerCredentials::from_stream(stream) {
+            Ok(peer) if self.allows(&peer) => return Some(peer),
+            peer => peer.ok(),
+        };
+        warn!(
+            socket = %socket.display(),
+            uid = ?peer.as_ref().map(|p| p.uid),
+            gid = ?peer.as_ref().map(|p| p.gid),
+            pid = ?peer.as_ref().and_then(|p| p.pid),
+            "Rejected client of unexpected user"
+        );
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn own_uid() -> u32 {
+        unsafe { libc::geteuid() }
+    }
+
+    #[test]
+    fn test_default_policy_allows_own_user_and_root() {
+        let policy = PeerPolicy::default();
+        let peer = |uid| PeerCredentials {
+            uid,
+            gid: 12345,
+            ..Default::default()
+        };
+        assert!(policy.allows(&peer(own_uid())));
+        assert!(policy.allows(&peer(0)));
+        assert!(!policy.allows(&peer(own_uid() + 1000)));
+    }
+
+    #[test]
+    fn test_allowed_uids_and_groups() {
+        let other = PeerCredentials {
+            uid: own_uid() + 1000,
+            gid: 4242,
+            ..Default::default()
+        };
+        let policy = PeerPolicy {
+            allowed_uids: vec![other.uid],
+            ..Default::default()
+        };
+        assert!(policy.allows(&other));
+
+        let policy = PeerPolicy {
+            allowed_gids: vec![4242],
+            ..Default::default()
+        };
+        assert!(policy.allows(&other));
+
+        let policy = PeerPolicy {
+            allowed_gids: vec![4243],
+            ..Default::default()
+        };
+        assert!(!policy.allows(&other));
+
+        let other = PeerCredentials {
+            supplementary_gids: vec![4243],
+            ..other
+        };
+        assert!(policy.allows(&other));
+    }
+
+    #[tokio::test]
+    async fn test_peer_credentials_of_own_process() {
+        let (a, _b) = UnixStream::pair().unwrap();
+        let peer = PeerCredentials::from_stream(&a).unwrap();
+        assert_eq!(peer.uid, own_uid());
+        #[cfg(target_os = "linux")]
+        {
+            assert_eq!(peer.pid, Some(std::process::id() as i32));
+            // SAFETY: a zero size only asks for the number of groups
+            let count = unsafe { libc::getgroups(0, std::ptr::null_mut()) };
+            let mut own_groups = vec![0; count as usize];
+            // SAFETY: own_groups has room for count groups
+            unsafe { libc::getgroups(count, own_groups.as_mut_ptr()) };
+            own_groups.sort_unstable();
+            let mut groups = peer.supplementary_gids.clone();
+            groups.sort_unstable();
+            assert_eq!(groups, own_groups);
+        }
+        assert!(PeerPolicy::default().allows(&peer));
+    }
+}
@@ -0,0 +1,235 @@
+//! Presets for well-known SSH agents
+//!
+//! A preset (`preset:1password`) stands for the documented socket
+//! locations of an agent on the current platform, and for the requests the
+//! agent is known to refuse.
+
+use super::target::{COMMAND_PREFIX, DISCOVER_PREFIX, UpstreamTarget};
+use crate::error::{Error, Result};
+use crate::protocol::MessageType;
+use crate::utils::path::expand_path;
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// Prefix of preset upstreams
+pub const PRESET_PREFIX: &str = "preset:";
+
+/// Well-known SSH agent
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Preset {
+    /// 1Password SSH agent
+    OnePassword,
+    /// GnuPG agent with `enable-ssh-support`
+    GpgAgent,
+    /// KeePassXC (adds keys to the system agent while a database is unlocked)
+    KeePassXc,
+    /// GNOME Keyring / gcr SSH agent
+    GnomeKeyring,
+    /// OpenSSH ssh-agent (launchd on macOS, systemd user unit or `ssh-agent` on Linux)
+    OpenSsh,
+}
+
+/// Requests managed in the 1Password app rather than through the agent
+const ONEPASSWORD_REFUSES: &[MessageType] = &[
+    MessageType::AddIdentity,
+    MessageType::AddIdConstrained,
+    MessageType::RemoveIdentity,
+    MessageType::RemoveAllIdentities,
+    MessageType::AddSmartcardKey,
+    MessageType::AddSmartcardKeyConstrained,
+    MessageType::RemoveSmartcardKey,
+    MessageType::Lock,
+    MessageType::Unlock,
+];
+
+/// Requests gpg-agent does not implement for SSH
+const GPG_AGENT_REFUSES: &[MessageType] = &[
+    MessageType::RemoveAllIdentities,
+    MessageType::Lock,
+    MessageType::Unlock,
+];
+
+/// Sockets of agents KeePassXC adds its keys to (OpenSSH, GNOME)
+const SYSTEM_AGENT_SOCKETS: &[&str] = &[
+    "$XDG_RUNTIME_DIR/ssh-agent.socket",
+    "$XDG_RUNTIME_DIR/gcr/ssh",
+    "$XDG_RUNTIME_DIR/keyring/ssh",
+    "discover:/tmp/ssh-*/agent.*",
+];
+
+impl Preset {
+    /// All presets
+    pub const ALL: [Preset; 5] = [
+        Preset::OnePassword,
+        Preset::GpgAgent,
+        Preset::KeePassXc,
+        Preset::GnomeKeyring,
+        Preset::OpenSsh,
+    ];
+
+    /// Get the preset name
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Preset::OnePassword => "1password",
+            Preset::GpgAgent => "gpg-agent",
+            Preset::KeePassXc => "keepassxc",
+            Preset::GnomeKeyring => "gnome-keyring",
+            Preset::OpenSsh => "openssh",
+        }
+    }
+
+    /// Get a short description (for completion)
+    pub fn description(&self) -> &'static str {
+        match self {
+            Preset::OnePassword => "1Password SSH agent (keys are managed in the app)",
+            Preset::GpgAgent => "GnuPG agent (socket from gpgconf)",
+            Preset::KeePassXc => "Agent KeePassXC adds keys to (empty while locked)",
+            Preset::GnomeKeyring => "GNOME Keyring / gcr SSH agent (Linux)",
+            Preset::OpenSsh => "OpenSSH ssh-agent (launchd, systemd or /tmp/ssh-*)",
+        }
+    }
+
+    /// Documented socket locations on this platform, most specific first
+    ///
+    /// `$SSH_AUTH_SOCK` is never used, as it may point to the proxy itself.
+    pub fn candidates(&self) -> &'static [&'static str] {
+        if cfg!(target_os = "macos") {
+            match self {
+                Preset::OnePassword => &[
+                    "~/Library/Group Containers/2BUA8C4S2C.com.1password/t/agent.sock",
+                    "~/.1password/agent.sock",
+                ],
+                Preset::GpgAgent => &[
+                    "command:gpgconf --list-dirs agent-ssh-socket",
+                    "~/.gnupg/S.gpg-agent.ssh",
+                ],
+                Preset::KeePassXc | Preset::OpenSsh => {
+                    &["discover:/private/tmp/com.apple.launchd.*/Listeners"]
+                }
+                Preset::GnomeKeyring => &[],
+            }
+        } else {
+            match self {
+                Preset::OnePassword => &["~/.1password/agent.sock"],
+                Preset::GpgAgent => &[
+                    "command:gpgconf --list-dirs agent-ssh-socket",
+                    "$XDG_RUNTIME_DIR/gnupg/S.gpg-agent.ssh",
+                    "~/.gnupg/S.gpg-agent.ssh",
+                ],
+                Preset::KeePassXc => SYSTEM_AGENT_SOCKETS,
+                Preset::GnomeKeyring => {
+                    &["$XDG_RUNTIME_DIR/gcr/ssh", "$XDG_RUNTIME_DIR/keyring/ssh"]
+                }
+                Preset::OpenSsh => &[
+                    "$XDG_RUNTIME_DIR/ssh-agent.socket",
+                    "discover:/tmp/ssh-*/agent.*",
+                ],
+            }
+        }
+    }
+
+    /// Requests the agent is known to refuse
+    ///
+    /// These are answered with FAILURE by the proxy instead of being
+    /// forwarded, so the reason shows up in the logs.
+    pub fn refuses(&self, msg_type: MessageType) -> bool {
+        let refused = match self {
+            Preset::OnePassword => ONEPASSWORD_REFUSES,
+            Preset::GpgAgent => GPG_AGENT_REFUSES,
+            Preset::KeePassXc | Preset::GnomeKeyring | Preset::OpenSsh => &[],
+        };
+        refused.contains(&msg_type)
+    }
+
+    /// Find the socket of the agent (the first candidate that exists)
+    pub fn resolve(&self) -> Result<PathBuf> {
+        if self.candidates().is_empty() {
+            return Err(Error::UpstreamNotAvailable(format!(
+                "{} is not available on this platform",
+                self
+            )));
+        }
+        for candidate in self.candidates() {
+            let target = if is_dynamic(candidate) {
+                UpstreamTarget::parse(candidate)?
+            } else {
+                // Skip locations whose variables are not set
+                match expand_path(candidate) {
+                    Ok(path) => UpstreamTarget::Path(PathBuf::from(path)),
+                    Err(_) => continue,
+                }
+            };
+            if let Ok(path) = target.resolve()
+                && path.exists()
+            {
+                return Ok(path);
+            }
+        }
+        Err(Error::UpstreamNotAvailable(format!(
+            "No {} socket found (tried {})",
+            self,
+            self.candidates().join(", ")
+        )))
+    }
+}
+
+fn is_dynamic(candidate: &str) -> bool {
+    candidate.starts_with(DISCOVER_PREFIX) || candidate.starts_with(COMMAND_PREFIX)
+}
+
+impl fmt::Display for Preset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Preset {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Preset::ALL
+            .into_iter()
+            .find(|p| p.as_str() == s)
+            .ok_or_else(|| {
+                let names: Vec<_> = Preset::ALL.iter().map(Preset::as_str).collect();
+                Error::Config(format!(
+                    "Unknown upstream preset '{}' (expected one of: {})",
+                    s,
+                    names.join(", ")
+                ))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_preset() {
+        for preset in Preset::ALL {
+            assert_eq!(preset.as_str().parse::<Preset>().unwrap(), preset);
+        }
+        assert!("putty".parse::<Preset>().is_err());
+    }
+
+    #[test]
+    fn test_onepassword_refuses_adding_keys() {
+        assert!(Preset::OnePassword.refuses(MessageType::AddIdentity));
+        assert!(Preset::OnePassword.refuses(MessageType::Lock));
+        assert!(!Preset::OnePassword.refuses(MessageType::SignRequest));
+        assert!(!Preset::OpenSsh.refuses(MessageType::AddIdentity));
+    }
+
+    #[test]
+    fn test_candidates_are_valid_targets() {
+        for preset in Preset::ALL {
+            for candidate in preset.candidates() {
+                if is_dynamic(candidate) {
+                    assert!(UpstreamTarget::parse(candidate).is_ok(), "{}", candidate);
+                }
+            }
+        }
+    }
+}
@@ -3,32 +3,84 @@
 //! This module implements the core proxy functionality that filters
 //! SSH agent requests between a client and the upstream agent.
 
-use crate::error::Result;
-use crate::filter::FilterEvaluator;
+use crate::error::{Error, Result};
+use crate::filter::{ClientProcess, FilterContext, FilterEvaluator, KnownHost, SignContext};
 use crate::protocol::{AgentCodec, AgentMessage, Identity, MessageType};
 use bytes::Bytes;
-use std::collections::HashSet;
-use std::sync::Arc;
+use std::path::PathBuf;
 use std::sync::atomic::{AtomicU64, Ordering};
-use tokio::net::UnixStream;
-use tokio::sync::RwLock;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite};
 use tracing::{debug, info, trace, warn};
 
-use super::Upstream;
+use super::lock::SocketLock;
+use super::session::{ClientSession, HostBinding};
+use super::{AccessPolicy, Confirm, ConfirmRequest, PeerCredentials, SocketMode, Upstream};
+
+/// How long upstream identities are reused to look up unlisted keys
+const IDENTITY_CACHE_TTL: Duration = Duration::from_secs(5);
+
+/// Identities of all upstreams as last fetched, with the index of the
+/// upstream holding each key
+///
+/// Only used to recover the comment (and upstream) of a key signed on a
+/// connection that has not listed it; nothing is allowed because of it.
+#[derive(Debug)]
+struct IdentitySnapshot {
+    /// When the identities were fetched
+    fetched: Instant,
+    /// Unfiltered identities
+    identities: Vec<(Identity, usize)>,
+}
+
+impl IdentitySnapshot {
+    fn new(identities: Vec<(Identity, usize)>) -> Self {
+        Self {
+            fetched: Instant::now(),
+            identities,
+        }
+    }
+
+    /// Check if the snapshot is recent enough to be used
+    fn is_fresh(&self) -> bool {
+        self.fetched.elapsed() < IDENTITY_CACHE_TTL
+    }
+
+    /// Find a key in the snapshot
+    fn find(&self, key_blob: &[u8]) -> Option<&(Identity, usize)> {
+        self.identities
+            .iter()
+            .find(|(identity, _)| identity.key_blob == key_blob)
+    }
+}
 
 /// SSH Agent proxy that filters requests
 pub struct Proxy {
-    /// Upstream agent connection manager
-    upstream: Arc<Upstream>,
+    /// Upstream agent connection managers (identities of all are merged)
+    upstreams: Vec<Arc<Upstream>>,
     /// Filter evaluator for key filtering
     filter: Arc<FilterEvaluator>,
     /// Socket path for identification
     socket_path: String,
+    /// Feature flags for messages other than REQUEST_IDENTITIES / SIGN_REQUEST
+    access: AccessPolicy,
+    /// Whether listing and/or signing is served
+    mode: SocketMode,
+    /// known_hosts files used to name session-bind destinations
+    known_hosts: Vec<PathBuf>,
     /// Connection counter for client IDs
     connection_counter: AtomicU64,
-    /// Socket-level cache for allowed keys (shared across all connections)
-    /// Updated when REQUEST_IDENTITIES is processed
-    allowed_keys_cache: Arc<RwLock<HashSet<Bytes>>>,
+    /// Upstream identities recently fetched (shared by all connections)
+    identity_cache: Mutex<Option<IdentitySnapshot>>,
+    /// Whether LOCK / UNLOCK lock this socket instead of the upstream agent
+    local_lock: bool,
+    /// Lock the socket after no successful sign for this long
+    idle_lock: Option<Duration>,
+    /// Proxy-local lock state of the socket
+    lock: SocketLock,
+    /// Program asking the user before each signature
+    confirm: Option<Confirm>,
 }
 
 impl Proxy {
@@ -39,22 +91,36 @@ impl Proxy {
     /// * `filter` - Filter evaluator for key filtering
     pub fn new(upstream: Upstream, filter: FilterEvaluator) -> Self {
         Self {
-            upstream: Arc::new(upstream),
+            upstreams: vec![Arc::new(upstream)],
             filter: Arc::new(filter),
             socket_path: String::new(),
+            access: AccessPolicy::default(),
+            mode: SocketMode::default(),
+            known_hosts: KnownHost::default_files(),
             connection_counter: AtomicU64::new(0),
-            allowed_keys_cache: Arc::new(RwLock::new(HashSet::new())),
+            identity_cache: Mutex::new(None),
+            local_lock: false,
+            idle_lock: None,
+            lock: SocketLock::default(),
+            confirm: None,
         }
     }
 
     /// Create a new proxy with Arc-wrapped components
     pub fn new_shared(upstream: Arc<Upstream>, filter: Arc<FilterEvaluator>) -> Self {
         Self {
-            upstream,
+            upstreams: vec![upstream],
             filter,
             socket_path: String::new(),
+            access: AccessPolicy::default(),
+            mode: SocketMode::default(),
+            known_hosts: KnownHost::default_files(),
             connection_counter: AtomicU64::new(0),
-            allowed_keys_cache: Arc::new(RwLock::new(HashSet::new())),
+            identity_cache: Mutex::new(None),
+            local_lock: false,
+            idle_lock: None,
+            lock: SocketLock::default(),
+            confirm: None,
         }
     }
 
@@ -64,9 +130,103 @@ impl Proxy {
         self
     }
 
-    /// Get a reference to the upstream
+    /// Set the feature flags for non-filtered messages
+    pub fn with_access_policy(mut self, access: AccessPolicy) -> Self {
+        self.access = access;
+        self
+    }
+
+    /// Set the socket mode (full, list-only, sign-only)
+    pub fn with_mode(mut self, mode: SocketMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Handle LOCK / UNLOCK in the proxy, locking only this socket
+    ///
+    /// The upstream agent is never locked or unlocked through this socket,
+    /// whatever `allow_lock` says.
+    pub fn with_local_lock(mut self, local_lock: bool) -> Self {
+        self.local_lock = local_lock;
+        self
+    }
+
+    /// Lock the socket (in the proxy) after no successful sign for `timeout`
+    ///
+    /// [`Self::resume`] lifts the lock; UNLOCK from clients only does with
+    /// the passphrase set by [`Self::with_idle_passphrase`].
+    pub fn with_idle_lock(mut self, timeout: Option<Duration>) -> Self {
+        self.idle_lock = timeout;
+        self
+    }
+
+    /// Let UNLOCK with this passphrase lift the idle lock of this socket
+    pub fn with_idle_passphrase(mut self, passphrase: &[u8]) -> Result<Self> {
+        self.lock = SocketLock::with_idle_passphrase(passphrase).ok_or_else(|| {
+            Error::Config("Failed to hash the idle unlock passphrase".to_string())
+        })?;
+        Ok(self)
+    }
+
+    /// Ask the user (through an askpass program) before each signature
+    pub fn with_confirm(mut self, confirm: Option<Confirm>) -> Self {
+        self.confirm = confirm;
+        self
+    }
+
+    /// Check if the socket is locked by the proxy
+    pub fn is_locked(&self) -> bool {
+        self.lock.is_locked()
+    }
+
+    /// Lock the socket if the idle timeout has passed since the last sign
+    ///
+    /// Returns `true` if the socket was locked by this call.
+    pub fn lock_if_idle(&self) -> bool {
+        let Some(timeout) = self.idle_lock else {
+            return false;
+        };
+        let locked = self.lock.lock_if_idle(timeout);
+        if locked {
+            info!(
+                socket = %self.socket_path,
+                idle_lock = ?timeout,
+                "Socket locked after idle timeout"
+            );
+        }
+        locked
+    }
+
+    /// Resume a socket locked by the idle timeout
+    ///
+    /// A lock set with LOCK keeps needing its passphrase. Returns `true` if
+    /// the socket was resumed.
+    pub fn resume(&self) -> bool {
+        let resumed = self.lock.resume_idle();
+        if resumed {
+            info!(socket = %self.socket_path, "Socket resumed from idle lock");
+        }
+        resumed
+    }
+
+    /// Set the known_hosts files used to resolve destination hosts
+    pub fn with_known_hosts(mut self, files: Vec<PathBuf>) -> Self {
+        self.known_hosts = files;
+        self
+    }
+
+    /// Merge the identities of a further upstream agent into this socket
+    ///
+    /// Sign requests are routed to the upstream holding the key; if several
+    /// hold it, the one added first is used.
+    pub fn with_merged_upstream(mut self, upstream: Arc<Upstream>) -> Self {
+        self.upstreams.push(upstream);
+        self
+    }
+
+    /// Get a reference to the (primary) upstream
     pub fn upstream(&self) -> &Upstream {
-        &self.upstream
+        &self.upstreams[0]
     }
 
     /// Get a reference to the filter
@@ -77,16 +237,48 @@ impl Proxy {
     /// Handle a client connection
     ///
     /// This method processes messages from the client, applies filtering,
-    /// and forwards requests to the upstream agent.
-    pub async fn handle_client(&self, mut client_stream: UnixStream) -> Result<()> {
+    /// and forwards requests to the upstream agent. The client may be any
+    /// byte stream (a Unix socket connection, or stdin/stdout in stdio mode).
+    /// The client process is unknown, so client process filters deny.
+    pub async fn handle_client<S>(&self, client_stream: S) -> Result<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        self.handle_client_from(client_stream, None).await
+    }
+
+    /// Handle a client connection from a process with known credentials
+    ///
+    /// The executable and parent chain of the process are looked up for
+    /// client process filters and logging.
+    pub async fn handle_client_from<S>(
+        &self,
+        mut client_stream: S,
+        peer: Option<PeerCredentials>,
+    ) -> Result<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
         let client_id = self.connection_counter.fetch_add(1, Ordering::Relaxed);
+        let client = peer
+            .as_ref()
+            .and_then(|p| p.pid)
+            .and_then(ClientProcess::resolve);
         debug!(
             socket = %self.socket_path,
             client_id = client_id,
+            pid = peer.as_ref().and_then(|p| p.pid),
+            uid = peer.as_ref().map(|p| p.uid),
+            client_exe = ?client.as_ref().and_then(ClientProcess::exe),
+            client_cmdline = client.as_ref().map(ClientProcess::cmdline),
+            client_ancestors = ?client.as_ref().map(ClientProcess::ancestor_names),
             "Client connected"
         );
 
-        let result = self.handle_client_inner(&mut client_stream).await;
+        let mut session = ClientSession::for_client(client);
+        let result = self
+            .handle_client_inner(&mut client_stream, &mut session)
+            .await;
 
         debug!(
             socket = %self.socket_path,
@@ -97,12 +289,17 @@ impl Proxy {
         result
     }
 
-    async fn handle_client_inner(&self, client_stream: &mut UnixStream) -> Result<()> {
-        let (mut client_reader, mut client_writer) = client_stream.split();
-
+    async fn handle_client_inner<S>(
+        &self,
+        client_stream: &mut S,
+        session: &mut ClientSession,
+    ) -> Result<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
         loop {
             // Read request from client
-            let request = match AgentCodec::read(&mut client_reader).await? {
+            let request = match AgentCodec::read(client_stream).await? {
                 Some(msg) => msg,
                 None => {
                     trace!("Client disconnected");
@@ -112,25 +309,123 @@ impl Proxy {
 
             trace!(msg_type = ?request.msg_type, "Received request from client");
 
-            // Process the request (uses socket-level shared cache)
-            let response = self.process_request(request).await?;
+            // Process the request
+            let response = self.process_request(session, request).await?;
 
             // Send response to client
-            AgentCodec::write(&mut client_writer, &response).await?;
+            AgentCodec::write(client_stream, &response).await?;
         }
 
         Ok(())
     }
 
     /// Process a single request from the client
-    async fn process_request(&self, request: AgentMessage) -> Result<AgentMessage> {
-        match request.msg_type {
-            MessageType::RequestIdentities => self.handle_request_identities(request).await,
-            MessageType::SignRequest => self.handle_sign_request(request).await,
+    async fn process_request(
+        &self,
+        session: &mut ClientSession,
+        request: AgentMessage,
+    ) -> Result<AgentMessage> {
+        self.lock_if_idle();
+        // A socket locked by the proxy is unlocked by the proxy, even
+        // without local_lock
+        let is_lock = matches!(request.msg_type, MessageType::Lock | MessageType::Unlock);
+        if is_lock && (self.local_lock || self.lock.is_locked()) {
+            return Ok(self.handle_local_lock(&request).await);
+        }
+        if self.lock.is_locked() {
+            // Like a locked ssh-agent: no keys, and nothing else either
+            debug!(
+                socket = %self.socket_path,
+                msg_type = request.msg_type.as_str(),
+                "Request refused: socket is locked"
+            );
+            return Ok(match request.msg_type {
+                MessageType::RequestIdentities => AgentMessage::build_identities_answer(&[]),
+                _ => AgentMessage::failure(),
+            });
+        }
+
+        // A denied extension is refused below without recording its binding
+        let is_session_bind = match request.msg_type {
+            MessageType::Extension if self.access.permits(request.msg_type) => {
+                match self.handle_extension(session, &request).await {
+                    Some(is_session_bind) => is_session_bind,
+                    None => return Ok(AgentMessage::failure()),
+                }
+            }
+            _ => false,
+        };
+
+        let result = match request.msg_type {
+            MessageType::RequestIdentities => {
+                self.handle_request_identities(session, request).await
+            }
+            MessageType::SignRequest => self.handle_sign_request(session, request).await,
+            msg_type if !self.access.permits(msg_type) => {
+                warn!(
+                    socket = %self.socket_path,
+                    msg_type = msg_type.as_str(),
+                    flag = AccessPolicy::flag_for(msg_type).unwrap_or_default(),
+                    "Request denied by socket policy"
+                );
+                Ok(AgentMessage::failure())
+            }
+            MessageType::Extension if is_session_bind => {
+                self.forward_session_bind(session, request).await
+            }
             _ => {
-                // Pass through other messages
-                self.forward_to_upstream(request).await
+                // Pass through other messages (to the primary upstream)
+                self.forward_to_upstream(session, 0, &request).await
+            }
+        };
+
+        // An upstream that is not running (yet) is not the client's fault;
+        // answer FAILURE and keep the connection open
+        match result {
+            Err(Error::UpstreamNotAvailable(reason)) => {
+                debug!(
+                    socket = %self.socket_path,
+                    reason,
+                    "Request failed: upstream agent not available"
+                );
+                Ok(AgentMessage::failure())
+            }
+            result => result,
+        }
+    }
+
+    /// Handle SSH_AGENTC_LOCK (22) / SSH_AGENTC_UNLOCK (23) for this socket
+    async fn handle_local_lock(&self, request: &AgentMessage) -> AgentMessage {
+        let passphrase = match request.parse_lock_passphrase() {
+            Ok(passphrase) => passphrase,
+            Err(e) => {
+                warn!(error = %e, "Failed to parse lock request");
+                return AgentMessage::failure();
             }
+        };
+        if request.msg_type == MessageType::Lock {
+            if self.lock.lock(&passphrase).await {
+                info!(socket = %self.socket_path, "Socket locked");
+                AgentMessage::success()
+            } else {
+                warn!(socket = %self.socket_path, "Lock refused: socket is already locked");
+                AgentMessage::failure()
+            }
+        } else if self.lock.unlock(&passphrase).await {
+            info!(socket = %self.socket_path, "Socket unlocked");
+            AgentMessage::success()
+        } else if self.lock.is_idle_locked() && !self.lock.has_idle_passphrase() {
+            warn!(
+                socket = %self.socket_path,
+                "Unlock refused: socket is locked after idle timeout, resume with SIGUSR1"
+            );
+            AgentMessage::failure()
+        } else if self.lock.is_locked() {
+            warn!(socket = %self.socket_path, "Unlock refused: wrong passphrase");
+            AgentMessage::failure()
+        } else {
+            warn!(socket = %self.socket_path, "Unlock refused: socket is not locked");
+            AgentMessage::failure()
         }
     }
 
@@ -138,101 +433,480 @@ impl Proxy {
     ///
     /// Forwards the request to upstream, then filters the response
     /// to only include keys that match the filter rules.
-    async fn handle_request_identities(&self, request: AgentMessage) -> Result<AgentMessage> {
+    /// In sign-only mode, an empty list is returned without asking upstream.
+    async fn handle_request_identities(
+        &self,
+        session: &mut ClientSession,
+        request: AgentMessage,
+    ) -> Result<AgentMessage> {
         debug!("Handling REQUEST_IDENTITIES");
 
-        // Forward to upstream
-        let response = self.forward_to_upstream(request).await?;
-
-        // Only process if we got an IdentitiesAnswer
-        if response.msg_type != MessageType::IdentitiesAnswer {
-            warn!(msg_type = ?response.msg_type, "Unexpected response type for REQUEST_IDENTITIES");
-            return Ok(response);
+        if !self.mode.allows_listing() {
+            debug!(
+                socket = %self.socket_path,
+                mode = %self.mode,
+                "Returning empty identity list"
+            );
+            return Ok(AgentMessage::build_identities_answer(&[]));
         }
 
-        // Parse the identities
-        let identities = match response.parse_identities() {
-            Ok(ids) => ids,
-            Err(e) => {
-                warn!(error = %e, "Failed to parse identities from upstream");
-                return Ok(AgentMessage::failure());
+        // Forward to every upstream. An upstream that is not available (yet)
+        // lists no keys; with merged upstreams, one that fails is left out
+        // instead of failing the whole listing.
+        let merged = self.upstreams.len() > 1;
+        let mut identities: Vec<(Identity, usize)> = Vec::new();
+        for index in 0..self.upstreams.len() {
+            let listed = match self.list_upstream(session, index, &request).await {
+                Ok(Ok(listed)) => listed,
+                Ok(Err(response)) if !merged => return Ok(response),
+                Err(Error::UpstreamNotAvailable(reason)) => {
+                    debug!(
+                        socket = %self.socket_path,
+                        upstream = self.upstreams[index].name(),
+                        reason,
+                        "Upstream agent not available, listing no keys from it"
+                    );
+                    continue;
+                }
+                Err(e) if !merged => return Err(e),
+                Ok(Err(_)) => continue,
+                Err(e) => {
+                    warn!(
+                        socket = %self.socket_path,
+                        upstream = self.upstreams[index].name(),
+                        error = %e,
+                        "Failed to list identities, skipping upstream"
+                    );
+                    continue;
+                }
+            };
+            for identity in listed {
+                // A key held by several upstreams is signed by the first one
+                if !identities
+                    .iter()
+                    .any(|(i, _)| i.key_blob == identity.key_blob)
+                {
+                    identities.push((identity, index));
+                }
             }
-        };
+        }
 
         let original_count = identities.len();
         debug!(count = original_count, "Received identities from upstream");
+        self.store_identities(identities.clone());
 
-        // Filter the identities
-        let filtered: Vec<Identity> = identities
+        // Filter the identities for the bound destination host (if any)
+        // and the connected process
+        let host = session.destination();
+        let client = session.client();
+        let filtered: Vec<(Identity, usize)> = identities
             .into_iter()
-            .filter(|id| self.filter.matches(id))
+            .filter(|(id, index)| {
+                let ctx = FilterContext::default()
+                    .with_host(host)
+                    .with_upstream(Some(self.upstreams[*index].name()))
+                    .with_client(client);
+                self.filter.matches_with(id, &ctx)
+            })
             .collect();
 
         let filtered_count = filtered.len();
         info!(
             original = original_count,
             filtered = filtered_count,
+            host = host.map(KnownHost::fingerprint),
+            host_names = ?host.map(KnownHost::names),
+            client_exe = ?client.and_then(ClientProcess::exe),
             "Filtered identities"
         );
 
-        // Update socket-level shared allowed keys cache
+        // Remember the listed keys for sign requests on this connection only;
+        // other clients (and concurrent listings) never see them
+        session.set_listed_keys(&filtered);
+
+        // Build filtered response
+        let filtered: Vec<Identity> = filtered.into_iter().map(|(id, _)| id).collect();
+        Ok(AgentMessage::build_identities_answer(&filtered))
+    }
+
+    /// Ask one upstream for its identities
+    ///
+    /// Returns `Err(response)` with the message to answer the client with if
+    /// the upstream did not send a valid identity list.
+    async fn list_upstream(
+        &self,
+        session: &mut ClientSession,
+        index: usize,
+        request: &AgentMessage,
+    ) -> Result<std::result::Result<Vec<Identity>, AgentMessage>> {
+        let upstream = self.upstreams[index].name();
+        let response = self.forward_to_upstream(session, index, request).await?;
+
+        // Only process if we got an IdentitiesAnswer
+        if response.msg_type != MessageType::IdentitiesAnswer {
+            warn!(upstream, msg_type = ?response.msg_type, "Unexpected response type for REQUEST_IDENTITIES");
+            return Ok(Err(response));
+        }
+
+        // Parse the identities
+        match response.parse_identities() {
+            Ok(ids) => {
+                trace!(upstream, count = ids.len(), "Received identities");
+                Ok(Ok(ids))
+            }
+            Err(e) => {
+                warn!(upstream, error = %e, "Failed to parse identities from upstream");
+                Ok(Err(AgentMessage::failure()))
+            }
+        }
+    }
+
+    /// Look up a key that was not listed on the connection
+    ///
+    /// Returns the key's identity as held by upstream (with its comment) and
+    /// the index of the upstream holding it. Upstream identities fetched in
+    /// the last few seconds are reused, so that a client signing on fresh
+    /// connections does not list every upstream each time.
+    async fn lookup_key(
+        &self,
+        session: &mut ClientSession,
+        key_blob: &[u8],
+    ) -> Option<(Identity, usize)> {
+        if let Some(snapshot) = self.identity_cache.lock().unwrap().as_ref()
+            && snapshot.is_fresh()
+            && let Some(found) = snapshot.find(key_blob)
         {
-            let mut cache = self.allowed_keys_cache.write().await;
-            cache.clear();
-            for identity in &filtered {
-                cache.insert(identity.key_blob.clone());
+            trace!(socket = %self.socket_path, "Found unlisted key in identity cache");
+            return Some(found.clone());
+        }
+
+        let request = AgentMessage::new(MessageType::RequestIdentities, Bytes::new());
+        let mut identities: Vec<(Identity, usize)> = Vec::new();
+        for index in 0..self.upstreams.len() {
+            let Ok(Ok(listed)) = self.list_upstream(session, index, &request).await else {
+                continue;
+            };
+            for identity in listed {
+                if !identities
+                    .iter()
+                    .any(|(i, _)| i.key_blob == identity.key_blob)
+                {
+                    identities.push((identity, index));
+                }
             }
         }
+        let found = identities
+            .iter()
+            .find(|(identity, _)| identity.key_blob == key_blob)
+            .cloned();
+        self.store_identities(identities);
+        found
+    }
 
-        // Build filtered response
-        Ok(AgentMessage::build_identities_answer(&filtered))
+    /// Remember the identities of all upstreams for [`Self::lookup_key`]
+    fn store_identities(&self, identities: Vec<(Identity, usize)>) {
+        *self.identity_cache.lock().unwrap() = Some(IdentitySnapshot::new(identities));
     }
 
     /// Handle SSH_AGENTC_SIGN_REQUEST (13)
     ///
-    /// Only allows signing with keys that are in the allowed set
-    /// (i.e., keys that passed the filter in a previous REQUEST_IDENTITIES),
-    /// or keys that match the filter directly.
-    async fn handle_sign_request(&self, request: AgentMessage) -> Result<AgentMessage> {
-        // Parse the key blob from the request
-        let key_blob = match request.parse_sign_request_key() {
-            Ok(blob) => blob,
+    /// Only allows signing with keys that match the filter, evaluated with
+    /// the contents of the sign request (remote user, algorithm, ...), and
+    /// with `confirm`, only after the user agreed.
+    /// Keys are checked with their comment, as listed on the same connection
+    /// or looked up from upstream.
+    /// In list-only mode, every sign request is denied. Data that is neither
+    /// an SSH login nor an SSHSIG signature is denied unless permitted.
+    async fn handle_sign_request(
+        &self,
+        session: &mut ClientSession,
+        request: AgentMessage,
+    ) -> Result<AgentMessage> {
+        if !self.mode.allows_signing() {
+            warn!(
+                socket = %self.socket_path,
+                mode = %self.mode,
+                "Sign request denied by socket mode"
+            );
+            return Ok(AgentMessage::failure());
+        }
+
+        // Parse the key blob and the data to be signed
+        let sign_request = match request.parse_sign_request() {
+            Ok(req) => req,
             Err(e) => {
                 warn!(error = %e, "Failed to parse sign request");
                 return Ok(AgentMessage::failure());
             }
         };
+        let sign = SignContext::new(&sign_request);
+        let purpose = sign.payload.kind();
+        let userauth = sign.payload.userauth();
+        let namespace = sign.payload.sshsig().map(|s| s.namespace.as_str());
+        let algorithm = sign.algorithm.as_deref();
+
+        // Refuse to sign arbitrary data unless the socket permits it
+        if !self.access.permits_sign(&sign.payload) {
+            warn!(
+                socket = %self.socket_path,
+                purpose,
+                algorithm,
+                "Sign request denied: unrecognised data (allow-unknown-sign is not set)"
+            );
+            return Ok(AgentMessage::failure());
+        }
 
-        // Create identity for filter check
-        let identity = Identity::new(key_blob.clone(), String::new());
-
-        // Check if this key is allowed:
-        // 1. First check the socket-level shared cache (from REQUEST_IDENTITIES)
-        // 2. If not in cache, apply the filter directly
-        //    (handles cases where SSH client uses separate connections)
-        let is_allowed = {
-            let cache = self.allowed_keys_cache.read().await;
-            cache.contains(key_blob.as_ref())
-        } || self.filter.matches(&identity);
-        // Note: filter.matches() is called outside the lock to avoid holding
-        // the lock longer than necessary. This also handles cases where
-        // SSH client uses separate connections and the key wasn't cached.
-        // For comment-based filters, this may not match, but that's acceptable
-        // since the key should have been in the cache from REQUEST_IDENTITIES.
-
-        if !is_allowed {
-            warn!("Sign request denied: key not allowed by filter");
+        // Use the identity listed on this connection if available. A key
+        // signed on a fresh connection (or one it was not listed) is looked
+        // up in the upstream identities, so comment filters see the same
+        // comment as when listing. Other connections' listings are never
+        // used. If no upstream lists the key, a single upstream is still
+        // asked to sign, with the filter applied to the bare key.
+        let listed = match session.listed_key(&sign_request.key_blob).cloned() {
+            Some(listed) => Some(listed),
+            None => self.lookup_key(session, &sign_request.key_blob).await,
+        };
+        let (identity, index) = match listed {
+            Some(listed) => listed,
+            None if self.upstreams.len() > 1 => {
+                warn!(
+                    socket = %self.socket_path,
+                    "Sign request denied: key not held by any upstream"
+                );
+                return Ok(AgentMessage::failure());
+            }
+            None => (
+                Identity::new(sign_request.key_blob.clone(), String::new()),
+                0,
+            ),
+        };
+        let upstream = self.upstreams[index].name();
+        // The destination of an SSH login is the host its session was bound to
+        let host = userauth.and_then(|u| session.destination_for(&u.session_id));
+        let host_names = host.map(KnownHost::names);
+        let client = session.client();
+        let client_exe = client.and_then(ClientProcess::exe);
+
+        // Sign request filters (ssh-user=, sign-alg=, ...) are always
+        // evaluated here, even for keys that were listed.
+        let ctx = FilterContext::for_sign(&sign)
+            .with_host(host)
+            .with_upstream(Some(upstream))
+            .with_client(client);
+        if !self.filter.matches_with(&identity, &ctx) {
+            warn!(
+                socket = %self.socket_path,
+                upstream,
+                client_exe = ?client_exe,
+                client_ancestors = ?client.map(ClientProcess::ancestor_names),
+                purpose,
+                host_names = ?host_names,
+                ssh_user = userauth.map(|u| u.user.as_str()),
+                ssh_service = userauth.map(|u| u.service.as_str()),
+                namespace,
+                algorithm,
+                "Sign request denied: key not allowed by filter"
+            );
             return Ok(AgentMessage::failure());
         }
 
-        // Forward to upstream
-        self.forward_to_upstream(request).await
+        // Sign requests that pass the filter may still need the user's consent
+        if let Some(confirm) = &self.confirm {
+            let request = ConfirmRequest {
+                socket: self.socket_path.clone(),
+                fingerprint: identity
+                    .fingerprint()
+                    .map(|f| f.to_string())
+                    .unwrap_or_default(),
+                comment: identity.comment.clone(),
+                client: client.map(|c| c.process.to_string()),
+                destination: host.map(|h| match h.names() {
+                    [] => h.fingerprint().to_string(),
+                    names => names.join(", "),
+                }),
+            };
+            let confirmed = match confirm.ask(&request).await {
+                Ok(confirmed) => confirmed,
+                Err(e) => {
+                    warn!(socket = %self.socket_path, error = %e, "Failed to ask for confirmation");
+                    false
+                }
+            };
+            if !confirmed {
+                warn!(
+                    socket = %self.socket_path,
+                    upstream,
+                    client_exe = ?client_exe,
+                    fingerprint = %request.fingerprint,
+                    host_names = ?host_names,
+                    "Sign request denied: not confirmed"
+                );
+                return Ok(AgentMessage::failure());
+            }
+        }
+
+        debug!(
+            upstream,
+            client_exe = ?client_exe,
+            purpose,
+            host_names = ?host_names,
+            ssh_user = userauth.map(|u| u.user.as_str()),
+            ssh_service = userauth.map(|u| u.service.as_str()),
+            namespace,
+            algorithm,
+            "Sign request allowed"
+        );
+
+        // Forward to the upstream holding the key; a signature restarts the
+        // idle timeout
+        let response = self.forward_to_upstream(session, index, &request).await?;
+        if response.msg_type == MessageType::SignResponse {
+            self.lock.touch();
+        }
+        Ok(response)
+    }
+
+    /// Inspect SSH_AGENTC_EXTENSION (27) for session-bind@openssh.com
+    ///
+    /// Only called when the socket permits extensions. A binding whose host
+    /// key signature verifies is recorded for this client connection.
+    /// Returns whether the request is a session bind, or `None` if it must
+    /// be answered with FAILURE.
+    async fn handle_extension(
+        &self,
+        session: &mut ClientSession,
+        request: &AgentMessage,
+    ) -> Option<bool> {
+        let bind = match request.parse_session_bind() {
+            Ok(Some(bind)) => bind,
+            Ok(None) => return Some(false),
+            Err(e) => {
+                warn!(error = %e, "Failed to parse session-bind request");
+                return None;
+            }
+        };
+
+        if !bind.verify() {
+            warn!(
+                socket = %self.socket_path,
+                "Session bind rejected: invalid host key signature"
+            );
+            return None;
+        }
+
+        // Reading known_hosts is file I/O, keep it off the runtime
+        let host_key = bind.host_key.clone();
+        let files = self.known_hosts.clone();
+        let host =
+            match tokio::task::spawn_blocking(move || KnownHost::resolve_in(&host_key, &files))
+                .await
+            {
+                Ok(host) => host,
+                Err(e) => {
+                    warn!(error = %e, "Failed to look up session-bind host");
+                    return None;
+                }
+            };
+
+        let fingerprint = host.fingerprint().to_string();
+        let names = host.names().to_vec();
+        let known = host.is_known();
+        if !session.bind(HostBinding {
+            session_id: bind.session_id,
+            host,
+        }) {
+            warn!(socket = %self.socket_path, "Session bind rejected: too many bindings");
+            return None;
+        }
+
+        info!(
+            socket = %self.socket_path,
+            host = fingerprint,
+            host_names = ?names,
+            known,
+            forwarding = bind.is_forwarding,
+            "Session bound to host"
+        );
+        Some(true)
+    }
+
+    /// Forward session-bind@openssh.com to every upstream
+    ///
+    /// Each agent enforces the bindings of its own keys, so all of them must
+    /// see it. The client gets SUCCESS if any upstream accepted the binding,
+    /// and the binding is then replayed whenever an upstream reconnects.
+    async fn forward_session_bind(
+        &self,
+        session: &mut ClientSession,
+        request: AgentMessage,
+    ) -> Result<AgentMessage> {
+        let mut response: Option<AgentMessage> = None;
+        for index in 0..self.upstreams.len() {
+            match self.forward_to_upstream(session, index, &request).await {
+                Ok(r) if r.msg_type == MessageType::Success => response = Some(r),
+                Ok(r) => {
+                    debug!(
+                        upstream = self.upstreams[index].name(),
+                        msg_type = ?r.msg_type,
+                        "Upstream refused session binding"
+                    );
+                    response.get_or_insert(r);
+                }
+                Err(e) if self.upstreams.len() == 1 => return Err(e),
+                Err(e) => {
+                    warn!(
+                        socket = %self.socket_path,
+                        upstream = self.upstreams[index].name(),
+                        error = %e,
+                        "Failed to forward session binding"
+                    );
+                }
+            }
+        }
+
+        let response = response.unwrap_or_else(AgentMessage::failure);
+        // Upstream keeps session bindings per connection; remember them so
+        // that they survive a reconnect
+        if response.msg_type == MessageType::Success {
+            session.remember_for_reconnect(request);
+        }
+        Ok(response)
     }
 
-    /// Forward a message to the upstream agent
-    async fn forward_to_upstream(&self, request: AgentMessage) -> Result<AgentMessage> {
-        let mut conn = self.upstream.connect().await?;
-        conn.send_receive(&request).await
+    /// Forward a message to an upstream agent (by index)
+    ///
+    /// Each client connection uses its own upstream connection for its
+    /// lifetime, so that per-connection agent state (session bindings,
+    /// confirmation context) is preserved. If the upstream connection has
+    /// been closed, a new one is opened, the client's session bindings are
+    /// replayed, and the request is sent again (see [`Upstream::send`]).
+    /// Stateless requests use the upstream connection pool when it is
+    /// enabled.
+    async fn forward_to_upstream(
+        &self,
+        session: &mut ClientSession,
+        index: usize,
+        request: &AgentMessage,
+    ) -> Result<AgentMessage> {
+        let upstream = &self.upstreams[index];
+
+        // Listing keys does not depend on per-connection state unless the
+        // client has bound its session, so it may use a pooled connection
+        if upstream.has_pool()
+            && request.msg_type == MessageType::RequestIdentities
+            && session.reconnect_messages().is_empty()
+        {
+            return upstream.send_pooled(request).await;
+        }
+
+        let mut slot = session.take_upstream(index);
+        let result = upstream
+            .send(&mut slot, session.reconnect_messages(), request)
+            .await;
+        if let Some(conn) = slot {
+            session.set_upstream(index, conn);
+        }
+        result
     }
 }
 
@@ -258,4 +932,68 @@ mod tests {
 
         assert_eq!(proxy.socket_path, "/tmp/my.sock");
     }
+
+    #[test]
+    fn test_identity_snapshot_expires() {
+        let key = Identity::new(Bytes::from_static(b"key"), "user@work".into());
+        let mut snapshot = IdentitySnapshot::new(vec![(key, 1)]);
+        assert!(snapshot.is_fresh());
+        assert_eq!(
+            snapshot.find(b"key").map(|(i, _)| i.comment.as_str()),
+            Some("user@work")
+        );
+        assert!(snapshot.find(b"other").is_none());
+
+        snapshot.fetched = Instant::now() - IDENTITY_CACHE_TTL;
+        assert!(!snapshot.is_fresh());
+    }
+
+    #[tokio::test]
+    async fn test_sign_only_returns_empty_identities() {
+        // Upstream does not exist; sign-only mode must not contact it for listing
+        let upstream = Upstream::new("/tmp/nonexistent-socket-12345.sock");
+        let filter = FilterEvaluator::default();
+        let proxy = Proxy::new(upstream, filter).with_mode(SocketMode::SignOnly);
+
+        let request = AgentMessage::new(MessageType::RequestIdentities, Bytes::new());
+        let response = proxy
+            .process_request(&mut ClientSession::default(), request)
+            .await
+            .unwrap();
+        assert!(response.parse_identities().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_list_only_denies_sign() {
+        let upstream = Upstream::new("/tmp/nonexistent-socket-12345.sock");
+        let filter = FilterEvaluator::default();
+        let proxy = Proxy::new(upstream, filter).with_mode(SocketMode::ListOnly);
+
+        let mut payload = bytes::BytesMut::new();
+        payload.extend_from_slice(&0u32.to_be_bytes());
+        let request = AgentMessage::new(MessageType::SignRequest, payload.freeze());
+        let response = proxy
+            .process_request(&mut ClientSession::default(), request)
+            .await
+            .unwrap();
+        assert_eq!(response.msg_type, MessageType::Failure);
+    }
+
+    #[tokio::test]
+    async fn test_denied_message_returns_failure() {
+        // Upstream does not exist, so a forwarded request would error out
+        let upstream = Upstream::new("/tmp/nonexistent-socket-12345.sock");
+        let filter = FilterEvaluator::default();
+        let proxy = Proxy::new(upstream, filter).with_access_policy(AccessPolicy {
+            allow_remove: Some(false),
+            ..Default::default()
+        });
+
+        let request = AgentMessage::new(MessageType::RemoveAllIdentities, Bytes::new());
+        let response = proxy
+            .process_request(&mut ClientSession::default(), request)
+            .await
+            .unwrap();
+        assert_eq!(response.msg_type, MessageType::Failure);
+    }
 }
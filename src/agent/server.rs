@@ -3,8 +3,11 @@
 //! This module provides a Unix socket server that listens for client
 //! connections and spawns proxy handlers for each connection.
 
+use super::peer::{PeerCredentials, PeerPolicy};
 use crate::error::{Error, Result};
-use crate::utils::socket::{prepare_socket_path, set_socket_permissions};
+use crate::utils::socket::{
+    SocketPermissions, bind_listener, is_abstract, prepare_socket_path, set_socket_permissions,
+};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use tokio::net::{UnixListener, UnixStream};
@@ -13,8 +16,12 @@ use tracing::{debug, error, info, trace, warn};
 
 /// Unix socket server for accepting SSH agent client connections
 pub struct Server {
-    /// Path to the socket file
+    /// Path to the socket file (or `@name` for an abstract socket)
     socket_path: PathBuf,
+    /// Mode and group of the socket file
+    permissions: SocketPermissions,
+    /// Users allowed to connect
+    peers: PeerPolicy,
     /// The listener (created on bind)
     listener: Option<UnixListener>,
 }
@@ -27,10 +34,24 @@ impl Server {
     pub fn new<P: AsRef<Path>>(socket_path: P) -> Self {
         Self {
             socket_path: socket_path.as_ref().to_path_buf(),
+            permissions: SocketPermissions::default(),
+            peers: PeerPolicy::default(),
             listener: None,
         }
     }
 
+    /// Set the mode and group of the socket file (default: 0600)
+    pub fn with_permissions(mut self, permissions: SocketPermissions) -> Self {
+        self.permissions = permissions;
+        self
+    }
+
+    /// Set the users allowed to connect besides the owner and root
+    pub fn with_peer_policy(mut self, peers: PeerPolicy) -> Self {
+        self.peers = peers;
+        self
+    }
+
     /// Get the socket path
     pub fn socket_path(&self) -> &Path {
         &self.socket_path
@@ -40,12 +61,15 @@ impl Server {
     ///
     /// This creates the Unix socket file. If a file already exists at the path,
     /// it will be removed first (to handle stale sockets from crashed processes).
+    /// An `@name` path binds in the Linux abstract namespace instead, where no
+    /// file is created.
     pub async fn bind(&mut self) -> Result<()> {
         // Prepare socket path (remove existing, create parent dir)
-        prepare_socket_path(&self.socket_path).map_err(|e| Error::Socket(e.to_string()))?;
+        prepare_socket_path(&self.socket_path, &self.permissions)
+            .map_err(|e| Error::Socket(e.to_string()))?;
 
         // Create the listener
-        let listener = UnixListener::bind(&self.socket_path).map_err(|e| {
+        let listener = bind_listener(&self.socket_path).map_err(|e| {
             Error::Socket(format!(
                 "Failed to bind to socket at {}: {}",
                 self.socket_path.display(),
@@ -53,30 +77,37 @@ impl Server {
             ))
         })?;
 
-        // Set socket permissions to 0600 (owner read/write only)
-        set_socket_permissions(&self.socket_path).map_err(|e| Error::Socket(e.to_string()))?;
+        // Set socket permissions (0600, owner read/write only, unless configured)
+        set_socket_permissions(&self.socket_path, &self.permissions)
+            .map_err(|e| Error::Socket(e.to_string()))?;
 
-        info!(path = %self.socket_path.display(), "Server listening");
+        debug!(path = %self.socket_path.display(), "Server listening");
         self.listener = Some(listener);
         Ok(())
     }
 
     /// Accept the next client connection
     ///
-    /// Returns `None` if the server is not bound or if the listener encounters a fatal error.
-    pub async fn accept(&self) -> Result<UnixStream> {
+    /// Returns the stream with the client's credentials, or an error if the
+    /// server is not bound or the listener fails. Clients of users not
+    /// allowed by the peer policy are rejected and skipped.
+    pub async fn accept(&self) -> Result<(UnixStream, PeerCredentials)> {
         let listener = self
             .listener
             .as_ref()
             .ok_or_else(|| Error::Socket("Server is not bound".to_string()))?;
 
-        let (stream, _addr) = listener
-            .accept()
-            .await
-            .map_err(|e| Error::Socket(format!("Failed to accept connection: {}", e)))?;
-
-        trace!("Accepted new client connection");
-        Ok(stream)
+        loop {
+            let (stream, _addr) = listener
+                .accept()
+                .await
+                .map_err(|e| Error::Socket(format!("Failed to accept connection: {}", e)))?;
+
+            if let Some(peer) = self.peers.admit(&stream, &self.socket_path) {
+                trace!("Accepted new client connection");
+                return Ok((stream, peer));
+            }
+        }
     }
 
     /// Run the server with a connection handler
@@ -84,7 +115,8 @@ impl Server {
     /// This method runs until the shutdown signal is received.
     ///
     /// # Arguments
-    /// * `handler` - Async function to handle each client connection
+    /// * `handler` - Async function to handle each client connection, given
+    ///   the credentials of its (admitted) peer
     /// * `shutdown_rx` - Watch receiver for shutdown signal
     pub async fn run<F, Fut>(
         &self,
@@ -92,7 +124,7 @@ impl Server {
         mut shutdown_rx: watch::Receiver<bool>,
     ) -> Result<()>
     where
-        F: Fn(UnixStream) -> Fut + Send + Sync + 'static,
+        F: Fn(UnixStream, PeerCredentials) -> Fut + Send + Sync + 'static,
         Fut: std::future::Future<Output = Result<()>> + Send + 'static,
     {
         let listener = self
@@ -116,10 +148,14 @@ impl Server {
                 accept_result = listener.accept() => {
                     match accept_result {
                         Ok((stream, _addr)) => {
+                            // Only serve expected users, whatever the socket permissions
+                            let Some(peer) = self.peers.admit(&stream, &self.socket_path) else {
+                                continue;
+                            };
                             trace!("Accepted new client connection");
                             let handler = Arc::clone(&handler);
                             tokio::spawn(async move {
-                                if let Err(e) = handler(stream).await {
+                                if let Err(e) = handler(stream, peer).await {
                                     // Connection errors are expected (client disconnect, etc.)
                                     debug!(error = %e, "Connection handler error");
                                 }
@@ -137,9 +173,9 @@ impl Server {
         Ok(())
     }
 
-    /// Clean up the socket file
+    /// Clean up the socket file (abstract sockets vanish with the listener)
     fn cleanup(&self) {
-        if self.socket_path.exists() {
+        if !is_abstract(&self.socket_path) && self.socket_path.exists() {
             if let Err(e) = std::fs::remove_file(&self.socket_path) {
                 warn!(
                     path = %self.socket_path.display(),
@@ -177,7 +213,7 @@ impl SocketCleanupGuard {
 
 impl Drop for SocketCleanupGuard {
     fn drop(&mut self) {
-        if self.path.exists() {
+        if !is_abstract(&self.path) && self.path.exists() {
             if let Err(e) = std::fs::remove_file(&self.path) {
                 warn!(
                     path = %self.path.display(),
@@ -249,4 +285,23 @@ mod tests {
         // File should be removed after guard is dropped
         assert!(!socket_path.exists());
     }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_server_abstract_socket() {
+        use std::os::linux::net::SocketAddrExt;
+
+        let name = format!("authsock-filter-server-test-{}", std::process::id());
+        let mut server = Server::new(format!("@{}", name));
+        server.bind().await.unwrap();
+
+        let addr = std::os::unix::net::SocketAddr::from_abstract_name(name.as_bytes()).unwrap();
+        let _client = std::os::unix::net::UnixStream::connect_addr(&addr).unwrap();
+        // Connections of the same user are accepted, with their credentials
+        let (_stream, peer) = server.accept().await.unwrap();
+        assert_eq!(peer.uid, unsafe { libc::getuid() });
+
+        drop(server);
+        assert!(std::os::unix::net::UnixStream::connect_addr(&addr).is_err());
+    }
 }
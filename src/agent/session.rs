@@ -0,0 +1,156 @@
+//! Per-client connection state
+
+use super::upstream::UpstreamConnection;
+use crate::filter::{ClientProcess, KnownHost};
+use crate::protocol::{AgentMessage, Identity};
+use bytes::Bytes;
+use std::collections::HashMap;
+
+/// Maximum number of session bindings per connection (as in ssh-agent)
+const MAX_BINDINGS: usize = 16;
+
+/// A verified session-bind@openssh.com binding
+#[derive(Debug, Clone)]
+pub struct HostBinding {
+    /// Session identifier of the SSH connection
+    pub session_id: Bytes,
+    /// Server the session was established with
+    pub host: KnownHost,
+}
+
+/// State of a single client connection
+#[derive(Debug, Default)]
+pub struct ClientSession {
+    /// Session bindings in the order they were made
+    bindings: Vec<HostBinding>,
+    /// Upstream connections dedicated to this client, by upstream index
+    /// (opened on first use)
+    upstreams: HashMap<usize, UpstreamConnection>,
+    /// Requests accepted by upstream that must be replayed after a reconnect
+    reconnect_messages: Vec<AgentMessage>,
+    /// Process connected to the socket, if it could be inspected
+    client: Option<ClientProcess>,
+    /// Keys returned by this connection's last REQUEST_IDENTITIES, with the
+    /// index of the upstream holding each
+    listed_keys: HashMap<Bytes, (Identity, usize)>,
+}
+
+impl ClientSession {
+    /// Create the state of a connection from a (possibly unknown) process
+    pub fn for_client(client: Option<ClientProcess>) -> Self {
+        Self {
+            client,
+            ..Default::default()
+        }
+    }
+
+    /// Get the process connected to the socket
+    pub fn client(&self) -> Option<&ClientProcess> {
+        self.client.as_ref()
+    }
+
+    /// Record a session binding
+    ///
+    /// Returns `false` if the connection already has too many bindings.
+    pub fn bind(&mut self, binding: HostBinding) -> bool {
+        if self.bindings.len() >= MAX_BINDINGS {
+            return false;
+        }
+        self.bindings.push(binding);
+        true
+    }
+
+    /// Get the most recently bound host (used when listing keys)
+    pub fn destination(&self) -> Option<&KnownHost> {
+        self.bindings.last().map(|b| &b.host)
+    }
+
+    /// Get the host bound to an SSH session (used when signing userauth data)
+    pub fn destination_for(&self, session_id: &[u8]) -> Option<&KnownHost> {
+        self.bindings
+            .iter()
+            .rev()
+            .find(|b| b.session_id.as_ref() == session_id)
+            .map(|b| &b.host)
+    }
+
+    /// Replace the keys listed to this client
+    pub fn set_listed_keys(&mut self, keys: &[(Identity, usize)]) {
+        self.listed_keys = keys
+            .iter()
+            .map(|(identity, index)| (identity.key_blob.clone(), (identity.clone(), *index)))
+            .collect();
+    }
+
+    /// Get a key listed to this client, with the index of its upstream
+    pub fn listed_key(&self, key_blob: &[u8]) -> Option<&(Identity, usize)> {
+        self.listed_keys.get(key_blob)
+    }
+
+    /// Take the client's connection to an upstream, if one is open
+    pub fn take_upstream(&mut self, index: usize) -> Option<UpstreamConnection> {
+        self.upstreams.remove(&index)
+    }
+
+    /// Keep an upstream connection for later requests of this client
+    pub fn set_upstream(&mut self, index: usize, conn: UpstreamConnection) {
+        self.upstreams.insert(index, conn);
+    }
+
+    /// Remember a request to replay on a new upstream connection
+    pub fn remember_for_reconnect(&mut self, request: AgentMessage) {
+        self.reconnect_messages.push(request);
+    }
+
+    /// Get the requests to replay on a new upstream connection
+    pub fn reconnect_messages(&self) -> &[AgentMessage] {
+        &self.reconnect_messages
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn binding(session_id: &'static [u8]) -> HostBinding {
+        HostBinding {
+            session_id: Bytes::from_static(session_id),
+            host: KnownHost::resolve_in(session_id, &[]),
+        }
+    }
+
+    #[test]
+    fn test_destination() {
+        let mut session = ClientSession::default();
+        assert!(session.destination().is_none());
+
+        assert!(session.bind(binding(b"first")));
+        assert!(session.bind(binding(b"second")));
+        assert!(session.destination().is_some());
+        assert!(session.destination_for(b"first").is_some());
+        assert!(session.destination_for(b"unknown").is_none());
+    }
+
+    #[test]
+    fn test_listed_keys_are_replaced() {
+        let key = |blob: &'static [u8]| Identity::new(Bytes::from_static(blob), "k".into());
+        let mut session = ClientSession::default();
+        assert!(session.listed_key(b"a").is_none());
+
+        session.set_listed_keys(&[(key(b"a"), 0), (key(b"b"), 1)]);
+        assert_eq!(session.listed_key(b"b").map(|(_, i)| *i), Some(1));
+
+        session.set_listed_keys(&[(key(b"b"), 0)]);
+        assert!(session.listed_key(b"a").is_none());
+        assert_eq!(session.listed_key(b"b").map(|(_, i)| *i), Some(0));
+    }
+
+    #[test]
+    fn test_binding_limit() {
+        let mut session = ClientSession::default();
+        for _ in 0..MAX_BINDINGS {
+            assert!(session.bind(binding(b"id")));
+        }
+        assert!(!session.bind(binding(b"id")));
+    }
+}
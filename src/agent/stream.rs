@@ -0,0 +1,162 @@
+//! Streams to upstream agents
+//!
+//! An upstream agent is reached over a Unix socket, or over the stdin and
+//! stdout of a spawned process (`exec:ssh bastion -- socat - UNIX-CONNECT:...`).
+
+use crate::error::{Error, Result};
+use crate::utils::path::split_command;
+use std::io;
+use std::pin::Pin;
+use std::process::Stdio;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::UnixStream;
+use tokio::process::{Child, ChildStdin, ChildStdout, Command};
+
+/// Byte stream speaking the agent protocol with an upstream agent
+#[derive(Debug)]
+pub enum UpstreamStream {
+    /// Unix socket of the agent
+    Unix(UnixStream),
+    /// Stdin/stdout of a process relaying to the agent
+    Process(ProcessStream),
+}
+
+/// Spawned process whose stdin/stdout carry the agent protocol
+///
+/// The process is killed when the stream is dropped.
+#[derive(Debug)]
+pub struct ProcessStream {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: ChildStdout,
+}
+
+impl ProcessStream {
+    /// Spawn a command (split like a shell would, without running a shell)
+    ///
+    /// Its stderr is passed through to the proxy's stderr.
+    pub fn spawn(command: &str) -> Result<Self> {
+        let args = split_command(command)?;
+        let mut child = Command::new(&args[0])
+            .args(&args[1..])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| {
+                Error::UpstreamNotAvailable(format!(
+                    "Failed to start upstream command '{}': {}",
+                    command, e
+                ))
+            })?;
+        let (Some(stdin), Some(stdout)) = (child.stdin.take(), child.stdout.take()) else {
+            return Err(Error::UpstreamNotAvailable(format!(
+                "Upstream command '{}' has no stdin/stdout",
+                command
+            )));
+        };
+        Ok(Self {
+            child,
+            stdin,
+            stdout,
+        })
+    }
+
+    /// Get the process ID (None once it has exited)
+    pub fn id(&self) -> Option<u32> {
+        self.child.id()
+    }
+}
+
+impl UpstreamStream {
+    /// Check that the stream is idle and still open
+    ///
+    /// An idle agent connection has nothing to read; readable data or EOF
+    /// means it can no longer be used for request/response pairs. A process
+    /// is considered open while it is running.
+    pub fn is_healthy(&mut self) -> bool {
+        match self {
+            Self::Unix(stream) => {
+                let mut buf = [0u8; 1];
+                matches!(
+                    stream.try_read(&mut buf),
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock
+                )
+            }
+            Self::Process(process) => matches!(process.child.try_wait(), Ok(None)),
+        }
+    }
+}
+
+impl AsyncRead for UpstreamStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Unix(stream) => Pin::new(stream).poll_read(cx, buf),
+            Self::Process(process) => Pin::new(&mut process.stdout).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for UpstreamStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Self::Unix(stream) => Pin::new(stream).poll_write(cx, buf),
+            Self::Process(process) => Pin::new(&mut process.stdin).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Unix(stream) => Pin::new(stream).poll_flush(cx),
+            Self::Process(process) => Pin::new(&mut process.stdin).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Unix(stream) => Pin::new(stream).poll_shutdown(cx),
+            Self::Process(process) => Pin::new(&mut process.stdin).poll_shutdown(cx),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{AgentCodec, AgentMessage, MessageType};
+
+    #[tokio::test]
+    async fn test_process_stream_round_trip() {
+        // cat echoes the request back as the "response"
+        let mut stream = UpstreamStream::Process(ProcessStream::spawn("cat").unwrap());
+        assert!(stream.is_healthy());
+
+        let request = AgentMessage::new(MessageType::RequestIdentities, Default::default());
+        AgentCodec::write(&mut stream, &request).await.unwrap();
+        let echoed = AgentCodec::read(&mut stream).await.unwrap().unwrap();
+        assert_eq!(echoed.msg_type, MessageType::RequestIdentities);
+    }
+
+    #[tokio::test]
+    async fn test_process_stream_exited() {
+        let mut stream = UpstreamStream::Process(ProcessStream::spawn("true").unwrap());
+        assert!(AgentCodec::read(&mut stream).await.unwrap().is_none());
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert!(!stream.is_healthy());
+
+        assert!(matches!(
+            ProcessStream::spawn("/nonexistent/command"),
+            Err(Error::UpstreamNotAvailable(_))
+        ));
+    }
+}
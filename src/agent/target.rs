@@ -0,0 +1,277 @@
+//! Upstream agent locations
+//!
+//! An upstream is usually a fixed socket path. Some agents live at paths
+//! that change over time (e.g., forwarded agents under `/tmp/ssh-*/`) or
+//! are only known to a helper command (`gpgconf`), so their location is
+//! resolved whenever a connection is needed.
+
+use super::preset::{PRESET_PREFIX, Preset};
+use crate::error::{Error, Result};
+use crate::utils::path::{command_path, split_command};
+use globset::GlobBuilder;
+use std::fmt;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+
+/// Prefix of auto-discovered upstreams
+pub const DISCOVER_PREFIX: &str = "discover:";
+
+/// Prefix of upstreams whose socket path is printed by a command
+pub const COMMAND_PREFIX: &str = "command:";
+
+/// Prefix of upstreams reached over a spawned process's stdin/stdout
+pub const EXEC_PREFIX: &str = "exec:";
+
+/// Location of an upstream agent socket
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum UpstreamTarget {
+    /// Fixed socket path
+    Path(PathBuf),
+    /// Newest live socket matching a glob pattern and owned by the current
+    /// user (`discover:/tmp/ssh-*/agent.*`)
+    Discover(String),
+    /// Socket path printed by a command
+    /// (`command:gpgconf --list-dirs agent-ssh-socket`)
+    Command(String),
+    /// Documented socket of a well-known agent (`preset:1password`)
+    Preset(Preset),
+    /// Process speaking the agent protocol on stdin/stdout, spawned for
+    /// each connection (`exec:ssh bastion -- socat - UNIX-CONNECT:...`)
+    Exec(String),
+}
+
+impl UpstreamTarget {
+    /// Parse an upstream spec (`discover:PATTERN`, `command:COMMAND`,
+    /// `preset:NAME`, `exec:COMMAND` or a socket path)
+    pub fn parse(spec: &str) -> Result<Self> {
+        if let Some(command) = spec.strip_prefix(EXEC_PREFIX) {
+            split_command(command)?;
+            return Ok(Self::Exec(command.trim().to_string()));
+        }
+        if let Some(name) = spec.strip_prefix(PRESET_PREFIX) {
+            return Ok(Self::Preset(name.parse()?));
+        }
+        if let Some(command) = spec.strip_prefix(COMMAND_PREFIX) {
+            split_command(command)?;
+            return Ok(Self::Command(command.trim().to_string()));
+        }
+        match spec.strip_prefix(DISCOVER_PREFIX) {
+            Some(pattern) => {
+                if !pattern.starts_with('/') {
+                    return Err(Error::Config(format!(
+                        "Discovery pattern must be an absolute path: {}",
+                        pattern
+                    )));
+                }
+                Ok(Self::Discover(pattern.to_string()))
+            }
+            None => Ok(Self::Path(PathBuf::from(spec))),
+        }
+    }
+
+    /// Resolve the socket path to connect to
+    ///
+    /// This may block (running a command), so callers on the runtime
+    /// should resolve dynamic targets on a blocking thread.
+    pub fn resolve(&self) -> Result<PathBuf> {
+        match self {
+            Self::Path(path) => Ok(path.clone()),
+            Self::Discover(pattern) => discover(pattern).ok_or_else(|| {
+                Error::UpstreamNotAvailable(format!("No live agent socket matches {}", pattern))
+            }),
+            Self::Command(command) => command_path(command).map_err(|e| {
+                Error::UpstreamNotAvailable(format!("Cannot resolve upstream socket: {}", e))
+            }),
+            Self::Preset(preset) => preset.resolve(),
+            Self::Exec(command) => Err(Error::UpstreamNotAvailable(format!(
+                "Upstream '{}{}' has no socket",
+                EXEC_PREFIX, command
+            ))),
+        }
+    }
+
+    /// Check if the socket currently exists
+    ///
+    /// An `exec:` upstream has no socket and is started on demand.
+    pub fn exists(&self) -> bool {
+        match self {
+            Self::Exec(_) => true,
+            target => target.resolve().is_ok_and(|path| path.exists()),
+        }
+    }
+}
+
+impl fmt::Display for UpstreamTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Path(path) => write!(f, "{}", path.display()),
+            Self::Discover(pattern) => write!(f, "{}{}", DISCOVER_PREFIX, pattern),
+            Self::Command(command) => write!(f, "{}{}", COMMAND_PREFIX, command),
+            Self::Preset(preset) => write!(f, "{}{}", PRESET_PREFIX, preset),
+            Self::Exec(command) => write!(f, "{}{}", EXEC_PREFIX, command),
+        }
+    }
+}
+
+impl From<PathBuf> for UpstreamTarget {
+    fn from(path: PathBuf) -> Self {
+        Self::Path(path)
+    }
+}
+
+impl From<&Path> for UpstreamTarget {
+    fn from(path: &Path) -> Self {
+        Self::Path(path.to_path_buf())
+    }
+}
+
+impl From<&PathBuf> for UpstreamTarget {
+    fn from(path: &PathBuf) -> Self {
+        Self::Path(path.clone())
+    }
+}
+
+/// Find the newest live agent socket matching a glob pattern
+///
+/// Only sockets owned by the current user that accept connections are
+/// considered; stale sockets of closed SSH sessions are skipped.
+fn discover(pattern: &str) -> Option<PathBuf> {
+    let uid = unsafe { libc::getuid() };
+    let mut newest: Option<(SystemTime, PathBuf)> = None;
+    for path in glob_paths(Path::new(pattern)) {
+        let Ok(meta) = std::fs::symlink_metadata(&path) else {
+            continue;
+        };
+        if !meta.file_type().is_socket() || meta.uid() != uid {
+            continue;
+        }
+        let modified = meta.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+        if newest.as_ref().is_some_and(|(time, _)| *time >= modified) {
+            continue;
+        }
+        if std::os::unix::net::UnixStream::connect(&path).is_err() {
+            continue;
+        }
+        newest = Some((modified, path));
+    }
+    newest.map(|(_, path)| path)
+}
+
+/// Expand a glob pattern (wildcards allowed in any component) to existing paths
+fn glob_paths(pattern: &Path) -> Vec<PathBuf> {
+    let mut paths = vec![PathBuf::from("/")];
+    for component in pattern.components() {
+        let name = match component {
+            Component::RootDir => continue,
+            Component::Normal(name) => name.to_string_lossy(),
+            _ => return Vec::new(),
+        };
+        if !name.contains(['*', '?', '[', '{']) {
+            paths = paths.into_iter().map(|p| p.join(name.as_ref())).collect();
+            continue;
+        }
+        let Ok(matcher) = GlobBuilder::new(&name)
+            .literal_separator(true)
+            .build()
+            .map(|g| g.compile_matcher())
+        else {
+            return Vec::new();
+        };
+        paths = paths
+            .iter()
+            .filter_map(|dir| std::fs::read_dir(dir).ok())
+            .flatten()
+            .flatten()
+            .filter(|entry| matcher.is_match(entry.file_name()))
+            .map(|entry| entry.path())
+            .collect();
+    }
+    paths
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            UpstreamTarget::parse("/tmp/agent.sock").unwrap(),
+            UpstreamTarget::Path(PathBuf::from("/tmp/agent.sock"))
+        );
+        let target = UpstreamTarget::parse("discover:/tmp/ssh-*/agent.*").unwrap();
+        assert_eq!(
+            target,
+            UpstreamTarget::Discover("/tmp/ssh-*/agent.*".to_string())
+        );
+        assert_eq!(target.to_string(), "discover:/tmp/ssh-*/agent.*");
+        assert!(UpstreamTarget::parse("discover:ssh-*/agent.*").is_err());
+
+        let target =
+            UpstreamTarget::parse("command: gpgconf --list-dirs agent-ssh-socket").unwrap();
+        assert_eq!(
+            target,
+            UpstreamTarget::Command("gpgconf --list-dirs agent-ssh-socket".to_string())
+        );
+        assert_eq!(
+            target.to_string(),
+            "command:gpgconf --list-dirs agent-ssh-socket"
+        );
+        assert!(UpstreamTarget::parse("command:").is_err());
+
+        let target = UpstreamTarget::parse("preset:1password").unwrap();
+        assert_eq!(target, UpstreamTarget::Preset(Preset::OnePassword));
+        assert_eq!(target.to_string(), "preset:1password");
+        assert!(UpstreamTarget::parse("preset:unknown").is_err());
+
+        let target =
+            UpstreamTarget::parse("exec:ssh bastion -- socat - UNIX-CONNECT:/run/agent.sock")
+                .unwrap();
+        assert_eq!(
+            target,
+            UpstreamTarget::Exec("ssh bastion -- socat - UNIX-CONNECT:/run/agent.sock".to_string())
+        );
+        assert!(target.exists());
+        assert!(UpstreamTarget::parse("exec:").is_err());
+    }
+
+    #[test]
+    fn test_resolve_command() {
+        let target = UpstreamTarget::parse("command:echo /tmp/agent.sock").unwrap();
+        assert_eq!(target.resolve().unwrap(), PathBuf::from("/tmp/agent.sock"));
+
+        let target = UpstreamTarget::parse("command:false").unwrap();
+        assert!(matches!(
+            target.resolve(),
+            Err(Error::UpstreamNotAvailable(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_discover_newest_live_socket() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let pattern = format!("{}/ssh-*/agent.*", dir.path().display());
+        let target = UpstreamTarget::parse(&format!("discover:{}", pattern)).unwrap();
+        assert!(target.resolve().is_err());
+
+        let bind = |session: &str| {
+            let session_dir = dir.path().join(session);
+            std::fs::create_dir(&session_dir).unwrap();
+            let path = session_dir.join("agent.1");
+            let listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+            (path, listener)
+        };
+        let (old, _old_listener) = bind("ssh-old");
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        let (new, new_listener) = bind("ssh-new");
+        // Not an agent socket
+        std::fs::write(dir.path().join("ssh-new/agent.2"), "").unwrap();
+
+        assert_eq!(target.resolve().unwrap(), new);
+
+        // A socket nobody listens on any more is skipped
+        drop(new_listener);
+        assert_eq!(target.resolve().unwrap(), old);
+    }
+}
@@ -3,12 +3,19 @@
 //! This module handles the connection to the upstream SSH agent,
 //! typically accessed via the SSH_AUTH_SOCK environment variable.
 
+use super::stream::{ProcessStream, UpstreamStream};
+use super::target::UpstreamTarget;
 use crate::error::{Error, Result};
-use crate::protocol::{AgentCodec, AgentMessage};
+use crate::protocol::{AgentCodec, AgentMessage, MessageType};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
 use std::path::{Path, PathBuf};
-use std::time::Duration;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU8, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
 use tokio::net::UnixStream;
-use tracing::{debug, trace};
+use tracing::{debug, info, trace, warn};
 
 /// Default connection timeout for upstream agent
 const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
@@ -16,10 +23,160 @@ const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
 /// Default request timeout for upstream agent (send + receive)
 const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
 
+/// Availability states of an upstream (as last seen when connecting)
+const AVAILABILITY_UNKNOWN: u8 = 0;
+const AVAILABILITY_UP: u8 = 1;
+const AVAILABILITY_DOWN: u8 = 2;
+
+/// Condition under which a request fails over to the next agent of a chain
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FailoverCondition {
+    /// The agent socket cannot be connected to
+    Connect,
+    /// The agent does not answer within the request timeout
+    Timeout,
+    /// The agent lists no identities (e.g., a locked password manager)
+    Empty,
+}
+
+impl FailoverCondition {
+    /// All conditions (the default for a failover chain)
+    pub const ALL: [FailoverCondition; 3] = [
+        FailoverCondition::Connect,
+        FailoverCondition::Timeout,
+        FailoverCondition::Empty,
+    ];
+
+    /// Get the configuration name
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FailoverCondition::Connect => "connect",
+            FailoverCondition::Timeout => "timeout",
+            FailoverCondition::Empty => "empty",
+        }
+    }
+}
+
+impl fmt::Display for FailoverCondition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Settings for pooling connections to the upstream agent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolConfig {
+    /// Maximum number of idle connections kept open
+    pub max_size: usize,
+    /// Idle connections unused for longer than this are closed
+    pub idle_timeout: Duration,
+}
+
+impl Default for PoolConfig {
+    /// Pooling disabled
+    fn default() -> Self {
+        Self {
+            max_size: 0,
+            idle_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Connection pool statistics
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PoolStats {
+    /// Idle connections currently in the pool
+    pub idle: usize,
+    /// Connections opened for pooled requests
+    pub created: u64,
+    /// Pooled requests served by an existing connection
+    pub reused: u64,
+    /// Connections closed because they were stale, expired or over capacity
+    pub discarded: u64,
+}
+
+/// Pool of idle upstream connections for stateless requests
+struct Pool {
+    config: PoolConfig,
+    idle: Mutex<Vec<(UpstreamConnection, Instant)>>,
+    created: AtomicU64,
+    reused: AtomicU64,
+    discarded: AtomicU64,
+}
+
+impl Pool {
+    fn new(config: PoolConfig) -> Self {
+        Self {
+            config,
+            idle: Mutex::new(Vec::new()),
+            created: AtomicU64::new(0),
+            reused: AtomicU64::new(0),
+            discarded: AtomicU64::new(0),
+        }
+    }
+
+    /// Take the most recently used healthy connection
+    fn checkout(&self) -> Option<UpstreamConnection> {
+        let mut idle = self.idle.lock().unwrap_or_else(|e| e.into_inner());
+        while let Some((mut conn, since)) = idle.pop() {
+            if since.elapsed() <= self.config.idle_timeout && conn.is_healthy() {
+                return Some(conn);
+            }
+            self.discarded.fetch_add(1, Ordering::Relaxed);
+        }
+        None
+    }
+
+    /// Return a connection to the pool (dropped if the pool is full)
+    fn checkin(&self, conn: UpstreamConnection) {
+        let mut idle = self.idle.lock().unwrap_or_else(|e| e.into_inner());
+        if idle.len() < self.config.max_size {
+            idle.push((conn, Instant::now()));
+        } else {
+            self.discarded.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Close idle connections that expired or were closed by upstream
+    fn prune(&self) {
+        let mut idle = self.idle.lock().unwrap_or_else(|e| e.into_inner());
+        let before = idle.len();
+        idle.retain_mut(|(conn, since)| {
+            since.elapsed() <= self.config.idle_timeout && conn.is_healthy()
+        });
+        self.discarded
+            .fetch_add((before - idle.len()) as u64, Ordering::Relaxed);
+    }
+
+    fn stats(&self) -> PoolStats {
+        PoolStats {
+            idle: self.idle.lock().unwrap_or_else(|e| e.into_inner()).len(),
+            created: self.created.load(Ordering::Relaxed),
+            reused: self.reused.load(Ordering::Relaxed),
+            discarded: self.discarded.load(Ordering::Relaxed),
+        }
+    }
+}
+
 /// Connection to an upstream SSH agent
 pub struct Upstream {
-    /// Path to the upstream agent socket
-    socket_path: PathBuf,
+    /// Location of the upstream agent socket
+    target: UpstreamTarget,
+    /// Name used in logs and `upstream=` filters (defaults to the target)
+    name: String,
+    /// Agents to fail over to, in order (name, location)
+    fallbacks: Vec<(String, UpstreamTarget)>,
+    /// Last resolved socket path of dynamic targets (by chain member)
+    resolved: Mutex<HashMap<usize, PathBuf>>,
+    /// Conditions that trigger failing over to the next agent
+    failover_on: Vec<FailoverCondition>,
+    /// Time to wait for a response to a request
+    request_timeout: Duration,
+    /// Optional pool of connections for stateless requests
+    pool: Option<Pool>,
+    /// Whether the last connection attempt succeeded (for logging changes)
+    availability: AtomicU8,
 }
 
 impl Upstream {
@@ -28,8 +185,71 @@ impl Upstream {
     /// # Arguments
     /// * `socket_path` - Path to the upstream agent socket
     pub fn new<P: AsRef<Path>>(socket_path: P) -> Self {
+        Self::from_target(socket_path.as_ref().into())
+    }
+
+    /// Create a connection manager for an agent at a (possibly dynamic) location
+    pub fn from_target(target: UpstreamTarget) -> Self {
         Self {
-            socket_path: socket_path.as_ref().to_path_buf(),
+            name: target.to_string(),
+            target,
+            fallbacks: Vec::new(),
+            resolved: Mutex::new(HashMap::new()),
+            failover_on: FailoverCondition::ALL.to_vec(),
+            request_timeout: DEFAULT_REQUEST_TIMEOUT,
+            pool: None,
+            availability: AtomicU8::new(AVAILABILITY_UNKNOWN),
+        }
+    }
+
+    /// Add an agent to fail over to (tried in the order added)
+    pub fn with_fallback(
+        mut self,
+        name: impl Into<String>,
+        target: impl Into<UpstreamTarget>,
+    ) -> Self {
+        self.fallbacks.push((name.into(), target.into()));
+        self
+    }
+
+    /// Set the conditions that trigger failing over (default: all)
+    pub fn with_failover_on(mut self, conditions: Vec<FailoverCondition>) -> Self {
+        self.failover_on = conditions;
+        self
+    }
+
+    /// Set the time to wait for a response to a request
+    pub fn with_request_timeout(mut self, timeout: Duration) -> Self {
+        self.request_timeout = timeout;
+        self
+    }
+
+    /// Set the name used in logs and `upstream=` filters
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    /// Enable pooling of connections for stateless requests
+    pub fn with_pool(mut self, config: PoolConfig) -> Self {
+        self.pool = (config.max_size > 0).then(|| Pool::new(config));
+        self
+    }
+
+    /// Check if connection pooling is enabled
+    pub fn has_pool(&self) -> bool {
+        self.pool.is_some()
+    }
+
+    /// Get connection pool statistics (None if pooling is disabled)
+    pub fn pool_stats(&self) -> Option<PoolStats> {
+        self.pool.as_ref().map(Pool::stats)
+    }
+
+    /// Close pooled connections that are idle for too long or closed by upstream
+    pub fn prune_pool(&self) {
+        if let Some(pool) = &self.pool {
+            pool.prune();
         }
     }
 
@@ -51,53 +271,337 @@ impl Upstream {
         Ok(Self::new(path))
     }
 
-    /// Get the socket path
-    pub fn socket_path(&self) -> &Path {
-        &self.socket_path
+    /// Get the location of the agent socket
+    pub fn target(&self) -> &UpstreamTarget {
+        &self.target
+    }
+
+    /// Get the upstream name
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Get the name and location of an agent of the failover chain
+    fn member(&self, member: usize) -> (&str, &UpstreamTarget) {
+        match member {
+            0 => (&self.name, &self.target),
+            n => {
+                let (name, target) = &self.fallbacks[n - 1];
+                (name, target)
+            }
+        }
+    }
+
+    /// Check if a condition met by an agent of the chain triggers failover
+    fn fails_over_on(&self, condition: FailoverCondition, member: usize) -> bool {
+        member < self.fallbacks.len() && self.failover_on.contains(&condition)
+    }
+
+    /// Check if a response triggers failover (empty identity list)
+    fn response_fails_over(&self, response: &AgentMessage, member: usize) -> bool {
+        self.fails_over_on(FailoverCondition::Empty, member)
+            && response.msg_type == MessageType::IdentitiesAnswer
+            && response.parse_identities().is_ok_and(|ids| ids.is_empty())
+    }
+
+    fn log_failover(&self, member: usize, condition: FailoverCondition, detail: &dyn fmt::Display) {
+        warn!(
+            upstream = %self.name,
+            from = self.member(member).0,
+            to = self.member(member + 1).0,
+            reason = %condition,
+            detail = %detail,
+            "Failing over to next upstream agent"
+        );
     }
 
     /// Connect to the upstream agent with timeout
+    ///
+    /// Along a failover chain, the first agent that accepts the connection
+    /// is used (if connect errors trigger failover).
     pub async fn connect(&self) -> Result<UpstreamConnection> {
-        let stream = tokio::time::timeout(
-            DEFAULT_CONNECT_TIMEOUT,
-            UnixStream::connect(&self.socket_path),
-        )
+        self.connect_from(0).await
+    }
+
+    /// Connect to the first available agent of the chain from `member` on
+    ///
+    /// The upstream is probed on every attempt, so an agent that starts
+    /// after the proxy is picked up by the next request.
+    async fn connect_from(&self, mut member: usize) -> Result<UpstreamConnection> {
+        let result = loop {
+            match self.connect_member(member).await {
+                Ok(conn) => break Ok(conn),
+                Err(e) if self.fails_over_on(FailoverCondition::Connect, member) => {
+                    self.log_failover(member, FailoverCondition::Connect, &e);
+                    member += 1;
+                }
+                Err(e) => break Err(e),
+            }
+        };
+
+        let state = if result.is_ok() {
+            AVAILABILITY_UP
+        } else {
+            AVAILABILITY_DOWN
+        };
+        match (self.availability.swap(state, Ordering::Relaxed), &result) {
+            (AVAILABILITY_DOWN, Ok(_)) => {
+                info!(upstream = %self.name, "Upstream agent is available");
+            }
+            (previous, Err(e)) if previous != AVAILABILITY_DOWN => {
+                warn!(
+                    upstream = %self.name,
+                    error = %e,
+                    "Upstream agent not available; waiting for it to appear"
+                );
+            }
+            _ => {}
+        }
+        result
+    }
+
+    /// Connect to one agent of the chain with timeout
+    ///
+    /// A dynamic location (`discover:`, `command:`, `preset:`) is resolved on
+    /// first use and re-resolved when connecting to the last resolved socket
+    /// fails. An `exec:` upstream spawns its process for every connection.
+    async fn connect_member(&self, member: usize) -> Result<UpstreamConnection> {
+        let (name, target) = self.member(member);
+        let stream = match target {
+            UpstreamTarget::Path(path) => connect_socket(path).await?,
+            UpstreamTarget::Exec(command) => {
+                let process = ProcessStream::spawn(command)?;
+                trace!(
+                    upstream = name,
+                    pid = process.id(),
+                    "Started upstream command"
+                );
+                UpstreamStream::Process(process)
+            }
+            target => {
+                let cached = self
+                    .resolved
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .get(&member)
+                    .cloned();
+                let failed = match cached {
+                    Some(path) => match connect_socket(&path).await {
+                        Ok(stream) => return Ok(self.connection(stream, member)),
+                        Err(e) => Some((path, e)),
+                    },
+                    None => None,
+                };
+
+                // Resolving may run a command, keep it off the runtime
+                let resolving = target.clone();
+                let path = tokio::task::spawn_blocking(move || resolving.resolve())
+                    .await
+                    .map_err(|e| Error::UpstreamNotAvailable(e.to_string()))??;
+                if let Some((cached, e)) = failed
+                    && cached == path
+                {
+                    return Err(e);
+                }
+                let stream = connect_socket(&path).await?;
+                info!(
+                    upstream = name,
+                    socket_path = %path.display(),
+                    "Resolved upstream agent socket"
+                );
+                self.resolved
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .insert(member, path);
+                stream
+            }
+        };
+        Ok(self.connection(stream, member))
+    }
+
+    fn connection(&self, stream: UpstreamStream, member: usize) -> UpstreamConnection {
+        UpstreamConnection {
+            stream,
+            member,
+            timeout: self.request_timeout,
+            requests: 0,
+        }
+    }
+
+    /// Send a request over a client's dedicated connection
+    ///
+    /// The connection in `slot` is reused, or opened if there is none or
+    /// upstream has closed it; `replay` is sent first on a new connection.
+    /// A request that timed out is not resent. Requests a preset agent is
+    /// known to refuse are answered with FAILURE. Along a failover chain, the
+    /// next agent is tried when a failover condition is met, and the
+    /// connection to the agent that answered is kept in `slot` until the
+    /// client lists keys again, which starts over from the first agent.
+    pub async fn send(
+        &self,
+        slot: &mut Option<UpstreamConnection>,
+        replay: &[AgentMessage],
+        request: &AgentMessage,
+    ) -> Result<AgentMessage> {
+        let mut member = 0;
+        if request.msg_type == MessageType::RequestIdentities
+            && slot.as_ref().is_some_and(|c| c.member > 0)
+        {
+            *slot = None;
+        }
+        if let UpstreamTarget::Preset(preset) = self.member(slot.as_ref().map_or(0, |c| c.member)).1
+            && preset.refuses(request.msg_type)
+        {
+            debug!(
+                upstream = %self.name,
+                preset = %preset,
+                msg_type = request.msg_type.as_str(),
+                "Request not supported by upstream agent"
+            );
+            return Ok(AgentMessage::failure());
+        }
+        if let Some(mut conn) = slot.take() {
+            member = conn.member;
+            match conn.send_receive(request).await {
+                Ok(response) if self.response_fails_over(&response, member) => {
+                    self.log_failover(member, FailoverCondition::Empty, &"no identities");
+                    member += 1;
+                }
+                Ok(response) => {
+                    *slot = Some(conn);
+                    return Ok(response);
+                }
+                Err(e @ Error::UpstreamNotAvailable(_)) => {
+                    // Timed out: the request may still be processed, do not
+                    // resend unless failing over to another agent
+                    if !self.fails_over_on(FailoverCondition::Timeout, member) {
+                        return Err(e);
+                    }
+                    self.log_failover(member, FailoverCondition::Timeout, &e);
+                    member += 1;
+                }
+                Err(e) => {
+                    debug!(
+                        upstream = self.member(member).0,
+                        error = %e,
+                        "Upstream connection lost, reconnecting"
+                    );
+                }
+            }
+        }
+
+        loop {
+            let mut conn = self.connect_from(member).await?;
+            member = conn.member;
+            for message in replay {
+                let response = conn.send_receive(message).await?;
+                if response.msg_type != MessageType::Success {
+                    debug!(
+                        upstream = self.member(member).0,
+                        msg_type = ?response.msg_type,
+                        "Upstream refused replayed request"
+                    );
+                }
+            }
+            match conn.send_receive(request).await {
+                Ok(response) if self.response_fails_over(&response, member) => {
+                    self.log_failover(member, FailoverCondition::Empty, &"no identities");
+                }
+                Ok(response) => {
+                    *slot = Some(conn);
+                    return Ok(response);
+                }
+                Err(e @ Error::UpstreamNotAvailable(_))
+                    if self.fails_over_on(FailoverCondition::Timeout, member) =>
+                {
+                    self.log_failover(member, FailoverCondition::Timeout, &e);
+                }
+                Err(e) => return Err(e),
+            }
+            member += 1;
+        }
+    }
+
+    /// Send a stateless request over a pooled connection
+    ///
+    /// Falls back to a one-off connection if pooling is disabled. A pooled
+    /// connection that turns out to be closed is discarded and the request
+    /// is sent over a new connection.
+    pub async fn send_pooled(&self, msg: &AgentMessage) -> Result<AgentMessage> {
+        let Some(pool) = &self.pool else {
+            return self.send(&mut None, &[], msg).await;
+        };
+
+        let mut slot = pool.checkout();
+        let checked_out = slot.is_some();
+        let result = self.send(&mut slot, &[], msg).await;
+        match slot {
+            Some(conn) if conn.requests > 1 => {
+                pool.reused.fetch_add(1, Ordering::Relaxed);
+                pool.checkin(conn);
+            }
+            Some(conn) => {
+                if checked_out {
+                    debug!("Discarded stale pooled upstream connection");
+                    pool.discarded.fetch_add(1, Ordering::Relaxed);
+                }
+                pool.created.fetch_add(1, Ordering::Relaxed);
+                pool.checkin(conn);
+            }
+            None if checked_out => {
+                pool.discarded.fetch_add(1, Ordering::Relaxed);
+            }
+            None => {}
+        }
+        result
+    }
+}
+
+/// Connect to an agent socket with timeout
+async fn connect_socket(socket_path: &Path) -> Result<UpstreamStream> {
+    let stream = tokio::time::timeout(DEFAULT_CONNECT_TIMEOUT, UnixStream::connect(socket_path))
         .await
         .map_err(|_| {
             Error::UpstreamNotAvailable(format!(
                 "Connection to upstream agent at {} timed out after {:?}",
-                self.socket_path.display(),
+                socket_path.display(),
                 DEFAULT_CONNECT_TIMEOUT
             ))
         })?
         .map_err(|e| {
             Error::UpstreamNotAvailable(format!(
                 "Failed to connect to upstream agent at {}: {}",
-                self.socket_path.display(),
+                socket_path.display(),
                 e
             ))
         })?;
 
-        trace!(socket_path = %self.socket_path.display(), "Connected to upstream agent");
-        Ok(UpstreamConnection { stream })
-    }
+    trace!(socket_path = %socket_path.display(), "Connected to upstream agent");
+    Ok(UpstreamStream::Unix(stream))
 }
 
 /// An active connection to the upstream agent
 #[derive(Debug)]
 pub struct UpstreamConnection {
-    stream: UnixStream,
+    stream: UpstreamStream,
+    /// Index of the connected agent in the failover chain
+    member: usize,
+    /// Time to wait for a response
+    timeout: Duration,
+    /// Number of requests sent over this connection
+    requests: u64,
 }
 
 impl UpstreamConnection {
     /// Send a message to the upstream agent and receive the response
     pub async fn send_receive(&mut self, msg: &AgentMessage) -> Result<AgentMessage> {
-        tokio::time::timeout(DEFAULT_REQUEST_TIMEOUT, self.send_receive_inner(msg))
+        self.requests += 1;
+        tokio::time::timeout(self.timeout, self.send_receive_inner(msg))
             .await
             .map_err(|_| {
                 Error::UpstreamNotAvailable(format!(
                     "Request to upstream agent timed out after {:?}",
-                    DEFAULT_REQUEST_TIMEOUT
+                    self.timeout
                 ))
             })?
     }
@@ -106,13 +610,11 @@ impl UpstreamConnection {
     async fn send_receive_inner(&mut self, msg: &AgentMessage) -> Result<AgentMessage> {
         trace!(msg_type = ?msg.msg_type, "Sending message to upstream");
 
-        let (mut reader, mut writer) = self.stream.split();
-
         // Send the message
-        AgentCodec::write(&mut writer, msg).await?;
+        AgentCodec::write(&mut self.stream, msg).await?;
 
         // Read the response
-        let response = AgentCodec::read(&mut reader).await?.ok_or_else(|| {
+        let response = AgentCodec::read(&mut self.stream).await?.ok_or_else(|| {
             Error::Protocol("Upstream agent closed connection unexpectedly".to_string())
         })?;
 
@@ -120,13 +622,18 @@ impl UpstreamConnection {
         Ok(response)
     }
 
-    /// Get mutable access to the underlying stream for split operations
-    pub fn stream_mut(&mut self) -> &mut UnixStream {
+    /// Check that the connection is idle and still open
+    pub fn is_healthy(&mut self) -> bool {
+        self.stream.is_healthy()
+    }
+
+    /// Get mutable access to the underlying stream
+    pub fn stream_mut(&mut self) -> &mut UpstreamStream {
         &mut self.stream
     }
 
     /// Consume self and return the underlying stream
-    pub fn into_stream(self) -> UnixStream {
+    pub fn into_stream(self) -> UpstreamStream {
         self.stream
     }
 }
@@ -138,7 +645,11 @@ mod tests {
     #[test]
     fn test_upstream_new() {
         let upstream = Upstream::new("/tmp/test.sock");
-        assert_eq!(upstream.socket_path(), Path::new("/tmp/test.sock"));
+        assert_eq!(
+            upstream.target(),
+            &UpstreamTarget::Path(PathBuf::from("/tmp/test.sock"))
+        );
+        assert_eq!(upstream.name(), "/tmp/test.sock");
     }
 
     #[tokio::test]
@@ -160,6 +671,287 @@ mod tests {
         assert!(err.contains("Failed to connect") || err.contains("upstream"));
     }
 
+    /// Start an agent answering every request with SUCCESS
+    async fn start_success_agent(path: &Path) {
+        let listener = tokio::net::UnixListener::bind(path).unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let (mut reader, mut writer) = stream.split();
+                    while let Ok(Some(_)) = AgentCodec::read(&mut reader).await {
+                        let response = AgentMessage::success();
+                        if AgentCodec::write(&mut writer, &response).await.is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+    }
+
+    /// Start an agent listing `count` identities (and never answering if None)
+    async fn start_listing_agent(path: &Path, count: Option<usize>) {
+        let listener = tokio::net::UnixListener::bind(path).unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let (mut reader, mut writer) = stream.split();
+                    while let Ok(Some(_)) = AgentCodec::read(&mut reader).await {
+                        let Some(count) = count else { continue };
+                        let identities: Vec<_> = (0..count)
+                            .map(|i| {
+                                crate::protocol::Identity::new(
+                                    bytes::Bytes::from(vec![i as u8]),
+                                    format!("key{}", i),
+                                )
+                            })
+                            .collect();
+                        let response = AgentMessage::build_identities_answer(&identities);
+                        if AgentCodec::write(&mut writer, &response).await.is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+    }
+
+    fn list_request() -> AgentMessage {
+        AgentMessage::new(MessageType::RequestIdentities, Default::default())
+    }
+
+    async fn listed(upstream: &Upstream, slot: &mut Option<UpstreamConnection>) -> usize {
+        let response = upstream.send(slot, &[], &list_request()).await.unwrap();
+        response.parse_identities().unwrap().len()
+    }
+
+    #[tokio::test]
+    async fn test_failover_on_connect_error() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let fallback = dir.path().join("fallback.sock");
+        start_listing_agent(&fallback, Some(2)).await;
+
+        let upstream =
+            Upstream::new(dir.path().join("missing.sock")).with_fallback("fallback", &fallback);
+        let mut slot = None;
+        assert_eq!(listed(&upstream, &mut slot).await, 2);
+        assert_eq!(slot.as_ref().map(|c| c.member), Some(1));
+
+        // Without the condition, the connect error is returned
+        let upstream = Upstream::new(dir.path().join("missing.sock"))
+            .with_fallback("fallback", &fallback)
+            .with_failover_on(vec![FailoverCondition::Empty]);
+        assert!(upstream.connect().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_failover_on_empty_identities() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let primary = dir.path().join("primary.sock");
+        let fallback = dir.path().join("fallback.sock");
+        start_listing_agent(&primary, Some(0)).await;
+        start_listing_agent(&fallback, Some(1)).await;
+
+        let upstream = Upstream::new(&primary).with_fallback("fallback", &fallback);
+        let mut slot = None;
+        assert_eq!(listed(&upstream, &mut slot).await, 1);
+        // The client stays with the agent that answered
+        assert_eq!(slot.as_ref().map(|c| c.member), Some(1));
+
+        let upstream = Upstream::new(&primary)
+            .with_fallback("fallback", &fallback)
+            .with_failover_on(vec![FailoverCondition::Connect]);
+        assert_eq!(listed(&upstream, &mut None).await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_failover_retries_primary_when_listing() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let primary = dir.path().join("primary.sock");
+        let fallback = dir.path().join("fallback.sock");
+        start_success_agent(&fallback).await;
+
+        let upstream = Upstream::new(&primary).with_fallback("fallback", &fallback);
+        let mut slot = None;
+        let response = upstream
+            .send(&mut slot, &[], &AgentMessage::success())
+            .await
+            .unwrap();
+        assert_eq!(response.msg_type, MessageType::Success);
+        assert_eq!(slot.as_ref().map(|c| c.member), Some(1));
+
+        // Other requests stay with the fallback, listing goes back to the
+        // primary once it is up
+        start_listing_agent(&primary, Some(2)).await;
+        upstream
+            .send(&mut slot, &[], &AgentMessage::success())
+            .await
+            .unwrap();
+        assert_eq!(slot.as_ref().map(|c| c.member), Some(1));
+        assert_eq!(listed(&upstream, &mut slot).await, 2);
+        assert_eq!(slot.as_ref().map(|c| c.member), Some(0));
+    }
+
+    #[tokio::test]
+    async fn test_failover_on_timeout() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let primary = dir.path().join("primary.sock");
+        let fallback = dir.path().join("fallback.sock");
+        start_listing_agent(&primary, None).await;
+        start_listing_agent(&fallback, Some(1)).await;
+
+        let upstream = Upstream::new(&primary)
+            .with_fallback("fallback", &fallback)
+            .with_request_timeout(Duration::from_millis(50));
+        assert_eq!(listed(&upstream, &mut None).await, 1);
+
+        // The last agent of the chain has nothing to fail over to
+        let upstream = Upstream::new(&primary).with_request_timeout(Duration::from_millis(50));
+        assert!(matches!(
+            upstream.send(&mut None, &[], &list_request()).await,
+            Err(Error::UpstreamNotAvailable(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_discover_re_resolves_on_connect_failure() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let first = dir.path().join("ssh-a/agent.1");
+        let second = dir.path().join("ssh-b/agent.1");
+        std::fs::create_dir(first.parent().unwrap()).unwrap();
+        std::fs::create_dir(second.parent().unwrap()).unwrap();
+
+        let target =
+            UpstreamTarget::parse(&format!("discover:{}/ssh-*/agent.*", dir.path().display()))
+                .unwrap();
+        let upstream = Upstream::from_target(target);
+        assert!(upstream.connect().await.is_err());
+
+        start_listing_agent(&first, Some(1)).await;
+        assert_eq!(listed(&upstream, &mut None).await, 1);
+
+        // A newer socket does not replace one that still works
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        start_listing_agent(&second, Some(2)).await;
+        assert_eq!(listed(&upstream, &mut None).await, 1);
+
+        // Once the resolved socket is gone, the newest one is picked up
+        std::fs::remove_file(&first).unwrap();
+        assert_eq!(listed(&upstream, &mut None).await, 2);
+    }
+
+    #[tokio::test]
+    async fn test_command_re_evaluated_on_connect_failure() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let first = dir.path().join("first.sock");
+        let second = dir.path().join("second.sock");
+        let current = dir.path().join("current");
+        start_listing_agent(&first, Some(1)).await;
+        start_listing_agent(&second, Some(2)).await;
+        std::fs::write(&current, first.display().to_string()).unwrap();
+
+        let target = UpstreamTarget::parse(&format!("command:cat {}", current.display())).unwrap();
+        let upstream = Upstream::from_target(target);
+        assert_eq!(listed(&upstream, &mut None).await, 1);
+
+        std::fs::write(&current, second.display().to_string()).unwrap();
+        assert_eq!(listed(&upstream, &mut None).await, 1);
+
+        std::fs::remove_file(&first).unwrap();
+        assert_eq!(listed(&upstream, &mut None).await, 2);
+    }
+
+    #[tokio::test]
+    async fn test_preset_refuses_unsupported_requests() {
+        let upstream =
+            Upstream::from_target(UpstreamTarget::Preset(crate::agent::Preset::OnePassword));
+        assert_eq!(upstream.name(), "preset:1password");
+        let request = AgentMessage::new(MessageType::AddIdentity, Default::default());
+        let response = upstream.send(&mut None, &[], &request).await.unwrap();
+        assert_eq!(response.msg_type, MessageType::Failure);
+    }
+
+    #[tokio::test]
+    async fn test_exec_upstream() {
+        // cat answers every message with itself
+        let upstream = Upstream::from_target(UpstreamTarget::parse("exec:cat").unwrap());
+        let mut slot = None;
+        for _ in 0..2 {
+            let response = upstream
+                .send(&mut slot, &[], &AgentMessage::success())
+                .await
+                .unwrap();
+            assert_eq!(response.msg_type, MessageType::Success);
+        }
+        // The process is kept for the client connection
+        assert_eq!(slot.as_ref().map(|c| c.requests), Some(2));
+
+        let upstream = Upstream::from_target(UpstreamTarget::parse("exec:true").unwrap());
+        assert!(
+            upstream
+                .send(&mut None, &[], &AgentMessage::success())
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_pool_reuses_connections() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("agent.sock");
+        start_success_agent(&path).await;
+
+        let upstream = Upstream::new(&path).with_pool(PoolConfig {
+            max_size: 2,
+            idle_timeout: Duration::from_secs(60),
+        });
+        let request = AgentMessage::new(
+            crate::protocol::MessageType::RequestIdentities,
+            Default::default(),
+        );
+        for _ in 0..3 {
+            upstream.send_pooled(&request).await.unwrap();
+        }
+
+        let stats = upstream.pool_stats().unwrap();
+        assert_eq!(stats.created, 1);
+        assert_eq!(stats.reused, 2);
+        assert_eq!(stats.idle, 1);
+    }
+
+    #[tokio::test]
+    async fn test_pool_discards_expired_connections() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("agent.sock");
+        start_success_agent(&path).await;
+
+        let upstream = Upstream::new(&path).with_pool(PoolConfig {
+            max_size: 2,
+            idle_timeout: Duration::ZERO,
+        });
+        let request = AgentMessage::new(
+            crate::protocol::MessageType::RequestIdentities,
+            Default::default(),
+        );
+        upstream.send_pooled(&request).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        upstream.prune_pool();
+
+        let stats = upstream.pool_stats().unwrap();
+        assert_eq!(stats.idle, 0);
+        assert_eq!(stats.discarded, 1);
+    }
+
+    #[test]
+    fn test_pool_disabled_by_zero_size() {
+        let upstream = Upstream::new("/tmp/test.sock").with_pool(PoolConfig {
+            max_size: 0,
+            idle_timeout: Duration::from_secs(60),
+        });
+        assert!(!upstream.has_pool());
+        assert!(upstream.pool_stats().is_none());
+    }
+
     #[tokio::test]
     async fn test_connect_not_a_socket() {
         // Attempt to connect to a regular file (not a socket)
@@ -5,11 +5,51 @@ use clap_complete::Shell;
 use clap_complete::engine::{ArgValueCompleter, CompletionCandidate};
 use std::path::PathBuf;
 
+use crate::agent::{AccessPolicy, PRESET_PREFIX, Preset, SocketMode};
+use crate::error::{Error, Result};
+
 /// Parsed socket configuration from CLI arguments
 #[derive(Debug, Clone, Default)]
 pub struct SocketSpec {
     pub path: PathBuf,
     pub filters: Vec<String>,
+    /// Socket-level feature flags (override the upstream group's flags)
+    pub access: AccessPolicy,
+    /// Socket mode (full, list-only, sign-only)
+    pub mode: Option<SocketMode>,
+    /// Lock only this socket on LOCK / UNLOCK
+    pub local_lock: bool,
+    /// Lock this socket after no successful sign for this duration
+    pub idle_lock: Option<String>,
+    /// File holding the passphrase with which UNLOCK lifts the idle lock
+    pub idle_unlock_file: Option<String>,
+    /// Ask the user before each signature
+    pub confirm: bool,
+    /// Program asking for confirmation instead of $SSH_ASKPASS
+    pub confirm_program: Option<String>,
+    /// Socket file permissions in octal
+    pub permissions: Option<String>,
+    /// Group owning the socket file
+    pub group: Option<String>,
+    /// Permissions of the socket's parent directory in octal
+    pub dir_permissions: Option<String>,
+    /// Allow permissions that let any user connect
+    pub force_permissions: bool,
+    /// Users allowed to connect besides the proxy's user and root
+    pub allowed_uids: Vec<u32>,
+    /// Groups whose members are allowed to connect
+    pub allowed_groups: Vec<String>,
+}
+
+impl SocketSpec {
+    /// Check if any socket file option (--permissions, --group,
+    /// --dir-permissions, --force-permissions) was given
+    pub fn has_file_options(&self) -> bool {
+        self.permissions.is_some()
+            || self.group.is_some()
+            || self.dir_permissions.is_some()
+            || self.force_permissions
+    }
 }
 
 /// Upstream group containing an upstream path and its associated sockets
@@ -17,6 +57,8 @@ pub struct SocketSpec {
 pub struct UpstreamGroup {
     pub path: PathBuf,
     pub sockets: Vec<SocketSpec>,
+    /// Upstream-level feature flags (inherited by sockets in this group)
+    pub access: AccessPolicy,
 }
 
 /// Arguments for the `run` command
@@ -44,6 +86,103 @@ pub struct RunArgs {
     #[arg(long, num_args = 1.., value_name = "PATH [ARGS...]", add = ArgValueCompleter::new(socket_completer))]
     pub socket: Vec<String>,
 
+    /// Socket mode for the preceding --socket [default: full]
+    ///
+    ///   full:      list filtered keys and sign with them
+    ///   list-only: list filtered keys, never sign
+    ///   sign-only: sign with explicitly requested keys, list nothing
+    #[arg(long, value_name = "MODE", action = clap::ArgAction::Append, value_parser = clap::builder::PossibleValuesParser::new(SocketMode::NAMES))]
+    pub mode: Vec<String>,
+
+    /// Lock only the preceding --socket on LOCK / UNLOCK (ssh-add -x / -X)
+    ///
+    /// The upstream agent is not locked. While locked, the socket lists no
+    /// keys and refuses signing until unlocked with the same passphrase.
+    #[arg(long, action = clap::ArgAction::Count)]
+    pub local_lock: u8,
+
+    /// Lock the preceding --socket after no successful sign for DURATION (e.g. 15m)
+    ///
+    /// The upstream agent is not locked. Resume by sending SIGUSR1 to the
+    /// proxy; ssh-add -X unlocks it only with --idle-unlock-file.
+    #[arg(long, value_name = "DURATION", action = clap::ArgAction::Append)]
+    pub idle_lock: Vec<String>,
+
+    /// File whose first line is the passphrase with which ssh-add -X resumes
+    /// the preceding --socket from its idle lock
+    ///
+    /// The file must not be accessible by other users.
+    #[arg(long, value_name = "PATH", action = clap::ArgAction::Append)]
+    pub idle_unlock_file: Vec<String>,
+
+    /// Ask before each signature on the preceding --socket (like ssh-add -c)
+    ///
+    /// Runs $SSH_ASKPASS (or --confirm-program) with the key, client and
+    /// destination; the request is signed only if it exits with 0.
+    #[arg(long, action = clap::ArgAction::Count)]
+    pub confirm: u8,
+
+    /// Program asking for confirmation on the preceding --socket (implies --confirm)
+    #[arg(long, value_name = "COMMAND", action = clap::ArgAction::Append)]
+    pub confirm_program: Vec<String>,
+
+    /// File permissions of the preceding --socket in octal [default: 0600]
+    ///
+    /// Permissions that let any user connect (e.g. 0666) are refused unless
+    /// --force-permissions is given.
+    #[arg(long, value_name = "MODE", action = clap::ArgAction::Append)]
+    pub permissions: Vec<String>,
+
+    /// Group owning the preceding --socket (name or gid)
+    #[arg(long, value_name = "GROUP", action = clap::ArgAction::Append)]
+    pub group: Vec<String>,
+
+    /// Permissions of the preceding --socket's parent directory in octal
+    #[arg(long, value_name = "MODE", action = clap::ArgAction::Append)]
+    pub dir_permissions: Vec<String>,
+
+    /// Allow --permissions that let any user connect to the preceding --socket
+    #[arg(long, action = clap::ArgAction::Count)]
+    pub force_permissions: u8,
+
+    /// User ID allowed to connect to the preceding --socket (repeatable)
+    ///
+    /// Clients of other users than the proxy's own and root are rejected
+    /// unless allowed by --allowed-uid or --allowed-group.
+    #[arg(long, value_name = "UID", action = clap::ArgAction::Append)]
+    pub allowed_uid: Vec<u32>,
+
+    /// Group (name or gid) whose members may connect to the preceding --socket (repeatable)
+    #[arg(long, value_name = "GROUP", action = clap::ArgAction::Append)]
+    pub allowed_group: Vec<String>,
+
+    /// Allow adding keys (ADD_IDENTITY) [default: true]
+    ///
+    /// Feature flags apply to the preceding --socket, or to all sockets of the
+    /// preceding --upstream group when given before any --socket.
+    #[arg(long, value_name = "BOOL", action = clap::ArgAction::Append)]
+    pub allow_add: Vec<bool>,
+
+    /// Allow removing keys (REMOVE_IDENTITY, REMOVE_ALL_IDENTITIES) [default: true]
+    #[arg(long, value_name = "BOOL", action = clap::ArgAction::Append)]
+    pub allow_remove: Vec<bool>,
+
+    /// Allow locking the agent (LOCK, UNLOCK) [default: true]
+    #[arg(long, value_name = "BOOL", action = clap::ArgAction::Append)]
+    pub allow_lock: Vec<bool>,
+
+    /// Allow protocol extensions (EXTENSION) [default: true]
+    #[arg(long, value_name = "BOOL", action = clap::ArgAction::Append)]
+    pub allow_extension: Vec<bool>,
+
+    /// Allow smartcard key operations [default: true]
+    #[arg(long, value_name = "BOOL", action = clap::ArgAction::Append)]
+    pub allow_smartcard: Vec<bool>,
+
+    /// Allow signing data that is not an SSH login or SSHSIG signature [default: false]
+    #[arg(long, value_name = "BOOL", action = clap::ArgAction::Append)]
+    pub allow_unknown_sign: Vec<bool>,
+
     /// Print configuration as TOML and exit (useful for creating config file)
     #[arg(long)]
     pub print_config: bool,
@@ -58,7 +197,7 @@ impl RunArgs {
     ///
     /// Each --upstream starts a new group, and subsequent --socket definitions
     /// belong to that upstream until the next --upstream.
-    pub fn parse_upstream_groups(&self) -> Vec<UpstreamGroup> {
+    pub fn parse_upstream_groups(&self) -> Result<Vec<UpstreamGroup>> {
         parse_upstream_groups_from_args()
     }
 }
@@ -91,6 +230,14 @@ pub struct UnregisterArgs {
     pub name: String,
 }
 
+/// Arguments for the `stdio` command
+#[derive(Args, Debug, Clone)]
+pub struct StdioArgs {
+    /// Name of the socket in the configuration file whose filters apply
+    #[arg(long, value_name = "NAME")]
+    pub socket_name: String,
+}
+
 /// Arguments for the `log` command
 #[derive(Args, Debug, Clone)]
 pub struct LogArgs {
@@ -117,11 +264,22 @@ pub struct CompletionArgs {
 /// belong to that upstream until the next --upstream.
 ///
 /// If no --upstream is specified, uses SSH_AUTH_SOCK environment variable.
-pub fn parse_upstream_groups_from_args() -> Vec<UpstreamGroup> {
+///
+/// Feature flags (`--allow-*`) apply to the current --socket if any, otherwise
+/// to the current --upstream group. Flags given before any --upstream or
+/// --socket apply to every group. Socket options (`--mode`, `--permissions`,
+/// ...) given before any --socket are an error.
+pub fn parse_upstream_groups_from_args() -> Result<Vec<UpstreamGroup>> {
     let args: Vec<String> = std::env::args().collect();
+    parse_upstream_groups_from(&args)
+}
+
+/// Parse upstream groups from the given command line arguments
+fn parse_upstream_groups_from(args: &[String]) -> Result<Vec<UpstreamGroup>> {
     let mut groups: Vec<UpstreamGroup> = Vec::new();
     let mut current_group: Option<UpstreamGroup> = None;
     let mut current_socket: Option<SocketSpec> = None;
+    let mut global_access = AccessPolicy::default();
 
     let mut iter = args.iter().peekable();
     while let Some(arg) = iter.next() {
@@ -150,6 +308,7 @@ pub fn parse_upstream_groups_from_args() -> Vec<UpstreamGroup> {
                 current_group = Some(UpstreamGroup {
                     path: expand_path(path),
                     sockets: Vec::new(),
+                    access: AccessPolicy::default(),
                 });
             }
         } else if arg == "--socket" || arg.starts_with("--socket=") {
@@ -175,14 +334,71 @@ pub fn parse_upstream_groups_from_args() -> Vec<UpstreamGroup> {
                     current_group = Some(UpstreamGroup {
                         path: PathBuf::from(ssh_auth_sock),
                         sockets: Vec::new(),
+                        access: AccessPolicy::default(),
                     });
                 }
 
                 current_socket = Some(SocketSpec {
                     path: expand_path(path),
-                    filters: Vec::new(),
+                    ..Default::default()
                 });
             }
+        } else if arg == "--mode" || arg.starts_with("--mode=") {
+            // Socket mode applies to the current --socket only
+            let value = if arg == "--mode" {
+                iter.next().map(|s| s.as_str())
+            } else {
+                arg.strip_prefix("--mode=")
+            };
+            let spec = current_spec(&mut current_socket, "--mode")?;
+            if let Some(mode) = value.and_then(|v| v.parse().ok()) {
+                spec.mode = Some(mode);
+            }
+        } else if let Some((option, inline_value)) = split_socket_option(arg) {
+            // Socket options apply to the current --socket only
+            let value = match inline_value {
+                Some(v) => Some(v),
+                None => iter.next().map(|s| s.as_str()),
+            };
+            let spec = current_spec(&mut current_socket, &format!("--{}", option))?;
+            if let Some(value) = value {
+                let value = value.to_string();
+                match option {
+                    "permissions" => spec.permissions = Some(value),
+                    "group" => spec.group = Some(value),
+                    "dir-permissions" => spec.dir_permissions = Some(value),
+                    "idle-lock" => spec.idle_lock = Some(value),
+                    "idle-unlock-file" => spec.idle_unlock_file = Some(value),
+                    "confirm-program" => spec.confirm_program = Some(value),
+                    "allowed-uid" => spec.allowed_uids.extend(value.parse::<u32>().ok()),
+                    "allowed-group" => spec.allowed_groups.push(value),
+                    _ => unreachable!("unhandled socket option --{}", option),
+                }
+            }
+        } else if arg == "--force-permissions" {
+            current_spec(&mut current_socket, arg)?.force_permissions = true;
+        } else if arg == "--local-lock" {
+            current_spec(&mut current_socket, arg)?.local_lock = true;
+        } else if arg == "--confirm" {
+            current_spec(&mut current_socket, arg)?.confirm = true;
+        } else if let Some((name, inline_value)) = split_access_flag(arg) {
+            // Feature flag: --allow-xxx BOOL or --allow-xxx=BOOL
+            let value = match inline_value {
+                Some(v) => Some(v),
+                None => iter.next().map(|s| s.as_str()),
+            };
+            let Some(value) = value.and_then(|v| v.parse::<bool>().ok()) else {
+                continue;
+            };
+
+            let access = if let Some(ref mut spec) = current_socket {
+                &mut spec.access
+            } else if let Some(ref mut group) = current_group {
+                &mut group.access
+            } else {
+                &mut global_access
+            };
+            access.set(name, value);
         } else if let Some(ref mut spec) = current_socket {
             // Arguments after --socket PATH belong to this socket
             // Skip known global options
@@ -227,7 +443,64 @@ pub fn parse_upstream_groups_from_args() -> Vec<UpstreamGroup> {
         groups.push(group);
     }
 
-    groups
+    // Apply global feature flags to every group
+    for group in &mut groups {
+        group.access = group.access.or(global_access);
+    }
+
+    Ok(groups)
+}
+
+/// Get the --socket a socket option applies to
+///
+/// Socket options given before any --socket would otherwise be dropped and
+/// leave the socket less restricted than intended.
+fn current_spec<'a>(
+    current_socket: &'a mut Option<SocketSpec>,
+    option: &str,
+) -> Result<&'a mut SocketSpec> {
+    current_socket
+        .as_mut()
+        .ok_or_else(|| Error::Config(format!("{} must follow --socket", option)))
+}
+
+/// Options with a value that apply to the current --socket
+const SOCKET_OPTIONS: &[&str] = &[
+    "permissions",
+    "group",
+    "dir-permissions",
+    "idle-lock",
+    "idle-unlock-file",
+    "confirm-program",
+    "allowed-uid",
+    "allowed-group",
+];
+
+/// Split a socket option into (option name, inline value)
+///
+/// Returns `None` if the argument is not one of `SOCKET_OPTIONS`.
+fn split_socket_option(arg: &str) -> Option<(&str, Option<&str>)> {
+    let rest = arg.strip_prefix("--")?;
+    let (name, value) = match rest.split_once('=') {
+        Some((name, value)) => (name, Some(value)),
+        None => (rest, None),
+    };
+    SOCKET_OPTIONS.contains(&name).then_some((name, value))
+}
+
+/// Split a feature flag argument into (flag name, inline value)
+///
+/// Returns `None` if the argument is not a `--allow-*` flag.
+fn split_access_flag(arg: &str) -> Option<(&str, Option<&str>)> {
+    let rest = arg.strip_prefix("--")?;
+    let (name, value) = match rest.split_once('=') {
+        Some((name, value)) => (name, Some(value)),
+        None => (rest, None),
+    };
+    // Validate the name against known flags
+    AccessPolicy::default()
+        .set(name, true)
+        .then_some((name, value))
 }
 
 /// Expand path with ~ and environment variables
@@ -236,10 +509,19 @@ fn expand_path(path: &str) -> PathBuf {
     PathBuf::from(expanded.as_ref())
 }
 
-/// Completer for --upstream arguments (path completion)
+/// Completer for --upstream arguments (presets and path completion)
 fn upstream_completer(current: &std::ffi::OsStr) -> Vec<CompletionCandidate> {
     let current = current.to_string_lossy();
-    complete_path(&current)
+    let mut candidates: Vec<CompletionCandidate> = Preset::ALL
+        .iter()
+        .map(|preset| (format!("{}{}", PRESET_PREFIX, preset), preset.description()))
+        .filter(|(name, _)| name.starts_with(current.as_ref()))
+        .map(|(name, help)| CompletionCandidate::new(name).help(Some(help.into())))
+        .collect();
+    if !current.starts_with(PRESET_PREFIX) {
+        candidates.extend(complete_path(&current));
+    }
+    candidates
 }
 
 /// Filter types for completion
@@ -250,12 +532,51 @@ const FILTER_TYPES: &[(&str, &str)] = &[
     ("type=", "Match by key type (ed25519, rsa, ecdsa, dsa)"),
     ("pubkey=", "Match by full public key"),
     ("keyfile=", "Match keys from file"),
+    (
+        "ssh-user=",
+        "Sign only for this remote user (glob or ~regex)",
+    ),
+    ("ssh-service=", "Sign only for this userauth service"),
+    ("sign-alg=", "Sign only with this signature algorithm"),
+    ("host=", "Only for this destination host (known_hosts name)"),
+    ("upstream=", "Match keys held by this upstream agent"),
+    ("client-exe=", "Only for clients running this executable"),
+    (
+        "client-ancestor=",
+        "Only for clients started by this executable",
+    ),
+    (
+        "namespace=",
+        "Sign only SSHSIG data in this namespace (git, file)",
+    ),
+    (
+        "sign-hash=",
+        "Sign only SSHSIG data with this hash (sha256, sha512)",
+    ),
     ("not-fingerprint=", "Exclude by fingerprint"),
     ("not-comment=", "Exclude by comment"),
     ("not-github=", "Exclude GitHub user keys"),
     ("not-type=", "Exclude key type"),
     ("not-pubkey=", "Exclude by public key"),
     ("not-keyfile=", "Exclude keys from file"),
+    ("not-ssh-user=", "Refuse signing for this remote user"),
+    (
+        "not-ssh-service=",
+        "Refuse signing for this userauth service",
+    ),
+    ("not-sign-alg=", "Refuse this signature algorithm"),
+    ("not-host=", "Never for this destination host"),
+    ("not-namespace=", "Refuse SSHSIG data in this namespace"),
+    ("not-sign-hash=", "Refuse SSHSIG data with this hash"),
+    ("not-upstream=", "Exclude keys held by this upstream agent"),
+    (
+        "not-client-exe=",
+        "Never for clients running this executable",
+    ),
+    (
+        "not-client-ancestor=",
+        "Never for clients started by this executable",
+    ),
 ];
 
 /// Key types for type= filter completion
@@ -410,3 +731,61 @@ fn complete_path(current: &str) -> Vec<CompletionCandidate> {
     }
     candidates
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Vec<UpstreamGroup>> {
+        let args: Vec<String> = std::iter::once("authsock-filter")
+            .chain(args.iter().copied())
+            .map(String::from)
+            .collect();
+        parse_upstream_groups_from(&args)
+    }
+
+    #[test]
+    fn test_socket_options_follow_socket() {
+        let groups = parse(&[
+            "--upstream",
+            "/tmp/agent.sock",
+            "--socket",
+            "/tmp/s1.sock",
+            "--mode",
+            "sign-only",
+            "--permissions=0660",
+            "--idle-lock",
+            "15m",
+            "--idle-unlock-file=~/.config/authsock-filter/unlock",
+        ])
+        .unwrap();
+        let spec = &groups[0].sockets[0];
+        assert_eq!(spec.mode, Some(SocketMode::SignOnly));
+        assert_eq!(spec.permissions.as_deref(), Some("0660"));
+        assert_eq!(spec.idle_lock.as_deref(), Some("15m"));
+        assert_eq!(
+            spec.idle_unlock_file.as_deref(),
+            Some("~/.config/authsock-filter/unlock")
+        );
+    }
+
+    #[test]
+    fn test_socket_options_before_socket_are_rejected() {
+        for option in [
+            &["--mode", "sign-only"][..],
+            &["--permissions=0660"],
+            &["--force-permissions"],
+            &["--local-lock"],
+            &["--confirm"],
+            &["--confirm-program", "/usr/bin/ssh-askpass"],
+        ] {
+            let args: Vec<&str> = ["--upstream", "/tmp/agent.sock"]
+                .into_iter()
+                .chain(option.iter().copied())
+                .chain(["--socket", "/tmp/s1.sock"])
+                .collect();
+            let err = parse(&args).unwrap_err().to_string();
+            assert!(err.contains("must follow --socket"), "{}", err);
+        }
+    }
+}
@@ -162,7 +162,37 @@ pub fn print_config_as_cli(exe: &str, config: &crate::config::Config) {
     // Group sockets by upstream (BTreeMap for stable ordering)
     let mut groups: BTreeMap<&str, Vec<(&str, &crate::config::SocketConfig)>> = BTreeMap::new();
     for (name, socket) in &config.sockets {
-        let upstream = socket.upstream.as_deref().unwrap_or(&config.upstream);
+        // Merged upstreams and failover chains cannot be expressed as CLI
+        // arguments; their first agent is shown
+        use crate::config::UpstreamConfig;
+        if socket.upstream.len() > 1 {
+            eprintln!(
+                "# warning: socket '{}' merges {} upstreams; only the first is shown",
+                name,
+                socket.upstream.len()
+            );
+        }
+        let mut upstream = socket
+            .upstream
+            .first()
+            .map(String::as_str)
+            .unwrap_or(&config.upstream);
+        if let Some(UpstreamConfig::Failover(chain)) = config.upstreams.get(upstream) {
+            eprintln!(
+                "# warning: socket '{}' uses failover chain '{}'; only its first upstream is shown",
+                name, upstream
+            );
+            upstream = chain
+                .failover
+                .first()
+                .map(String::as_str)
+                .unwrap_or(upstream);
+        }
+        match config.upstreams.get(upstream) {
+            Some(UpstreamConfig::Path(path)) => upstream = path,
+            Some(UpstreamConfig::Agent(agent)) => upstream = &agent.path,
+            _ => {}
+        }
         groups.entry(upstream).or_default().push((name, socket));
     }
 
@@ -185,11 +215,52 @@ pub fn print_config_as_cli(exe: &str, config: &crate::config::Config) {
             // Quote socket path
             let quoted_path = shlex::try_quote(&socket.path).unwrap_or(socket.path.clone().into());
 
+            // Socket options are attached to the first --socket line
+            let mut flags_str: String = config
+                .socket_access_policy(socket)
+                .flags()
+                .iter()
+                .map(|(name, value)| format!(" --{} {}", name, value))
+                .collect();
+            if !socket.mode.is_full() {
+                flags_str.push_str(&format!(" --mode {}", socket.mode));
+            }
+            if socket.local_lock == Some(true) {
+                flags_str.push_str(" --local-lock");
+            }
+            if socket.confirm == Some(true) {
+                flags_str.push_str(" --confirm");
+            }
+            let file_options = [
+                ("idle-lock", &socket.idle_lock),
+                ("idle-unlock-file", &socket.idle_unlock_file),
+                ("confirm-program", &socket.confirm_program),
+                ("permissions", &socket.permissions),
+                ("group", &socket.group),
+                ("dir-permissions", &socket.dir_permissions),
+            ];
+            for (name, value) in file_options {
+                if let Some(value) = value {
+                    let quoted = shlex::try_quote(value).unwrap_or(value.into());
+                    flags_str.push_str(&format!(" --{} {}", name, quoted));
+                }
+            }
+            if socket.force_permissions == Some(true) {
+                flags_str.push_str(" --force-permissions");
+            }
+            for uid in &socket.allowed_uids {
+                flags_str.push_str(&format!(" --allowed-uid {}", uid));
+            }
+            for group in &socket.allowed_groups {
+                let quoted = shlex::try_quote(group).unwrap_or(group.into());
+                flags_str.push_str(&format!(" --allowed-group {}", quoted));
+            }
+
             // Each AND group becomes a separate --socket line (OR between groups)
             if socket.filters.is_empty() {
                 let is_last = is_last_group && j == sockets.len() - 1;
                 let line_end = if is_last { "" } else { " \\" };
-                println!("    --socket {}{}", quoted_path, line_end);
+                println!("    --socket {}{}{}", quoted_path, flags_str, line_end);
             } else {
                 let filter_group_count = socket.filters.len();
                 for (k, and_group) in socket.filters.iter().enumerate() {
@@ -202,8 +273,12 @@ pub fn print_config_as_cli(exe: &str, config: &crate::config::Config) {
                         .map(|f| shlex::try_quote(f).unwrap_or(f.clone().into()).to_string())
                         .collect();
                     let filters_str = quoted_filters.join(" ");
+                    let flags_str = if k == 0 { flags_str.as_str() } else { "" };
 
-                    println!("    --socket {} {}{}", quoted_path, filters_str, line_end);
+                    println!(
+                        "    --socket {} {}{}{}",
+                        quoted_path, filters_str, flags_str, line_end
+                    );
                 }
             }
         }
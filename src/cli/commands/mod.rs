@@ -5,6 +5,7 @@ pub mod config;
 pub mod log;
 pub mod run;
 pub mod service;
+pub mod stdio;
 pub mod version;
 
 pub use crate::utils::version_manager::{
@@ -1,20 +1,24 @@
 //! Run command - execute the proxy in the foreground
 
 use anyhow::{Context, Result, bail};
+use std::collections::HashMap;
 use std::os::unix::fs::MetadataExt;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::net::UnixListener;
 use tokio::signal;
 use tokio::sync::watch;
 use tracing::{debug, error, info, warn};
 
-use crate::agent::{Proxy, Upstream};
+use crate::agent::{PoolConfig, Proxy, Server, Upstream, UpstreamTarget};
 use crate::cli::args::RunArgs;
-use crate::config::{Config, ExpandedConfig, SocketConfig, find_config_file, load_config};
+use crate::config::{
+    Config, ExpandedConfig, ExpandedSocketConfig, ExpandedUpstream, SocketConfig, expand_confirm,
+    expand_idle_lock, expand_idle_unlock_file, expand_peer_policy, expand_socket_permissions,
+    find_config_file, load_config, read_idle_passphrase,
+};
 use crate::filter::FilterEvaluator;
-use crate::utils::socket::{prepare_socket_path, set_socket_permissions};
+use crate::utils::socket::is_abstract;
 
 /// Execute the run command
 pub async fn execute(args: RunArgs, config_path: Option<PathBuf>) -> Result<()> {
@@ -31,162 +35,120 @@ pub async fn execute(args: RunArgs, config_path: Option<PathBuf>) -> Result<()>
     }
 
     info!(
-        default_upstream = %config.upstream.display(),
+        default_upstream = %config.upstream,
         socket_count = config.sockets.len(),
         "Starting authsock-filter"
     );
 
     // Log configuration
     for (name, spec) in &config.sockets {
-        let upstream_display = spec.upstream.as_ref().unwrap_or(&config.upstream);
+        let upstreams: Vec<String> = config
+            .socket_upstreams(spec)
+            .iter()
+            .map(|u| u.name.clone())
+            .collect();
         info!(
             name = %name,
             socket = %spec.path.display(),
-            upstream = %upstream_display.display(),
+            upstream = ?upstreams,
             filters = ?spec.filters,
+            access = ?spec.access.flags(),
+            mode = %spec.mode,
             "Configured socket"
         );
     }
 
     // Warn if default upstream socket doesn't exist
-    if !config.upstream.exists() {
+    if !upstream_exists(&ExpandedUpstream::from_target(config.upstream.clone())).await {
         warn!(
-            upstream = %config.upstream.display(),
-            "Default upstream socket does not exist. Sockets using it will be skipped."
+            upstream = %config.upstream,
+            "Default upstream socket does not exist yet. Sockets using it list no keys until it appears."
         );
     }
 
     // Cache for upstream connections (to avoid creating duplicate Upstream instances)
-    use std::collections::HashMap as UpstreamCache;
-    let mut upstream_cache: UpstreamCache<PathBuf, Arc<Upstream>> = UpstreamCache::new();
-    if config.upstream.exists() {
-        upstream_cache.insert(
-            config.upstream.clone(),
-            Arc::new(Upstream::new(config.upstream.to_string_lossy().to_string())),
-        );
-    }
+    let mut upstream_cache = UpstreamCache::new();
+
+    // Shutdown channel for the socket servers
+    let (shutdown_tx, _) = watch::channel(false);
 
     // Start proxy servers for each socket
     let mut handles = Vec::new();
     let mut socket_paths = Vec::new();
+    let mut idle_locked = Vec::new();
 
     for (name, spec) in &config.sockets {
-        // Determine upstream for this socket
-        let upstream_path = spec.upstream.as_ref().unwrap_or(&config.upstream);
-
-        // Validate upstream exists
-        if !upstream_path.exists() {
-            error!(
-                name = %name,
-                upstream = %upstream_path.display(),
-                "Upstream socket does not exist, skipping"
-            );
-            continue;
-        }
-
-        // Get or create upstream connection manager
-        let upstream = upstream_cache
-            .entry(upstream_path.clone())
-            .or_insert_with(|| Arc::new(Upstream::new(upstream_path.to_string_lossy().to_string())))
-            .clone();
-
-        // Parse filters
-        let filter = match FilterEvaluator::parse(&spec.filters) {
-            Ok(f) => f,
-            Err(e) => {
-                error!(
-                    name = %name,
-                    socket = %spec.path.display(),
-                    error = %e,
-                    "Failed to parse filters, skipping"
-                );
-                continue;
-            }
-        };
-
-        // Ensure async filters are loaded (e.g., GitHub keys)
-        if let Err(e) = filter.ensure_loaded().await {
-            error!(
-                name = %name,
-                socket = %spec.path.display(),
-                error = %e,
-                "Failed to load filter data, skipping"
-            );
-            continue;
-        }
-
+        let upstream_names: Vec<String> = config
+            .socket_upstreams(spec)
+            .into_iter()
+            .map(|u| u.name)
+            .collect();
         let socket_path_str = spec.path.to_string_lossy().to_string();
+        let proxy =
+            match build_proxy(&config, name, spec, &socket_path_str, &mut upstream_cache).await {
+                Ok(proxy) => Arc::new(proxy),
+                Err(e) => {
+                    error!(
+                        name = %name,
+                        socket = %spec.path.display(),
+                        error = %format!("{e:#}"),
+                        "Failed to set up socket, skipping"
+                    );
+                    continue;
+                }
+            };
 
-        // Create proxy
-        let proxy = Arc::new(
-            Proxy::new_shared(upstream, Arc::new(filter)).with_socket_path(&socket_path_str),
-        );
-
-        // Prepare socket path (remove existing with symlink protection, create parent dir)
-        if let Err(e) = prepare_socket_path(&spec.path) {
-            error!(
-                name = %name,
-                socket = %spec.path.display(),
-                error = %e,
-                "Failed to prepare socket, skipping"
-            );
-            continue;
-        }
-
-        // Bind listener
-        let listener = match UnixListener::bind(&spec.path) {
-            Ok(l) => l,
-            Err(e) => {
-                error!(
-                    name = %name,
-                    socket = %spec.path.display(),
-                    error = %e,
-                    "Failed to bind socket, skipping"
-                );
-                continue;
-            }
-        };
-
-        // Set socket permissions to 0600 (owner read/write only)
-        if let Err(e) = set_socket_permissions(&spec.path) {
+        // Bind the socket (stale file removal with symlink protection,
+        // parent directory and permissions)
+        let mut server = Server::new(&spec.path)
+            .with_permissions(spec.permissions)
+            .with_peer_policy(spec.peers.clone());
+        if let Err(e) = server.bind().await {
             error!(
                 name = %name,
                 socket = %spec.path.display(),
                 error = %e,
-                "Failed to set socket permissions, skipping"
+                "Failed to start socket, skipping"
             );
             continue;
         }
 
-        // Record inode for monitoring
+        // Record inode for monitoring (abstract sockets have no file to watch or remove)
+        let abstract_socket = is_abstract(&spec.path);
         let inode = std::fs::metadata(&spec.path).ok().map(|m| m.ino());
         info!(
             name = %name,
             path = %spec.path.display(),
-            upstream = %upstream_path.display(),
+            upstream = ?upstream_names,
             inode = ?inode,
+            permissions = %format!("{:04o}", spec.permissions.mode),
             "Listening on socket"
         );
 
-        socket_paths.push((spec.path.clone(), inode));
+        if spec.permissions.is_world_accessible() {
+            warn!(
+                name = %name,
+                socket = %spec.path.display(),
+                "Socket is accessible to all users (force_permissions)"
+            );
+        }
+
+        if !abstract_socket {
+            socket_paths.push((spec.path.clone(), inode));
+        }
+        if spec.idle_lock.is_some() {
+            idle_locked.push(proxy.clone());
+        }
 
         // Spawn task to handle connections
+        let shutdown_rx = shutdown_tx.subscribe();
         let handle = tokio::spawn(async move {
-            loop {
-                match listener.accept().await {
-                    Ok((stream, _)) => {
-                        let proxy = proxy.clone();
-                        tokio::spawn(async move {
-                            if let Err(e) = proxy.handle_client(stream).await {
-                                debug!(error = %e, "Client connection error");
-                            }
-                        });
-                    }
-                    Err(e) => {
-                        error!(error = %e, "Failed to accept connection");
-                        break;
-                    }
-                }
+            let handler = move |stream, peer| {
+                let proxy = proxy.clone();
+                async move { proxy.handle_client_from(stream, Some(peer)).await }
+            };
+            if let Err(e) = server.run(handler, shutdown_rx).await {
+                error!(error = %e, "Socket server failed");
             }
         });
 
@@ -202,15 +164,38 @@ pub async fn execute(args: RunArgs, config_path: Option<PathBuf>) -> Result<()>
         "Proxy server started. Press Ctrl+C to stop."
     );
 
-    // Create shutdown channel for inode monitor
-    let (shutdown_tx, _) = watch::channel(false);
+    // SIGUSR1 resumes sockets locked by their idle timeout
+    let resume_handle = spawn_idle_resume(idle_locked.clone());
 
-    // Spawn inode monitoring task
+    // Spawn inode monitoring task (also expires idle pooled upstream
+    // connections and locks idle sockets)
     let socket_paths_for_monitor = socket_paths.clone();
+    let pooled_upstreams: Vec<Arc<Upstream>> = upstream_cache
+        .values()
+        .filter(|u| u.has_pool())
+        .cloned()
+        .collect();
+    let pooled_for_shutdown = pooled_upstreams.clone();
     let monitor_handle = tokio::spawn(async move {
         let mut interval = tokio::time::interval(Duration::from_secs(5));
         loop {
             interval.tick().await;
+            for proxy in &idle_locked {
+                proxy.lock_if_idle();
+            }
+            for upstream in &pooled_upstreams {
+                upstream.prune_pool();
+                if let Some(stats) = upstream.pool_stats() {
+                    debug!(
+                        upstream = %upstream.name(),
+                        idle = stats.idle,
+                        created = stats.created,
+                        reused = stats.reused,
+                        discarded = stats.discarded,
+                        "Upstream pool statistics"
+                    );
+                }
+            }
             for (path, original_inode) in &socket_paths_for_monitor {
                 let current_inode = std::fs::metadata(path).ok().map(|m| m.ino());
                 match (original_inode, current_inode) {
@@ -248,8 +233,20 @@ pub async fn execute(args: RunArgs, config_path: Option<PathBuf>) -> Result<()>
     // Signal shutdown
     let _ = shutdown_tx.send(true);
 
+    for upstream in &pooled_for_shutdown {
+        if let Some(stats) = upstream.pool_stats() {
+            info!(
+                upstream = %upstream.name(),
+                created = stats.created,
+                reused = stats.reused,
+                discarded = stats.discarded,
+                "Upstream pool statistics"
+            );
+        }
+    }
+
     // Cancel all listener tasks
-    for handle in handles {
+    for handle in handles.into_iter().chain(resume_handle) {
         handle.abort();
     }
 
@@ -269,24 +266,128 @@ pub async fn execute(args: RunArgs, config_path: Option<PathBuf>) -> Result<()>
     Ok(())
 }
 
+/// Resume idle-locked sockets on SIGUSR1
+///
+/// Returns `None` if no socket has an idle lock, leaving SIGUSR1 at its
+/// default action.
+pub(crate) fn spawn_idle_resume(proxies: Vec<Arc<Proxy>>) -> Option<tokio::task::JoinHandle<()>> {
+    if proxies.is_empty() {
+        return None;
+    }
+    let Ok(mut usr1) = signal::unix::signal(signal::unix::SignalKind::user_defined1()) else {
+        warn!("Failed to listen for SIGUSR1, idle-locked sockets cannot be resumed");
+        return None;
+    };
+    Some(tokio::spawn(async move {
+        while usr1.recv().await.is_some() {
+            let resumed = proxies.iter().filter(|proxy| proxy.resume()).count();
+            info!(resumed, "Received SIGUSR1, resumed idle-locked sockets");
+        }
+    }))
+}
+
+/// Check if any agent of an upstream exists
+///
+/// Resolving `command:`, `discover:` and `preset:` upstreams may run a
+/// command or probe sockets, so it is kept off the runtime.
+async fn upstream_exists(upstream: &ExpandedUpstream) -> bool {
+    let upstream = upstream.clone();
+    tokio::task::spawn_blocking(move || upstream.exists())
+        .await
+        .unwrap_or(false)
+}
+
+/// Upstream connection managers shared by sockets with the same upstream
+pub(crate) type UpstreamCache = HashMap<ExpandedUpstream, Arc<Upstream>>;
+
+/// Create the filtering proxy of a configured socket
+///
+/// Upstreams that do not exist yet are connected to once they appear.
+pub(crate) async fn build_proxy(
+    config: &ExpandedConfig,
+    name: &str,
+    spec: &ExpandedSocketConfig,
+    socket_path: &str,
+    upstream_cache: &mut UpstreamCache,
+) -> Result<Proxy> {
+    let mut upstreams = Vec::new();
+    for upstream in config.socket_upstreams(spec) {
+        // Check upstream exists (any agent of a failover chain)
+        if !upstream_exists(&upstream).await {
+            warn!(
+                name = %name,
+                upstream = %upstream.target,
+                "Upstream socket does not exist yet, waiting for it"
+            );
+        }
+
+        // Get or create upstream connection manager
+        let manager = upstream_cache
+            .entry(upstream.clone())
+            .or_insert_with(|| Arc::new(build_upstream(&upstream, config.pool)))
+            .clone();
+        upstreams.push(manager);
+    }
+
+    let filter = FilterEvaluator::parse(&spec.filters).context("Failed to parse filters")?;
+
+    // Ensure async filters are loaded (e.g., GitHub keys)
+    filter
+        .ensure_loaded()
+        .await
+        .context("Failed to load filter data")?;
+
+    let mut proxy = Proxy::new_shared(upstreams[0].clone(), Arc::new(filter))
+        .with_socket_path(socket_path)
+        .with_access_policy(spec.access)
+        .with_mode(spec.mode)
+        .with_local_lock(spec.local_lock)
+        .with_idle_lock(spec.idle_lock)
+        .with_confirm(spec.confirm.clone());
+    if let Some(file) = &spec.idle_unlock_file {
+        let passphrase = read_idle_passphrase(file)?;
+        proxy = proxy.with_idle_passphrase(&passphrase)?;
+    }
+    for upstream in &upstreams[1..] {
+        proxy = proxy.with_merged_upstream(upstream.clone());
+    }
+    Ok(proxy)
+}
+
+/// Create the connection manager for a configured upstream
+fn build_upstream(spec: &ExpandedUpstream, pool: PoolConfig) -> Upstream {
+    let mut upstream = Upstream::from_target(spec.target.clone())
+        .with_name(&spec.name)
+        .with_pool(pool);
+    for (name, target) in &spec.fallbacks {
+        upstream = upstream.with_fallback(name, target.clone());
+    }
+    if !spec.fallbacks.is_empty() {
+        upstream = upstream.with_failover_on(spec.failover_on.clone());
+    }
+    if let Some(timeout) = spec.timeout {
+        upstream = upstream.with_request_timeout(timeout);
+    }
+    upstream
+}
+
 /// Load configuration from CLI args or config file
 fn load_configuration(args: &RunArgs, config_path: Option<PathBuf>) -> Result<ExpandedConfig> {
     // If CLI arguments are provided, use them
-    let cli_groups = args.parse_upstream_groups();
+    let cli_groups = args.parse_upstream_groups()?;
     if !cli_groups.is_empty() {
         // Convert CLI args to ExpandedConfig
-        use crate::config::ExpandedSocketConfig;
-        use std::collections::HashMap;
 
-        let default_upstream = cli_groups[0].path.clone();
+        let default_upstream = UpstreamTarget::parse(&cli_groups[0].path.to_string_lossy())?;
         let mut sockets: HashMap<String, ExpandedSocketConfig> = HashMap::new();
 
         for group in cli_groups.iter() {
             // If this group has a different upstream than the default, set it per-socket
-            let socket_upstream = if group.path != default_upstream {
-                Some(group.path.clone())
+            let target = UpstreamTarget::parse(&group.path.to_string_lossy())?;
+            let socket_upstream = if target != default_upstream {
+                vec![ExpandedUpstream::from_target(target)]
             } else {
-                None
+                vec![]
             };
 
             for spec in group.sockets.iter() {
@@ -296,15 +397,60 @@ fn load_configuration(args: &RunArgs, config_path: Option<PathBuf>) -> Result<Ex
                     .find(|(_, cfg)| cfg.path == spec.path)
                     .map(|(name, _)| name.clone());
 
+                let access = spec.access.or(group.access);
+                let permissions = expand_socket_permissions(
+                    &spec.path.to_string_lossy(),
+                    spec.permissions.as_deref(),
+                    spec.group.as_deref(),
+                    spec.dir_permissions.as_deref(),
+                    spec.force_permissions,
+                )?;
+                let peers = expand_peer_policy(
+                    &spec.path.to_string_lossy(),
+                    spec.group.as_deref(),
+                    &spec.allowed_uids,
+                    &spec.allowed_groups,
+                )?;
+                let idle_lock = spec
+                    .idle_lock
+                    .as_deref()
+                    .map(|value| expand_idle_lock(&spec.path.to_string_lossy(), value))
+                    .transpose()?;
+                let idle_unlock_file = expand_idle_unlock_file(
+                    &spec.path.to_string_lossy(),
+                    idle_lock,
+                    spec.idle_unlock_file.as_deref(),
+                )?;
+                let confirm = expand_confirm(
+                    spec.confirm.then_some(true),
+                    spec.confirm_program.as_deref(),
+                )?;
+
                 if let Some(name) = existing_name {
                     // Same path: add filters as OR group
+                    let existing = sockets.get_mut(&name).unwrap();
                     if !spec.filters.is_empty() {
-                        sockets
-                            .get_mut(&name)
-                            .unwrap()
-                            .filters
-                            .push(spec.filters.clone());
+                        existing.filters.push(spec.filters.clone());
+                    }
+                    existing.access = access.or(existing.access);
+                    if let Some(mode) = spec.mode {
+                        existing.mode = mode;
+                    }
+                    existing.local_lock |= spec.local_lock;
+                    if idle_lock.is_some() {
+                        existing.idle_lock = idle_lock;
+                    }
+                    if idle_unlock_file.is_some() {
+                        existing.idle_unlock_file = idle_unlock_file;
+                    }
+                    if confirm.is_some() {
+                        existing.confirm = confirm;
+                    }
+                    if spec.has_file_options() {
+                        existing.permissions = permissions;
                     }
+                    existing.peers.allowed_uids.extend(peers.allowed_uids);
+                    existing.peers.allowed_gids.extend(peers.allowed_gids);
                 } else {
                     // New socket path
                     let name = spec
@@ -339,6 +485,14 @@ fn load_configuration(args: &RunArgs, config_path: Option<PathBuf>) -> Result<Ex
                             } else {
                                 vec![spec.filters.clone()]
                             },
+                            access,
+                            mode: spec.mode.unwrap_or_default(),
+                            local_lock: spec.local_lock,
+                            idle_lock,
+                            idle_unlock_file,
+                            confirm,
+                            permissions,
+                            peers,
                         },
                     );
                 }
@@ -352,6 +506,7 @@ fn load_configuration(args: &RunArgs, config_path: Option<PathBuf>) -> Result<Ex
                 cache_ttl: std::time::Duration::from_secs(3600),
                 timeout: std::time::Duration::from_secs(10),
             },
+            pool: Default::default(),
         });
     }
 
@@ -359,10 +514,14 @@ fn load_configuration(args: &RunArgs, config_path: Option<PathBuf>) -> Result<Ex
     let config_file_path = config_path
         .or_else(find_config_file)
         .context("No configuration found. Use --socket option or create a config file.")?;
+    load_config_file(&config_file_path)
+}
 
+/// Load a configuration file and expand its paths
+pub(crate) fn load_config_file(config_file_path: &Path) -> Result<ExpandedConfig> {
     info!(path = %config_file_path.display(), "Loading configuration");
 
-    let config_file = load_config(&config_file_path)?;
+    let config_file = load_config(config_file_path)?;
     config_file
         .config
         .expand_paths()
@@ -371,9 +530,7 @@ fn load_configuration(args: &RunArgs, config_path: Option<PathBuf>) -> Result<Ex
 
 /// Print configuration as TOML from CLI arguments
 fn print_config_from_args(args: &RunArgs) -> Result<()> {
-    use std::collections::HashMap;
-
-    let cli_groups = args.parse_upstream_groups();
+    let cli_groups = args.parse_upstream_groups()?;
     if cli_groups.is_empty() {
         bail!("No configuration to print. Use --upstream and --socket options.");
     }
@@ -386,9 +543,9 @@ fn print_config_from_args(args: &RunArgs) -> Result<()> {
         let group_upstream = group.path.to_string_lossy().to_string();
         // Set per-socket upstream if different from default
         let socket_upstream = if group_upstream != default_upstream {
-            Some(group_upstream)
+            vec![group_upstream]
         } else {
-            None
+            vec![]
         };
 
         for spec in &group.sockets {
@@ -400,15 +557,44 @@ fn print_config_from_args(args: &RunArgs) -> Result<()> {
                 .find(|(_, cfg)| cfg.path == socket_path)
                 .map(|(name, _)| name.clone());
 
+            let access = spec.access.or(group.access);
+
             if let Some(name) = existing_name {
                 // Same path: add filters as OR group
+                let existing = sockets.get_mut(&name).unwrap();
                 if !spec.filters.is_empty() {
-                    sockets
-                        .get_mut(&name)
-                        .unwrap()
-                        .filters
-                        .push(spec.filters.clone());
+                    existing.filters.push(spec.filters.clone());
+                }
+                let merged = access.or(existing.access_policy());
+                existing.set_access_policy(merged);
+                if let Some(mode) = spec.mode {
+                    existing.mode = mode;
+                }
+                if spec.local_lock {
+                    existing.local_lock = Some(true);
+                }
+                if spec.idle_lock.is_some() {
+                    existing.idle_lock = spec.idle_lock.clone();
+                }
+                if spec.idle_unlock_file.is_some() {
+                    existing.idle_unlock_file = spec.idle_unlock_file.clone();
                 }
+                if spec.confirm {
+                    existing.confirm = Some(true);
+                }
+                if spec.confirm_program.is_some() {
+                    existing.confirm_program = spec.confirm_program.clone();
+                }
+                if spec.has_file_options() {
+                    existing.permissions = spec.permissions.clone();
+                    existing.group = spec.group.clone();
+                    existing.dir_permissions = spec.dir_permissions.clone();
+                    existing.force_permissions = spec.force_permissions.then_some(true);
+                }
+                existing.allowed_uids.extend(&spec.allowed_uids);
+                existing
+                    .allowed_groups
+                    .extend(spec.allowed_groups.iter().cloned());
             } else {
                 // New socket path
                 let name = spec
@@ -432,27 +618,41 @@ fn print_config_from_args(args: &RunArgs) -> Result<()> {
                     name
                 };
 
-                sockets.insert(
-                    final_name,
-                    SocketConfig {
-                        path: socket_path,
-                        upstream: socket_upstream.clone(),
-                        // CLI args are a single AND group
-                        filters: if spec.filters.is_empty() {
-                            vec![]
-                        } else {
-                            vec![spec.filters.clone()]
-                        },
+                let mut socket_config = SocketConfig {
+                    path: socket_path,
+                    upstream: socket_upstream.clone(),
+                    // CLI args are a single AND group
+                    filters: if spec.filters.is_empty() {
+                        vec![]
+                    } else {
+                        vec![spec.filters.clone()]
                     },
-                );
+                    mode: spec.mode.unwrap_or_default(),
+                    local_lock: spec.local_lock.then_some(true),
+                    idle_lock: spec.idle_lock.clone(),
+                    idle_unlock_file: spec.idle_unlock_file.clone(),
+                    confirm: spec.confirm.then_some(true),
+                    confirm_program: spec.confirm_program.clone(),
+                    permissions: spec.permissions.clone(),
+                    group: spec.group.clone(),
+                    dir_permissions: spec.dir_permissions.clone(),
+                    force_permissions: spec.force_permissions.then_some(true),
+                    allowed_uids: spec.allowed_uids.clone(),
+                    allowed_groups: spec.allowed_groups.clone(),
+                    ..Default::default()
+                };
+                socket_config.set_access_policy(access);
+                sockets.insert(final_name, socket_config);
             }
         }
     }
 
     let config = Config {
         upstream: default_upstream,
+        upstreams: HashMap::new(),
         sockets,
         github: Default::default(),
+        pool: Default::default(),
     };
 
     let toml = toml::to_string_pretty(&config).context("Failed to serialize config")?;
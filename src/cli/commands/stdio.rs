@@ -0,0 +1,64 @@
+//! Stdio command - serve one filtered agent session on stdin/stdout
+
+use anyhow::{Context, Result};
+use std::path::PathBuf;
+use std::sync::Arc;
+use tracing::{debug, info};
+
+use super::run::{UpstreamCache, build_proxy, load_config_file, spawn_idle_resume};
+use crate::cli::args::StdioArgs;
+use crate::config::find_config_file;
+
+/// Execute the stdio command
+///
+/// The socket is taken from the configuration file; its filters, mode and
+/// upstreams apply as if the client had connected to its Unix socket,
+/// including its idle lock (resumed with SIGUSR1). Logs go to stderr, so
+/// stdout carries only the agent protocol.
+pub async fn execute(args: StdioArgs, config_path: Option<PathBuf>) -> Result<()> {
+    let config_file_path = config_path
+        .or_else(find_config_file)
+        .context("No configuration found. Create a config file defining the socket.")?;
+    let config = load_config_file(&config_file_path)?;
+
+    let spec = config.sockets.get(&args.socket_name).with_context(|| {
+        let mut names: Vec<&str> = config.sockets.keys().map(String::as_str).collect();
+        names.sort();
+        format!(
+            "Socket '{}' is not defined in the configuration (available: {})",
+            args.socket_name,
+            names.join(", ")
+        )
+    })?;
+
+    let socket = format!("stdio:{}", args.socket_name);
+    let proxy = build_proxy(
+        &config,
+        &args.socket_name,
+        spec,
+        &socket,
+        &mut UpstreamCache::new(),
+    )
+    .await
+    .map(Arc::new)?;
+
+    // SIGUSR1 resumes the session if its idle lock kicked in
+    let resume_handle = spawn_idle_resume(
+        spec.idle_lock
+            .map(|_| vec![proxy.clone()])
+            .unwrap_or_default(),
+    );
+
+    info!(name = %args.socket_name, "Serving agent session on stdio");
+    let stdio = tokio::io::join(tokio::io::stdin(), tokio::io::stdout());
+    proxy
+        .handle_client(stdio)
+        .await
+        .context("Agent session on stdio failed")?;
+    debug!(name = %args.socket_name, "Stdio session closed");
+    if let Some(handle) = resume_handle {
+        handle.abort();
+    }
+
+    Ok(())
+}
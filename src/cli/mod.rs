@@ -9,7 +9,7 @@ pub mod exit_code;
 use clap::{Parser, Subcommand};
 use std::path::PathBuf;
 
-use args::{CompletionArgs, LogArgs, RegisterArgs, RunArgs, UnregisterArgs};
+use args::{CompletionArgs, LogArgs, RegisterArgs, RunArgs, StdioArgs, UnregisterArgs};
 
 /// SSH agent proxy with key filtering
 #[derive(Parser, Debug)]
@@ -46,7 +46,14 @@ pub struct Cli {
 #[derive(Subcommand, Debug)]
 pub enum Commands {
     /// Run the proxy in the foreground
-    Run(RunArgs),
+    Run(Box<RunArgs>),
+
+    /// Serve one filtered agent session on stdin/stdout
+    ///
+    /// For socat, npiperelay-style bridges and container exec plumbing.
+    /// Filters, mode and upstreams are those of the named socket in the
+    /// configuration file.
+    Stdio(StdioArgs),
 
     /// Manage configuration file
     Config {
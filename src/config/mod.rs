@@ -5,10 +5,16 @@
 
 mod file;
 
+use crate::agent::{
+    AccessPolicy, COMMAND_PREFIX, Confirm, DISCOVER_PREFIX, EXEC_PREFIX, FailoverCondition,
+    PRESET_PREFIX, PeerPolicy, PoolConfig, SocketMode, UpstreamTarget,
+};
 use crate::utils::path::expand_path;
+use crate::utils::socket::{SocketPermissions, group_id, is_abstract, parse_mode};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
 
 pub use file::{ConfigFile, ConfigPath, config_search_paths, find_config_file, load_config};
 
@@ -21,6 +27,11 @@ pub struct Config {
     #[serde(default = "default_upstream")]
     pub upstream: String,
 
+    /// Named upstream agents, referenced by name from socket `upstream`
+    /// and `upstream=` filters
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub upstreams: HashMap<String, UpstreamConfig>,
+
     /// Socket definitions
     #[serde(default)]
     pub sockets: HashMap<String, SocketConfig>,
@@ -28,10 +39,14 @@ pub struct Config {
     /// GitHub API settings
     #[serde(default)]
     pub github: GithubConfig,
+
+    /// Upstream connection pool settings
+    #[serde(default)]
+    pub pool: UpstreamPoolConfig,
 }
 
 /// Configuration for a single socket
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct SocketConfig {
     /// Path to the socket file
@@ -39,8 +54,15 @@ pub struct SocketConfig {
     pub path: String,
 
     /// Optional upstream for this socket (overrides global upstream)
-    #[serde(default)]
-    pub upstream: Option<String>,
+    /// A list merges the identities of several agents; entries are names
+    /// from `[upstreams]` or socket paths
+    #[serde(
+        default,
+        deserialize_with = "deserialize_upstream",
+        serialize_with = "serialize_upstream",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub upstream: Vec<String>,
 
     /// Filter rules for this socket
     /// Mixed format: strings are single OR terms, arrays are AND groups
@@ -51,6 +73,303 @@ pub struct SocketConfig {
         serialize_with = "serialize_filters"
     )]
     pub filters: Vec<Vec<String>>,
+
+    /// Socket mode: "full" (default), "list-only" or "sign-only"
+    #[serde(default, skip_serializing_if = "SocketMode::is_full")]
+    pub mode: SocketMode,
+
+    /// Allow ADD_IDENTITY / ADD_ID_CONSTRAINED (default: true)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allow_add: Option<bool>,
+
+    /// Allow REMOVE_IDENTITY / REMOVE_ALL_IDENTITIES (default: true)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allow_remove: Option<bool>,
+
+    /// Allow LOCK / UNLOCK (default: true)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allow_lock: Option<bool>,
+
+    /// Handle LOCK / UNLOCK in the proxy, locking only this socket
+    /// (default: false, forward to upstream)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub local_lock: Option<bool>,
+
+    /// Lock this socket (in the proxy) after no successful sign for this
+    /// duration, e.g. "15m" (default: never)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idle_lock: Option<String>,
+
+    /// File whose first line is the passphrase with which UNLOCK lifts the
+    /// idle lock (default: none, only SIGUSR1 resumes)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idle_unlock_file: Option<String>,
+
+    /// Ask the user before each signature with $SSH_ASKPASS (default: false)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub confirm: Option<bool>,
+
+    /// Program asking for confirmation instead of $SSH_ASKPASS (implies confirm)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub confirm_program: Option<String>,
+
+    /// Allow protocol extensions (default: true)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allow_extension: Option<bool>,
+
+    /// Allow smartcard key add/remove (default: true)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allow_smartcard: Option<bool>,
+
+    /// Allow signing data that is neither an SSH login nor an SSHSIG
+    /// signature (default: false)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allow_unknown_sign: Option<bool>,
+
+    /// Socket file permissions in octal (default: "0600")
+    /// Named `permissions` because `mode` selects listing/signing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub permissions: Option<String>,
+
+    /// Group owning the socket file (name or gid)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+
+    /// Permissions of the socket's parent directory in octal (default: unchanged)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dir_permissions: Option<String>,
+
+    /// Allow permissions that let any user connect (default: false)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub force_permissions: Option<bool>,
+
+    /// Users allowed to connect besides the proxy's user and root
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_uids: Vec<u32>,
+
+    /// Groups (names or gids) whose members are allowed to connect
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_groups: Vec<String>,
+}
+
+impl SocketConfig {
+    /// Get the feature flags configured for this socket
+    pub fn access_policy(&self) -> AccessPolicy {
+        AccessPolicy {
+            allow_add: self.allow_add,
+            allow_remove: self.allow_remove,
+            allow_lock: self.allow_lock,
+            allow_extension: self.allow_extension,
+            allow_smartcard: self.allow_smartcard,
+            allow_unknown_sign: self.allow_unknown_sign,
+        }
+    }
+
+    /// Set the feature flags for this socket
+    pub fn set_access_policy(&mut self, access: AccessPolicy) {
+        self.allow_add = access.allow_add;
+        self.allow_remove = access.allow_remove;
+        self.allow_lock = access.allow_lock;
+        self.allow_extension = access.allow_extension;
+        self.allow_smartcard = access.allow_smartcard;
+        self.allow_unknown_sign = access.allow_unknown_sign;
+    }
+
+    /// Get the validated permissions of the socket file
+    pub fn socket_permissions(&self) -> crate::Result<SocketPermissions> {
+        expand_socket_permissions(
+            &self.path,
+            self.permissions.as_deref(),
+            self.group.as_deref(),
+            self.dir_permissions.as_deref(),
+            self.force_permissions.unwrap_or(false),
+        )
+    }
+
+    /// Get the program confirming sign requests, if confirmation is enabled
+    pub fn confirmation(&self) -> crate::Result<Option<Confirm>> {
+        expand_confirm(self.confirm, self.confirm_program.as_deref())
+    }
+
+    /// Get the users allowed to connect
+    pub fn peer_policy(&self) -> crate::Result<PeerPolicy> {
+        expand_peer_policy(
+            &self.path,
+            self.group.as_deref(),
+            &self.allowed_uids,
+            &self.allowed_groups,
+        )
+    }
+}
+
+/// Resolve the confirmation program of a socket
+///
+/// A `confirm_program` enables confirmation unless `confirm` is false.
+pub fn expand_confirm(
+    confirm: Option<bool>,
+    program: Option<&str>,
+) -> crate::Result<Option<Confirm>> {
+    if !confirm.unwrap_or(program.is_some()) {
+        return Ok(None);
+    }
+    match program {
+        Some(program) => Confirm::with_program(program).map(Some),
+        None => Ok(Some(Confirm::askpass())),
+    }
+}
+
+/// Parse the idle timeout of a socket
+pub fn expand_idle_lock(path: &str, value: &str) -> crate::Result<std::time::Duration> {
+    let timeout = parse_duration(value)?;
+    if timeout.is_zero() {
+        return Err(crate::Error::Config(format!(
+            "idle_lock of socket {} must be longer than zero",
+            path
+        )));
+    }
+    Ok(timeout)
+}
+
+/// Resolve the passphrase file lifting the idle lock of a socket
+pub fn expand_idle_unlock_file(
+    path: &str,
+    idle_lock: Option<std::time::Duration>,
+    file: Option<&str>,
+) -> crate::Result<Option<PathBuf>> {
+    let Some(file) = file else {
+        return Ok(None);
+    };
+    if idle_lock.is_none() {
+        return Err(crate::Error::Config(format!(
+            "idle_unlock_file of socket {} needs idle_lock",
+            path
+        )));
+    }
+    expand_path(file).map(|file| Some(PathBuf::from(file)))
+}
+
+/// Read the passphrase lifting an idle lock
+///
+/// The passphrase is the first line of the file. Files other users could
+/// read or write are refused.
+pub fn read_idle_passphrase(file: &Path) -> crate::Result<Vec<u8>> {
+    let read_error = |e: std::io::Error| {
+        crate::Error::Config(format!(
+            "Failed to read idle_unlock_file {}: {}",
+            file.display(),
+            e
+        ))
+    };
+    let metadata = std::fs::metadata(file).map_err(read_error)?;
+    if metadata.permissions().mode() & 0o077 != 0 {
+        return Err(crate::Error::Config(format!(
+            "idle_unlock_file {} is accessible by other users (chmod 600 it)",
+            file.display()
+        )));
+    }
+    let content = std::fs::read(file).map_err(read_error)?;
+    let line = content.split(|&b| b == b'\n').next().unwrap_or_default();
+    let passphrase = line.strip_suffix(b"\r").unwrap_or(line);
+    if passphrase.is_empty() {
+        return Err(crate::Error::Config(format!(
+            "idle_unlock_file {} has an empty passphrase",
+            file.display()
+        )));
+    }
+    Ok(passphrase.to_vec())
+}
+
+/// Resolve the groups of a peer policy
+///
+/// Members of the group owning the socket file (`group`) are allowed to
+/// connect without repeating it in `allowed_groups`.
+pub fn expand_peer_policy(
+    path: &str,
+    group: Option<&str>,
+    allowed_uids: &[u32],
+    allowed_groups: &[String],
+) -> crate::Result<PeerPolicy> {
+    let mut allowed_gids: Vec<u32> = allowed_groups
+        .iter()
+        .map(|name| {
+            group_id(name).ok_or_else(|| {
+                crate::Error::Config(format!(
+                    "Unknown group '{}' in allowed_groups of socket {}",
+                    name, path
+                ))
+            })
+        })
+        .collect::<crate::Result<_>>()?;
+    if let Some(name) = group {
+        let gid = group_id(name).ok_or_else(|| {
+            crate::Error::Config(format!("Unknown group '{}' for socket {}", name, path))
+        })?;
+        if !allowed_gids.contains(&gid) {
+            allowed_gids.push(gid);
+        }
+    }
+    Ok(PeerPolicy {
+        allowed_uids: allowed_uids.to_vec(),
+        allowed_gids,
+    })
+}
+
+/// Parse and validate socket permissions
+///
+/// Permissions that let any user connect to the socket (or replace it) are
+/// refused unless `force` is set. Abstract sockets have no file, so setting
+/// permissions on them is an error.
+pub fn expand_socket_permissions(
+    path: &str,
+    permissions: Option<&str>,
+    group: Option<&str>,
+    dir_permissions: Option<&str>,
+    force: bool,
+) -> crate::Result<SocketPermissions> {
+    if is_abstract(std::path::Path::new(path))
+        && (permissions.is_some() || group.is_some() || dir_permissions.is_some())
+    {
+        return Err(crate::Error::Config(format!(
+            "Abstract socket {} has no file permissions; remove permissions, group and dir_permissions",
+            path
+        )));
+    }
+
+    let parse = |mode: &str| {
+        parse_mode(mode).ok_or_else(|| {
+            crate::Error::Config(format!(
+                "Invalid permissions '{}' for socket {}: expected octal like 0660",
+                mode, path
+            ))
+        })
+    };
+    let defaults = SocketPermissions::default();
+    let result = SocketPermissions {
+        mode: permissions.map(parse).transpose()?.unwrap_or(defaults.mode),
+        group: group
+            .map(|name| {
+                group_id(name).ok_or_else(|| {
+                    crate::Error::Config(format!("Unknown group '{}' for socket {}", name, path))
+                })
+            })
+            .transpose()?,
+        dir_mode: dir_permissions.map(parse).transpose()?,
+    };
+
+    if !force && result.is_world_accessible() {
+        return Err(crate::Error::Config(format!(
+            "Permissions {:04o} let any user connect to socket {}; set force_permissions to allow",
+            result.mode, path
+        )));
+    }
+    if !force && result.is_dir_world_writable() {
+        return Err(crate::Error::Config(format!(
+            "Directory permissions {:04o} let any user replace socket {}; set force_permissions to allow",
+            result.dir_mode.unwrap_or_default(),
+            path
+        )));
+    }
+    Ok(result)
 }
 
 /// Custom deserializer for filters:
@@ -132,6 +451,174 @@ where
     seq.end()
 }
 
+/// Named upstream agent definition
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum UpstreamConfig {
+    /// Socket path of the agent
+    /// Supports environment variable and tilde expansion
+    Path(String),
+    /// Ordered failover chain of agents
+    Failover(FailoverConfig),
+    /// Socket path of the agent with feature flags
+    Agent(AgentConfig),
+}
+
+impl UpstreamConfig {
+    /// Get the feature flags of sockets using this upstream
+    pub fn access_policy(&self) -> AccessPolicy {
+        match self {
+            UpstreamConfig::Path(_) => AccessPolicy::default(),
+            UpstreamConfig::Failover(chain) => chain.access_policy(),
+            UpstreamConfig::Agent(agent) => agent.access_policy(),
+        }
+    }
+}
+
+/// Agent configuration with feature flags
+///
+/// The flags apply to every socket using the agent, unless the socket sets
+/// them itself.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AgentConfig {
+    /// Socket path of the agent
+    /// Supports environment variable and tilde expansion
+    pub path: String,
+
+    /// Allow ADD_IDENTITY / ADD_ID_CONSTRAINED (default: true)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allow_add: Option<bool>,
+
+    /// Allow REMOVE_IDENTITY / REMOVE_ALL_IDENTITIES (default: true)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allow_remove: Option<bool>,
+
+    /// Allow LOCK / UNLOCK (default: true)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allow_lock: Option<bool>,
+
+    /// Allow protocol extensions (default: true)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allow_extension: Option<bool>,
+
+    /// Allow smartcard key add/remove (default: true)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allow_smartcard: Option<bool>,
+
+    /// Allow signing data that is neither an SSH login nor an SSHSIG
+    /// signature (default: false)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allow_unknown_sign: Option<bool>,
+}
+
+impl AgentConfig {
+    /// Get the feature flags configured for sockets using this upstream
+    pub fn access_policy(&self) -> AccessPolicy {
+        AccessPolicy {
+            allow_add: self.allow_add,
+            allow_remove: self.allow_remove,
+            allow_lock: self.allow_lock,
+            allow_extension: self.allow_extension,
+            allow_smartcard: self.allow_smartcard,
+            allow_unknown_sign: self.allow_unknown_sign,
+        }
+    }
+}
+
+/// Failover chain configuration
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FailoverConfig {
+    /// Agents in order of preference (names from `[upstreams]` or paths)
+    pub failover: Vec<String>,
+
+    /// Conditions that make a request go to the next agent:
+    /// "connect", "timeout", "empty" (default: all)
+    #[serde(default = "default_failover_on")]
+    pub on: Vec<FailoverCondition>,
+
+    /// Time to wait for an agent's response before failing over
+    /// Format: "5s", "1m", etc. (default: 30s)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<String>,
+
+    /// Allow ADD_IDENTITY / ADD_ID_CONSTRAINED (default: true)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allow_add: Option<bool>,
+
+    /// Allow REMOVE_IDENTITY / REMOVE_ALL_IDENTITIES (default: true)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allow_remove: Option<bool>,
+
+    /// Allow LOCK / UNLOCK (default: true)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allow_lock: Option<bool>,
+
+    /// Allow protocol extensions (default: true)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allow_extension: Option<bool>,
+
+    /// Allow smartcard key add/remove (default: true)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allow_smartcard: Option<bool>,
+
+    /// Allow signing data that is neither an SSH login nor an SSHSIG
+    /// signature (default: false)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allow_unknown_sign: Option<bool>,
+}
+
+impl FailoverConfig {
+    /// Get the feature flags configured for sockets using this upstream
+    pub fn access_policy(&self) -> AccessPolicy {
+        AccessPolicy {
+            allow_add: self.allow_add,
+            allow_remove: self.allow_remove,
+            allow_lock: self.allow_lock,
+            allow_extension: self.allow_extension,
+            allow_smartcard: self.allow_smartcard,
+            allow_unknown_sign: self.allow_unknown_sign,
+        }
+    }
+}
+
+fn default_failover_on() -> Vec<FailoverCondition> {
+    FailoverCondition::ALL.to_vec()
+}
+
+/// Custom deserializer for a socket upstream:
+/// - `"path"` → single upstream
+/// - `["a", "b"]` → merged upstreams
+fn deserialize_upstream<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(s) => vec![s],
+        OneOrMany::Many(v) => v,
+    })
+}
+
+/// Custom serializer for a socket upstream (single upstream → string)
+fn serialize_upstream<S>(upstream: &Vec<String>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    if let [single] = upstream.as_slice() {
+        serializer.serialize_str(single)
+    } else {
+        upstream.serialize(serializer)
+    }
+}
+
 /// GitHub API configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
@@ -147,12 +634,39 @@ pub struct GithubConfig {
     pub timeout: String,
 }
 
+/// Upstream connection pool configuration
+///
+/// Pooled connections are only used for stateless requests (listing keys).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UpstreamPoolConfig {
+    /// Maximum number of idle connections per upstream (0 = disabled)
+    #[serde(default)]
+    pub size: usize,
+
+    /// Close idle connections after this duration
+    /// Format: "30s", "5m", etc.
+    #[serde(default = "default_pool_idle_timeout")]
+    pub idle_timeout: String,
+}
+
 impl Default for Config {
     fn default() -> Self {
         Self {
             upstream: default_upstream(),
+            upstreams: HashMap::new(),
             sockets: HashMap::new(),
             github: GithubConfig::default(),
+            pool: UpstreamPoolConfig::default(),
+        }
+    }
+}
+
+impl Default for UpstreamPoolConfig {
+    fn default() -> Self {
+        Self {
+            size: 0,
+            idle_timeout: default_pool_idle_timeout(),
         }
     }
 }
@@ -178,52 +692,177 @@ fn default_timeout() -> String {
     "10s".to_string()
 }
 
+fn default_pool_idle_timeout() -> String {
+    "30s".to_string()
+}
+
 impl Config {
     /// Expand environment variables and tilde in all paths
     pub fn expand_paths(&self) -> crate::Result<ExpandedConfig> {
-        let upstream = expand_path(&self.upstream)?;
+        let upstream = expand_target(&self.upstream)?;
 
         let mut sockets = HashMap::new();
         for (name, socket) in &self.sockets {
-            let socket_upstream = socket
-                .upstream
-                .as_ref()
-                .map(|u| expand_path(u))
-                .transpose()?
-                .map(PathBuf::from);
-
+            let socket_upstream = match socket.upstream.as_slice() {
+                [single] => vec![self.expand_upstream(single)?],
+                merged => merged
+                    .iter()
+                    .map(|u| {
+                        let (name, target) = self.expand_agent(u)?;
+                        Ok(ExpandedUpstream::new(name, target))
+                    })
+                    .collect::<crate::Result<Vec<_>>>()?,
+            };
+
+            let idle_lock = socket
+                .idle_lock
+                .as_deref()
+                .map(|value| expand_idle_lock(&socket.path, value))
+                .transpose()?;
             sockets.insert(
                 name.clone(),
                 ExpandedSocketConfig {
                     path: PathBuf::from(expand_path(&socket.path)?),
                     upstream: socket_upstream,
                     filters: socket.filters.clone(),
+                    access: self.socket_access_policy(socket),
+                    mode: socket.mode,
+                    local_lock: socket.local_lock.unwrap_or(false),
+                    idle_lock,
+                    idle_unlock_file: expand_idle_unlock_file(
+                        &socket.path,
+                        idle_lock,
+                        socket.idle_unlock_file.as_deref(),
+                    )?,
+                    confirm: socket.confirmation()?,
+                    permissions: socket.socket_permissions()?,
+                    peers: socket.peer_policy()?,
                 },
             );
         }
 
         Ok(ExpandedConfig {
-            upstream: PathBuf::from(upstream),
+            upstream,
             sockets,
             github: ExpandedGithubConfig {
                 cache_ttl: parse_duration(&self.github.cache_ttl)?,
                 timeout: parse_duration(&self.github.timeout)?,
             },
+            pool: PoolConfig {
+                max_size: self.pool.size,
+                idle_timeout: parse_duration(&self.pool.idle_timeout)?,
+            },
+        })
+    }
+}
+
+impl Config {
+    /// Get the feature flags of a socket, falling back to those of the
+    /// `[upstreams]` entries it uses
+    ///
+    /// Flags of merged upstreams are combined; a denial by any of them wins.
+    pub fn socket_access_policy(&self, socket: &SocketConfig) -> AccessPolicy {
+        let upstream = socket
+            .upstream
+            .iter()
+            .filter_map(|entry| self.upstreams.get(entry))
+            .map(UpstreamConfig::access_policy)
+            .reduce(AccessPolicy::and)
+            .unwrap_or_default();
+        socket.access_policy().or(upstream)
+    }
+
+    /// Resolve a socket upstream entry (a name from `[upstreams]` or a path)
+    fn expand_upstream(&self, entry: &str) -> crate::Result<ExpandedUpstream> {
+        let Some(UpstreamConfig::Failover(chain)) = self.upstreams.get(entry) else {
+            let (name, target) = self.expand_agent(entry)?;
+            return Ok(ExpandedUpstream::new(name, target));
+        };
+
+        let mut members = chain
+            .failover
+            .iter()
+            .map(|member| self.expand_agent(member))
+            .collect::<crate::Result<Vec<_>>>()?;
+        if members.is_empty() {
+            return Err(crate::Error::Config(format!(
+                "Failover chain '{}' has no upstreams",
+                entry
+            )));
+        }
+        let (_, target) = members.remove(0);
+        Ok(ExpandedUpstream {
+            name: entry.to_string(),
+            target,
+            fallbacks: members,
+            failover_on: chain.on.clone(),
+            timeout: chain.timeout.as_deref().map(parse_duration).transpose()?,
         })
     }
+
+    /// Resolve a single agent (a name of a path in `[upstreams]` or a path)
+    fn expand_agent(&self, entry: &str) -> crate::Result<(String, UpstreamTarget)> {
+        match self.upstreams.get(entry) {
+            Some(UpstreamConfig::Path(path)) => Ok((entry.to_string(), expand_target(path)?)),
+            Some(UpstreamConfig::Agent(agent)) => {
+                Ok((entry.to_string(), expand_target(&agent.path)?))
+            }
+            Some(UpstreamConfig::Failover(_)) => Err(crate::Error::Config(format!(
+                "Failover chain '{}' cannot be nested or merged with other upstreams",
+                entry
+            ))),
+            None => {
+                let target = expand_target(entry)?;
+                Ok((target.to_string(), target))
+            }
+        }
+    }
+}
+
+/// Expand an upstream spec (a socket path, `discover:PATTERN`, `command:COMMAND`,
+/// `preset:NAME` or `exec:COMMAND`)
+///
+/// Arguments of a command are expanded each time it runs.
+fn expand_target(spec: &str) -> crate::Result<UpstreamTarget> {
+    if [COMMAND_PREFIX, PRESET_PREFIX, EXEC_PREFIX]
+        .iter()
+        .any(|prefix| spec.starts_with(prefix))
+    {
+        return UpstreamTarget::parse(spec);
+    }
+    match spec.strip_prefix(DISCOVER_PREFIX) {
+        Some(pattern) => {
+            UpstreamTarget::parse(&format!("{}{}", DISCOVER_PREFIX, expand_path(pattern)?))
+        }
+        None => Ok(UpstreamTarget::Path(PathBuf::from(expand_path(spec)?))),
+    }
 }
 
 /// Configuration with all paths expanded
 #[derive(Debug, Clone)]
 pub struct ExpandedConfig {
-    /// Resolved path to the upstream SSH agent socket
-    pub upstream: PathBuf,
+    /// Location of the default upstream SSH agent socket
+    pub upstream: UpstreamTarget,
 
     /// Socket definitions with expanded paths
     pub sockets: HashMap<String, ExpandedSocketConfig>,
 
     /// GitHub API settings with parsed durations
     pub github: ExpandedGithubConfig,
+
+    /// Upstream connection pool settings (max_size 0 = disabled)
+    pub pool: PoolConfig,
+}
+
+impl ExpandedConfig {
+    /// Get the upstreams of a socket (the global upstream unless overridden)
+    pub fn socket_upstreams(&self, socket: &ExpandedSocketConfig) -> Vec<ExpandedUpstream> {
+        if socket.upstream.is_empty() {
+            vec![ExpandedUpstream::from_target(self.upstream.clone())]
+        } else {
+            socket.upstream.clone()
+        }
+    }
 }
 
 /// Socket configuration with expanded path
@@ -232,11 +871,77 @@ pub struct ExpandedSocketConfig {
     /// Resolved socket path
     pub path: PathBuf,
 
-    /// Resolved upstream path (if overridden for this socket)
-    pub upstream: Option<PathBuf>,
+    /// Resolved upstreams (empty = global upstream, several = merged)
+    pub upstream: Vec<ExpandedUpstream>,
 
     /// Filter rules for this socket (outer: OR, inner: AND)
     pub filters: Vec<Vec<String>>,
+
+    /// Feature flags for messages other than REQUEST_IDENTITIES / SIGN_REQUEST
+    pub access: AccessPolicy,
+
+    /// Whether listing and/or signing is served
+    pub mode: SocketMode,
+
+    /// Whether LOCK / UNLOCK lock this socket instead of the upstream agent
+    pub local_lock: bool,
+
+    /// Lock the socket after no successful sign for this long
+    pub idle_lock: Option<std::time::Duration>,
+
+    /// File holding the passphrase with which UNLOCK lifts the idle lock
+    pub idle_unlock_file: Option<PathBuf>,
+
+    /// Program asking the user before each signature
+    pub confirm: Option<Confirm>,
+
+    /// Mode and group of the socket file
+    pub permissions: SocketPermissions,
+
+    /// Users allowed to connect
+    pub peers: PeerPolicy,
+}
+
+/// Upstream agent with expanded socket path
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ExpandedUpstream {
+    /// Name used in logs and `upstream=` filters
+    pub name: String,
+
+    /// Location of the upstream socket
+    pub target: UpstreamTarget,
+
+    /// Agents to fail over to, in order (name, location)
+    pub fallbacks: Vec<(String, UpstreamTarget)>,
+
+    /// Conditions that trigger failover
+    pub failover_on: Vec<FailoverCondition>,
+
+    /// Request timeout (None = default)
+    pub timeout: Option<std::time::Duration>,
+}
+
+impl ExpandedUpstream {
+    /// Create an upstream without failover
+    pub fn new(name: String, target: UpstreamTarget) -> Self {
+        Self {
+            name,
+            target,
+            fallbacks: Vec::new(),
+            failover_on: Vec::new(),
+            timeout: None,
+        }
+    }
+
+    /// Create an upstream named after its location
+    pub fn from_target(target: UpstreamTarget) -> Self {
+        Self::new(target.to_string(), target)
+    }
+
+    /// Check if the socket of any agent exists
+    pub fn exists(&self) -> bool {
+        self.target.exists() || self.fallbacks.iter().any(|(_, target)| target.exists())
+    }
 }
 
 /// GitHub configuration with parsed durations
@@ -374,6 +1079,146 @@ mod tests {
         assert!(config.sockets.is_empty());
         assert_eq!(config.github.cache_ttl, "1h");
         assert_eq!(config.github.timeout, "10s");
+        assert_eq!(config.pool.size, 0);
+        assert_eq!(config.pool.idle_timeout, "30s");
+    }
+
+    #[test]
+    fn test_parse_merged_upstreams() {
+        let toml_str = r#"
+upstream = "/tmp/agent.sock"
+
+[upstreams]
+1password = "/tmp/1password.sock"
+
+[sockets.all]
+path = "/tmp/all.sock"
+upstream = ["/tmp/agent.sock", "1password"]
+
+[sockets.single]
+path = "/tmp/single.sock"
+upstream = "1password"
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        let expanded = config.expand_paths().unwrap();
+        assert_eq!(
+            expanded.sockets["all"].upstream,
+            vec![
+                ExpandedUpstream::from_target(PathBuf::from("/tmp/agent.sock").into()),
+                ExpandedUpstream::new(
+                    "1password".to_string(),
+                    PathBuf::from("/tmp/1password.sock").into()
+                ),
+            ]
+        );
+        assert_eq!(expanded.sockets["single"].upstream.len(), 1);
+
+        // A single upstream is written back as a string
+        let toml = toml::to_string(&config.sockets["single"]).unwrap();
+        assert!(toml.contains("upstream = \"1password\""));
+    }
+
+    #[test]
+    fn test_parse_failover_chain() {
+        let toml_str = r#"
+upstream = "/tmp/agent.sock"
+
+[upstreams]
+1password = "/tmp/1password.sock"
+signing = { failover = ["1password", "/tmp/agent.sock"], on = ["connect", "empty"], timeout = "5s" }
+
+[sockets.sign]
+path = "/tmp/sign.sock"
+upstream = "signing"
+
+[sockets.bad]
+path = "/tmp/bad.sock"
+upstream = ["signing", "1password"]
+"#;
+        let mut config: Config = toml::from_str(toml_str).unwrap();
+        // Chains cannot be merged with other upstreams
+        assert!(config.expand_paths().is_err());
+
+        config.sockets.remove("bad");
+        let expanded = config.expand_paths().unwrap();
+        let upstream = &expanded.sockets["sign"].upstream[0];
+        assert_eq!(upstream.name, "signing");
+        assert_eq!(upstream.target, PathBuf::from("/tmp/1password.sock").into());
+        assert_eq!(
+            upstream.fallbacks,
+            vec![(
+                "/tmp/agent.sock".to_string(),
+                PathBuf::from("/tmp/agent.sock").into()
+            )]
+        );
+        assert_eq!(
+            upstream.failover_on,
+            vec![FailoverCondition::Connect, FailoverCondition::Empty]
+        );
+        assert_eq!(upstream.timeout, Some(std::time::Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn test_parse_discover_upstream() {
+        let toml_str = r#"
+upstream = "discover:/tmp/ssh-*/agent.*"
+
+[upstreams]
+forwarded = "discover:/tmp/ssh-*/agent.*"
+
+[sockets.work]
+path = "/tmp/work.sock"
+upstream = "forwarded"
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        let expanded = config.expand_paths().unwrap();
+        let target = UpstreamTarget::Discover("/tmp/ssh-*/agent.*".to_string());
+        assert_eq!(expanded.upstream, target);
+        assert_eq!(
+            expanded.sockets["work"].upstream,
+            vec![ExpandedUpstream::new("forwarded".to_string(), target)]
+        );
+    }
+
+    #[test]
+    fn test_parse_command_upstream() {
+        let toml_str = r#"
+upstream = "command:gpgconf --list-dirs agent-ssh-socket"
+
+[upstreams]
+gpg = "command:gpgconf --homedir ~/.gnupg --list-dirs agent-ssh-socket"
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        let expanded = config.expand_paths().unwrap();
+        assert_eq!(
+            expanded.upstream,
+            UpstreamTarget::Command("gpgconf --list-dirs agent-ssh-socket".to_string())
+        );
+        let (name, target) = config.expand_agent("gpg").unwrap();
+        assert_eq!(name, "gpg");
+        // Arguments are expanded when the command runs
+        assert_eq!(
+            target.to_string(),
+            "command:gpgconf --homedir ~/.gnupg --list-dirs agent-ssh-socket"
+        );
+    }
+
+    #[test]
+    fn test_parse_pool_config() {
+        let toml_str = r#"
+upstream = "/tmp/agent.sock"
+
+[pool]
+size = 4
+idle_timeout = "1m"
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        let expanded = config.expand_paths().unwrap();
+        assert_eq!(expanded.pool.max_size, 4);
+        assert_eq!(
+            expanded.pool.idle_timeout,
+            std::time::Duration::from_secs(60)
+        );
     }
 
     #[test]
@@ -455,12 +1300,250 @@ filters = ["f1", "f2", ["f3", "f4"]]
     fn test_filters_empty_serialization() {
         let config = SocketConfig {
             path: "/tmp/test.sock".to_string(),
-            upstream: None,
-            filters: vec![],
+            ..Default::default()
         };
 
         let serialized = toml::to_string(&config).unwrap();
         let config2: SocketConfig = toml::from_str(&serialized).unwrap();
         assert_eq!(config.filters, config2.filters);
     }
+
+    #[test]
+    fn test_parse_feature_flags() {
+        let toml_str = r#"
+path = "/tmp/test.sock"
+allow_remove = false
+allow_lock = false
+"#;
+
+        let config: SocketConfig = toml::from_str(toml_str).unwrap();
+        let access = config.access_policy();
+        assert_eq!(access.allow_add, None);
+        assert_eq!(access.allow_remove, Some(false));
+        assert_eq!(access.allow_lock, Some(false));
+
+        // Unset flags are not serialized
+        let serialized = toml::to_string(&config).unwrap();
+        assert!(serialized.contains("allow_remove = false"));
+        assert!(!serialized.contains("allow_add"));
+    }
+
+    #[test]
+    fn test_parse_upstream_feature_flags() {
+        let toml_str = r#"
+upstream = "/tmp/agent.sock"
+
+[upstreams]
+work = { path = "/tmp/work.sock", allow_lock = false, allow_remove = false }
+personal = { path = "/tmp/personal.sock", allow_lock = true, allow_add = false }
+signing = { failover = ["work", "/tmp/agent.sock"], allow_extension = false }
+
+[sockets.work]
+path = "/tmp/w.sock"
+upstream = "work"
+allow_remove = true
+
+[sockets.both]
+path = "/tmp/both.sock"
+upstream = ["work", "personal"]
+
+[sockets.sign]
+path = "/tmp/sign.sock"
+upstream = "signing"
+"#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        let expanded = config.expand_paths().unwrap();
+
+        // Socket flags override upstream flags
+        let work = &expanded.sockets["work"];
+        assert_eq!(
+            work.upstream[0].target,
+            PathBuf::from("/tmp/work.sock").into()
+        );
+        assert_eq!(work.access.allow_lock, Some(false));
+        assert_eq!(work.access.allow_remove, Some(true));
+
+        // A denial by any merged upstream wins
+        let both = &expanded.sockets["both"].access;
+        assert_eq!(both.allow_lock, Some(false));
+        assert_eq!(both.allow_add, Some(false));
+        assert_eq!(both.allow_remove, Some(false));
+        assert_eq!(both.allow_extension, None);
+
+        assert_eq!(expanded.sockets["sign"].access.allow_extension, Some(false));
+        assert_eq!(expanded.sockets["sign"].access.allow_lock, None);
+    }
+
+    #[test]
+    fn test_parse_socket_mode() {
+        let config: SocketConfig = toml::from_str("path = \"/tmp/a.sock\"").unwrap();
+        assert_eq!(config.mode, SocketMode::Full);
+        assert!(!toml::to_string(&config).unwrap().contains("mode"));
+
+        let config: SocketConfig =
+            toml::from_str("path = \"/tmp/a.sock\"\nmode = \"sign-only\"").unwrap();
+        assert_eq!(config.mode, SocketMode::SignOnly);
+        assert!(
+            toml::to_string(&config)
+                .unwrap()
+                .contains("mode = \"sign-only\"")
+        );
+
+        let result: Result<SocketConfig, _> =
+            toml::from_str("path = \"/tmp/a.sock\"\nmode = \"readonly\"");
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_parse_local_lock() {
+        let config: SocketConfig = toml::from_str("path = \"/tmp/a.sock\"").unwrap();
+        assert_eq!(config.local_lock, None);
+        assert!(!toml::to_string(&config).unwrap().contains("local_lock"));
+
+        let config: SocketConfig =
+            toml::from_str("path = \"/tmp/a.sock\"\nlocal_lock = true").unwrap();
+        assert_eq!(config.local_lock, Some(true));
+    }
+
+    #[test]
+    fn test_parse_idle_lock() {
+        let config: SocketConfig =
+            toml::from_str("path = \"/tmp/a.sock\"\nidle_lock = \"15m\"").unwrap();
+        assert_eq!(config.idle_lock.as_deref(), Some("15m"));
+        assert_eq!(
+            expand_idle_lock(&config.path, "15m").unwrap(),
+            std::time::Duration::from_secs(900)
+        );
+        assert!(expand_idle_lock(&config.path, "0s").is_err());
+        assert!(expand_idle_lock(&config.path, "soon").is_err());
+    }
+
+    #[test]
+    fn test_idle_unlock_file() {
+        let config: SocketConfig = toml::from_str(
+            "path = \"/tmp/a.sock\"\nidle_lock = \"15m\"\nidle_unlock_file = \"/tmp/unlock\"",
+        )
+        .unwrap();
+        assert_eq!(config.idle_unlock_file.as_deref(), Some("/tmp/unlock"));
+        let idle_lock = Some(std::time::Duration::from_secs(900));
+        assert_eq!(
+            expand_idle_unlock_file(&config.path, idle_lock, Some("/tmp/unlock")).unwrap(),
+            Some(PathBuf::from("/tmp/unlock"))
+        );
+        assert!(expand_idle_unlock_file(&config.path, None, Some("/tmp/unlock")).is_err());
+
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("unlock");
+        std::fs::write(&file, "resume me\nignored\n").unwrap();
+        std::fs::set_permissions(&file, std::fs::Permissions::from_mode(0o600)).unwrap();
+        assert_eq!(read_idle_passphrase(&file).unwrap(), b"resume me");
+        std::fs::set_permissions(&file, std::fs::Permissions::from_mode(0o644)).unwrap();
+        assert!(read_idle_passphrase(&file).is_err());
+        std::fs::write(&file, "\n").unwrap();
+        std::fs::set_permissions(&file, std::fs::Permissions::from_mode(0o600)).unwrap();
+        assert!(read_idle_passphrase(&file).is_err());
+        assert!(read_idle_passphrase(&dir.path().join("missing")).is_err());
+    }
+
+    #[test]
+    fn test_parse_confirm() {
+        let config: SocketConfig = toml::from_str("path = \"/tmp/a.sock\"").unwrap();
+        assert_eq!(config.confirmation().unwrap(), None);
+
+        let config: SocketConfig =
+            toml::from_str("path = \"/tmp/a.sock\"\nconfirm = true").unwrap();
+        assert_eq!(config.confirmation().unwrap(), Some(Confirm::askpass()));
+
+        // A program implies confirm, unless it is switched off
+        let config: SocketConfig =
+            toml::from_str("path = \"/tmp/a.sock\"\nconfirm_program = \"zenity --question\"")
+                .unwrap();
+        assert_eq!(
+            config.confirmation().unwrap(),
+            Some(Confirm::with_program("zenity --question").unwrap())
+        );
+        assert_eq!(
+            expand_confirm(Some(false), Some("zenity --question")).unwrap(),
+            None
+        );
+        assert!(expand_confirm(None, Some("'unterminated")).is_err());
+    }
+
+    #[test]
+    fn test_parse_socket_permissions() {
+        let config: SocketConfig = toml::from_str("path = \"/tmp/a.sock\"").unwrap();
+        assert_eq!(
+            config.socket_permissions().unwrap(),
+            SocketPermissions::default()
+        );
+
+        let config: SocketConfig = toml::from_str(
+            "path = \"/tmp/a.sock\"\npermissions = \"0660\"\ngroup = \"1234\"\ndir_permissions = \"0750\"",
+        )
+        .unwrap();
+        assert_eq!(
+            config.socket_permissions().unwrap(),
+            SocketPermissions {
+                mode: 0o660,
+                group: Some(1234),
+                dir_mode: Some(0o750),
+            }
+        );
+
+        // World-accessible sockets need force_permissions
+        let mut config: SocketConfig =
+            toml::from_str("path = \"/tmp/a.sock\"\npermissions = \"0666\"").unwrap();
+        assert!(config.socket_permissions().is_err());
+        config.force_permissions = Some(true);
+        assert_eq!(config.socket_permissions().unwrap().mode, 0o666);
+
+        let config: SocketConfig =
+            toml::from_str("path = \"/tmp/a.sock\"\ndir_permissions = \"0777\"").unwrap();
+        assert!(config.socket_permissions().is_err());
+
+        let config: SocketConfig =
+            toml::from_str("path = \"/tmp/a.sock\"\npermissions = \"rw\"").unwrap();
+        assert!(config.socket_permissions().is_err());
+
+        let config: SocketConfig =
+            toml::from_str("path = \"/tmp/a.sock\"\ngroup = \"no-such-group-authsock\"").unwrap();
+        assert!(config.socket_permissions().is_err());
+
+        let config: SocketConfig =
+            toml::from_str("path = \"@work\"\npermissions = \"0660\"").unwrap();
+        assert!(config.socket_permissions().is_err());
+    }
+
+    #[test]
+    fn test_parse_allowed_peers() {
+        let config: SocketConfig = toml::from_str("path = \"/tmp/a.sock\"").unwrap();
+        assert_eq!(config.peer_policy().unwrap(), PeerPolicy::default());
+
+        let config: SocketConfig = toml::from_str(
+            "path = \"/tmp/a.sock\"\nallowed_uids = [1001]\nallowed_groups = [\"2002\"]",
+        )
+        .unwrap();
+        assert_eq!(
+            config.peer_policy().unwrap(),
+            PeerPolicy {
+                allowed_uids: vec![1001],
+                allowed_gids: vec![2002],
+            }
+        );
+
+        let config: SocketConfig =
+            toml::from_str("path = \"/tmp/a.sock\"\nallowed_groups = [\"no-such-group-authsock\"]")
+                .unwrap();
+        assert!(config.peer_policy().is_err());
+
+        // The socket's group is allowed by default
+        let config: SocketConfig = toml::from_str(
+            "path = \"/tmp/a.sock\"\ngroup = \"2003\"\nallowed_groups = [\"2002\", \"2003\"]",
+        )
+        .unwrap();
+        assert_eq!(config.peer_policy().unwrap().allowed_gids, vec![2002, 2003]);
+        let config: SocketConfig =
+            toml::from_str("path = \"/tmp/a.sock\"\ngroup = \"2003\"").unwrap();
+        assert_eq!(config.peer_policy().unwrap().allowed_gids, vec![2003]);
+    }
 }
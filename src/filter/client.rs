@@ -0,0 +1,154 @@
+//! Client process matching filters (executable, ancestry)
+
+use crate::error::Result;
+use crate::filter::{FilterContext, Pattern, ProcessInfo};
+
+/// Client process attribute inspected by a [`ClientMatcher`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientField {
+    /// Executable of the connected process (`client-exe=`)
+    Exe,
+    /// Executable of any parent, grandparent, ... (`client-ancestor=`)
+    Ancestor,
+}
+
+impl ClientField {
+    /// Get the filter term name
+    pub fn name(&self) -> &'static str {
+        match self {
+            ClientField::Exe => "client-exe",
+            ClientField::Ancestor => "client-ancestor",
+        }
+    }
+}
+
+/// Matcher for the process connected to the socket
+///
+/// A pattern containing `/` is matched against the full executable path,
+/// any other pattern against the executable's file name. Command names and
+/// arguments are not matched, as a process can set them freely.
+///
+/// If the process cannot be inspected (no pid, another user's process, or a
+/// platform without /proc), the filter is undecided and fails closed.
+#[derive(Debug, Clone)]
+pub struct ClientMatcher {
+    /// The attribute to match
+    field: ClientField,
+    /// The pattern to match against
+    pattern: Pattern,
+}
+
+impl ClientMatcher {
+    /// Create a new client matcher (pattern: exact, `*glob*` or `~regex`)
+    pub fn new(field: ClientField, pattern: &str) -> Result<Self> {
+        Ok(Self {
+            field,
+            pattern: Pattern::new(pattern)?,
+        })
+    }
+
+    /// Get the attribute being matched
+    pub fn field(&self) -> ClientField {
+        self.field
+    }
+
+    /// Get the pattern being matched
+    pub fn pattern(&self) -> &str {
+        self.pattern.as_str()
+    }
+
+    /// Evaluate against the request context
+    ///
+    /// Returns `None` if the client process, or an executable that could
+    /// decide the result, is unknown.
+    pub fn evaluate(&self, ctx: &FilterContext) -> Option<bool> {
+        let client = ctx.client?;
+        match self.field {
+            ClientField::Exe => self.matches(&client.process),
+            ClientField::Ancestor => {
+                let mut unknown = false;
+                for ancestor in &client.ancestors {
+                    match self.matches(ancestor) {
+                        Some(true) => return Some(true),
+                        Some(false) => {}
+                        None => unknown = true,
+                    }
+                }
+                (!unknown).then_some(false)
+            }
+        }
+    }
+
+    fn matches(&self, process: &ProcessInfo) -> Option<bool> {
+        let exe = process.exe.as_deref()?;
+        if self.pattern.as_str().contains('/') {
+            Some(self.pattern.is_match(&exe.to_string_lossy()))
+        } else {
+            Some(process.exe_name().is_some_and(|n| self.pattern.is_match(n)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::ClientProcess;
+    use std::path::PathBuf;
+
+    fn process(pid: i32, exe: Option<&str>) -> ProcessInfo {
+        ProcessInfo {
+            pid,
+            exe: exe.map(PathBuf::from),
+            cmdline: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_client_exe() {
+        let client = ClientProcess {
+            process: process(100, Some("/usr/bin/git")),
+            ancestors: Vec::new(),
+        };
+        let ctx = FilterContext::default().with_client(Some(&client));
+        let eval = |pattern| {
+            ClientMatcher::new(ClientField::Exe, pattern)
+                .unwrap()
+                .evaluate(&ctx)
+        };
+        assert_eq!(eval("/usr/bin/git"), Some(true));
+        assert_eq!(eval("git"), Some(true));
+        assert_eq!(eval("/usr/local/bin/git"), Some(false));
+        assert_eq!(eval("ssh"), Some(false));
+
+        assert_eq!(
+            ClientMatcher::new(ClientField::Exe, "git")
+                .unwrap()
+                .evaluate(&FilterContext::default()),
+            None
+        );
+    }
+
+    #[test]
+    fn test_client_ancestor() {
+        let client = ClientProcess {
+            process: process(100, Some("/usr/bin/ssh")),
+            ancestors: vec![
+                process(90, Some("/usr/bin/git")),
+                process(80, None),
+                process(70, Some("/usr/share/code/code")),
+            ],
+        };
+        let ctx = FilterContext::default().with_client(Some(&client));
+        let eval = |pattern| {
+            ClientMatcher::new(ClientField::Ancestor, pattern)
+                .unwrap()
+                .evaluate(&ctx)
+        };
+        assert_eq!(eval("code"), Some(true));
+        assert_eq!(eval("git"), Some(true));
+        // An unreadable ancestor might have been the one
+        assert_eq!(eval("tmux"), None);
+        // The connected process itself is not its own ancestor
+        assert_eq!(eval("ssh"), None);
+    }
+}
@@ -1,28 +1,14 @@
 //! Comment matching filter
 
-use crate::error::{Error, Result};
+use crate::error::Result;
+use crate::filter::Pattern;
 use crate::protocol::Identity;
-use globset::{Glob, GlobMatcher};
-use regex::Regex;
-
-/// Type of comment matching
-#[derive(Debug, Clone)]
-enum MatchType {
-    /// Exact string match
-    Exact(String),
-    /// Glob pattern match
-    Glob(GlobMatcher),
-    /// Regular expression match
-    Regex(Regex),
-}
 
 /// Matcher for SSH key comments
 #[derive(Debug, Clone)]
 pub struct CommentMatcher {
-    /// The original pattern string
-    pattern: String,
-    /// The match type
-    match_type: MatchType,
+    /// The comment pattern
+    pattern: Pattern,
 }
 
 impl CommentMatcher {
@@ -33,40 +19,19 @@ impl CommentMatcher {
     /// - `*glob*` - glob pattern (if contains * or ?)
     /// - `exact` - exact match
     pub fn new(pattern: &str) -> Result<Self> {
-        let match_type = if let Some(regex_pattern) = pattern.strip_prefix('~') {
-            // Regex pattern
-            let regex = Regex::new(regex_pattern).map_err(|e| {
-                Error::Filter(format!("Invalid regex pattern '{}': {}", regex_pattern, e))
-            })?;
-            MatchType::Regex(regex)
-        } else if pattern.contains('*') || pattern.contains('?') {
-            // Glob pattern
-            let glob = Glob::new(pattern)
-                .map_err(|e| Error::Filter(format!("Invalid glob pattern '{}': {}", pattern, e)))?;
-            MatchType::Glob(glob.compile_matcher())
-        } else {
-            // Exact match
-            MatchType::Exact(pattern.to_string())
-        };
-
         Ok(Self {
-            pattern: pattern.to_string(),
-            match_type,
+            pattern: Pattern::new(pattern)?,
         })
     }
 
     /// Get the pattern being matched
     pub fn pattern(&self) -> &str {
-        &self.pattern
+        self.pattern.as_str()
     }
 
     /// Check if this matcher matches the given identity
     pub fn matches(&self, identity: &Identity) -> bool {
-        match &self.match_type {
-            MatchType::Exact(s) => identity.comment == *s,
-            MatchType::Glob(g) => g.is_match(&identity.comment),
-            MatchType::Regex(r) => r.is_match(&identity.comment),
-        }
+        self.pattern.is_match(&identity.comment)
     }
 }
 
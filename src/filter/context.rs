@@ -0,0 +1,75 @@
+//! Request context available to filters beyond the key itself
+
+use crate::filter::{ClientProcess, KnownHost};
+use crate::protocol::{SignPayload, SignRequest};
+
+/// Information about the request a filter is evaluated for
+///
+/// Filters that depend on information absent from the context (e.g., sign
+/// request terms while listing keys) are undecided. Undecided filters do
+/// not restrict key listing, but a sign request is only allowed if every
+/// filter can be decided. Client process filters are the exception: they
+/// fail closed when undecided, for listing as well.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FilterContext<'a> {
+    /// Details of the sign request (None when listing keys)
+    pub sign: Option<&'a SignContext>,
+    /// Server the client connection is bound to (session-bind@openssh.com)
+    pub host: Option<&'a KnownHost>,
+    /// Name of the upstream agent holding the key
+    pub upstream: Option<&'a str>,
+    /// Process connected to the socket (None if it cannot be inspected)
+    pub client: Option<&'a ClientProcess>,
+}
+
+impl<'a> FilterContext<'a> {
+    /// Create a context for evaluating a sign request
+    pub fn for_sign(sign: &'a SignContext) -> Self {
+        Self {
+            sign: Some(sign),
+            ..Default::default()
+        }
+    }
+
+    /// Set the destination host
+    pub fn with_host(mut self, host: Option<&'a KnownHost>) -> Self {
+        self.host = host;
+        self
+    }
+
+    /// Set the name of the upstream agent holding the key
+    pub fn with_upstream(mut self, upstream: Option<&'a str>) -> Self {
+        self.upstream = upstream;
+        self
+    }
+
+    /// Set the process connected to the socket
+    pub fn with_client(mut self, client: Option<&'a ClientProcess>) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Check if this context is for a sign request
+    pub fn is_sign(&self) -> bool {
+        self.sign.is_some()
+    }
+}
+
+/// Details of the data being signed
+#[derive(Debug, Clone)]
+pub struct SignContext {
+    /// Classified data to be signed
+    pub payload: SignPayload,
+    /// Signature algorithm the agent will use
+    pub algorithm: Option<String>,
+}
+
+impl SignContext {
+    /// Classify a parsed sign request
+    pub fn new(request: &SignRequest) -> Self {
+        Self {
+            payload: request.payload(),
+            algorithm: request.signature_algorithm(),
+        }
+    }
+}
@@ -1,7 +1,7 @@
 //! Filter evaluation engine
 
 use crate::error::Result;
-use crate::filter::{Filter, FilterRule};
+use crate::filter::{Filter, FilterContext, FilterRule};
 use crate::protocol::Identity;
 
 /// A group of rules that are ANDed together
@@ -23,11 +23,16 @@ impl FilterGroup {
 
     /// Check if all rules match the given identity (AND logic)
     pub fn matches(&self, identity: &Identity) -> bool {
+        self.matches_with(identity, &FilterContext::default())
+    }
+
+    /// Check if all rules match the given identity in a request context
+    pub fn matches_with(&self, identity: &Identity, ctx: &FilterContext) -> bool {
         // Empty rules = match all
         if self.rules.is_empty() {
             return true;
         }
-        self.rules.iter().all(|r| r.matches(identity))
+        self.rules.iter().all(|r| r.matches_with(identity, ctx))
     }
 
     /// Get rules for inspection
@@ -61,11 +66,16 @@ impl FilterEvaluator {
 
     /// Check if any group matches the given identity (OR logic between groups)
     pub fn matches(&self, identity: &Identity) -> bool {
+        self.matches_with(identity, &FilterContext::default())
+    }
+
+    /// Check if any group matches the given identity in a request context
+    pub fn matches_with(&self, identity: &Identity, ctx: &FilterContext) -> bool {
         // Empty groups = match all
         if self.groups.is_empty() {
             return true;
         }
-        self.groups.iter().any(|g| g.matches(identity))
+        self.groups.iter().any(|g| g.matches_with(identity, ctx))
     }
 
     /// Filter a list of identities
@@ -208,4 +218,40 @@ mod tests {
         assert!(!evaluator.matches(&make_identity("kawaz-rsa"))); // only f1
         assert!(!evaluator.matches(&make_identity("other"))); // none
     }
+
+    #[test]
+    fn test_sign_terms_enforced_at_sign_time() {
+        use crate::filter::SignContext;
+        use crate::protocol::{SignPayload, UserAuthRequest};
+
+        let sign_as = |user: &str| SignContext {
+            payload: SignPayload::UserAuth(UserAuthRequest {
+                session_id: Bytes::new(),
+                user: user.to_string(),
+                service: "ssh-connection".to_string(),
+                method: "publickey".to_string(),
+                algorithm: "ssh-ed25519".to_string(),
+                key_blob: Bytes::new(),
+                host_key: None,
+            }),
+            algorithm: Some("ssh-ed25519".to_string()),
+        };
+        let deploy = sign_as("deploy");
+        let root = sign_as("root");
+
+        // Work keys may only log in as deploy
+        let evaluator = FilterEvaluator::parse(&[vec![
+            "comment=*@work".to_string(),
+            "ssh-user=deploy".to_string(),
+        ]])
+        .unwrap();
+        let key = make_identity("me@work");
+
+        assert!(evaluator.matches(&key));
+        assert!(evaluator.matches_with(&key, &FilterContext::for_sign(&deploy)));
+        assert!(!evaluator.matches_with(&key, &FilterContext::for_sign(&root)));
+        assert!(
+            !evaluator.matches_with(&make_identity("me@home"), &FilterContext::for_sign(&deploy))
+        );
+    }
 }
@@ -0,0 +1,113 @@
+//! Destination host matching filter
+
+use crate::error::Result;
+use crate::filter::{FilterContext, Pattern};
+
+/// Matcher for the server a client connection is bound to
+///
+/// The destination is taken from session-bind@openssh.com and resolved to
+/// host names via known_hosts. Requests without a known destination, to a
+/// host missing from known_hosts, or whose hashed known_hosts names a glob or
+/// regex cannot be checked against, are undecided: keys are still listed, but
+/// signing is refused (with `not-host=` too).
+#[derive(Debug, Clone)]
+pub struct HostMatcher {
+    /// The host name pattern to match
+    pattern: Pattern,
+}
+
+impl HostMatcher {
+    /// Create a new host matcher (pattern: exact, `*glob*` or `~regex`)
+    pub fn new(pattern: &str) -> Result<Self> {
+        Ok(Self {
+            pattern: Pattern::new(pattern)?,
+        })
+    }
+
+    /// Get the pattern being matched
+    pub fn pattern(&self) -> &str {
+        self.pattern.as_str()
+    }
+
+    /// Evaluate against the request context
+    ///
+    /// Returns `None` if the destination host is unknown or cannot be
+    /// matched against the pattern.
+    pub fn evaluate(&self, ctx: &FilterContext) -> Option<bool> {
+        ctx.host.and_then(|host| host.matches(&self.pattern))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::KnownHost;
+    use ssh_key::PublicKey;
+    use std::io::Write;
+
+    #[test]
+    fn test_host_match() {
+        let key =
+            "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIOMqqnkVzrm0SdG6UOoqKLsabgH5C9okWi0dh2l9GKJl";
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "github.com {}", key).unwrap();
+        let blob = key.parse::<PublicKey>().unwrap().to_bytes().unwrap();
+        let host = KnownHost::resolve_in(&blob, &[file.path().to_path_buf()]);
+
+        let ctx = FilterContext::default().with_host(Some(&host));
+        assert_eq!(
+            HostMatcher::new("github.com").unwrap().evaluate(&ctx),
+            Some(true)
+        );
+        assert_eq!(
+            HostMatcher::new("*.example.com").unwrap().evaluate(&ctx),
+            Some(false)
+        );
+        assert_eq!(
+            HostMatcher::new("github.com")
+                .unwrap()
+                .evaluate(&FilterContext::default()),
+            None
+        );
+    }
+
+    #[test]
+    fn test_negated_glob_against_hashed_host_fails_closed() {
+        use crate::filter::{FilterRule, SignContext};
+        use crate::protocol::{Identity, SignPayload};
+        use bytes::Bytes;
+
+        let key =
+            "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIOMqqnkVzrm0SdG6UOoqKLsabgH5C9okWi0dh2l9GKJl";
+        let blob = key.parse::<PublicKey>().unwrap().to_bytes().unwrap();
+        // "prod.example.com" hashed by ssh-keygen -H
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(
+            file,
+            "|1|muA0PQgmanHx3DMFR1kSqfmaCOA=|4XWudsAyjTlGdAxSqwvUN+5OFSI= {}",
+            key
+        )
+        .unwrap();
+        let hashed = KnownHost::resolve_in(&blob, &[file.path().to_path_buf()]);
+        let unknown = KnownHost::resolve_in(&blob, &[]);
+
+        let rule = FilterRule::parse("not-host=*.example.com").unwrap();
+        let identity = Identity::new(Bytes::new(), "me@prod".to_string());
+        let sign = SignContext {
+            payload: SignPayload::Unknown,
+            algorithm: None,
+        };
+        for host in [&hashed, &unknown] {
+            let ctx = FilterContext::for_sign(&sign).with_host(Some(host));
+            assert_eq!(rule.filter.evaluate(&identity, &ctx), None);
+            assert!(!rule.matches_with(&identity, &ctx));
+        }
+
+        // The exact name is still checked against the hash
+        let ctx = FilterContext::for_sign(&sign).with_host(Some(&hashed));
+        let rule = FilterRule::parse("not-host=prod.example.com").unwrap();
+        assert!(!rule.matches_with(&identity, &ctx));
+        let rule = FilterRule::parse("not-host=dev.example.com").unwrap();
+        assert!(rule.matches_with(&identity, &ctx));
+    }
+}
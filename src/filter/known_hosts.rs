@@ -0,0 +1,194 @@
+//! Resolution of server host keys to host names via known_hosts files
+
+use crate::filter::Pattern;
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use ssh_key::PublicKey;
+use ssh_key::known_hosts::{Entry, HostPatterns};
+use std::path::PathBuf;
+
+/// A server host key together with the names it is known by
+#[derive(Debug, Clone)]
+pub struct KnownHost {
+    /// SHA256 fingerprint of the host key
+    fingerprint: String,
+    /// Host names (and patterns) of matching known_hosts entries
+    names: Vec<String>,
+    /// Hashed host names (salt, HMAC-SHA1) of matching known_hosts entries
+    hashed: Vec<(Vec<u8>, [u8; 20])>,
+}
+
+impl KnownHost {
+    /// Get the known_hosts files consulted by default (as ssh(1) does)
+    pub fn default_files() -> Vec<PathBuf> {
+        let mut files = Vec::new();
+        if let Some(home) = dirs::home_dir() {
+            files.push(home.join(".ssh/known_hosts"));
+            files.push(home.join(".ssh/known_hosts2"));
+        }
+        files.push(PathBuf::from("/etc/ssh/ssh_known_hosts"));
+        files.push(PathBuf::from("/etc/ssh/ssh_known_hosts2"));
+        files
+    }
+
+    /// Resolve a host key blob against the default known_hosts files
+    pub fn resolve(host_key: &[u8]) -> Self {
+        Self::resolve_in(host_key, &Self::default_files())
+    }
+
+    /// Resolve a host key blob against the given known_hosts files
+    ///
+    /// Missing files and unparsable lines are skipped. Revoked and
+    /// certificate authority entries are ignored.
+    pub fn resolve_in(host_key: &[u8], files: &[PathBuf]) -> Self {
+        let key = PublicKey::from_bytes(host_key).ok();
+        let mut host = Self {
+            fingerprint: key
+                .as_ref()
+                .map(|k| k.fingerprint(Default::default()).to_string())
+                .unwrap_or_default(),
+            names: Vec::new(),
+            hashed: Vec::new(),
+        };
+        let Some(key) = key else {
+            return host;
+        };
+
+        for file in files {
+            let Ok(content) = std::fs::read_to_string(file) else {
+                continue;
+            };
+            for line in content.lines() {
+                let line = line.trim();
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                let Ok(entry) = line.parse::<Entry>() else {
+                    continue;
+                };
+                if entry.marker().is_some() || entry.public_key().key_data() != key.key_data() {
+                    continue;
+                }
+                host.add_patterns(entry.host_patterns());
+            }
+        }
+        host
+    }
+
+    fn add_patterns(&mut self, patterns: &HostPatterns) {
+        match patterns {
+            HostPatterns::Patterns(patterns) => {
+                for pattern in patterns.iter().filter(|p| !p.starts_with('!')) {
+                    // "[host]:port" is also known by its plain host name
+                    if let Some(plain) = pattern
+                        .strip_prefix('[')
+                        .and_then(|p| p.split_once("]:"))
+                        .map(|(h, _)| h)
+                    {
+                        self.names.push(plain.to_string());
+                    }
+                    self.names.push(pattern.clone());
+                }
+            }
+            HostPatterns::HashedName { salt, hash } => {
+                self.hashed.push((salt.clone(), *hash));
+            }
+        }
+    }
+
+    /// Get the SHA256 fingerprint of the host key
+    pub fn fingerprint(&self) -> &str {
+        &self.fingerprint
+    }
+
+    /// Get the plain host names the key is known by
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// Check if any known_hosts entry matched the key
+    pub fn is_known(&self) -> bool {
+        !self.names.is_empty() || !self.hashed.is_empty()
+    }
+
+    /// Check if the host is known by a name matching the pattern
+    ///
+    /// Hashed known_hosts entries can only be matched by an exact host name.
+    /// Returns `None` if that cannot be decided: the host is not in
+    /// known_hosts, or a glob or regex meets hashed entries (which may hide a
+    /// matching name).
+    pub fn matches(&self, pattern: &Pattern) -> Option<bool> {
+        if !self.is_known() {
+            return None;
+        }
+        if self.names.iter().any(|name| pattern.is_match(name)) {
+            return Some(true);
+        }
+        if self.hashed.is_empty() {
+            return Some(false);
+        }
+        let name = pattern.literal()?;
+        Some(
+            self.hashed
+                .iter()
+                .any(|(salt, hash)| hashed_name_matches(salt, hash, name)),
+        )
+    }
+}
+
+/// Check a host name against a hashed known_hosts name (HMAC-SHA1)
+fn hashed_name_matches(salt: &[u8], hash: &[u8; 20], name: &str) -> bool {
+    let Ok(mut mac) = Hmac::<Sha1>::new_from_slice(salt) else {
+        return false;
+    };
+    mac.update(name.as_bytes());
+    mac.verify_slice(hash).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    const HOST_KEY: &str =
+        "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIOMqqnkVzrm0SdG6UOoqKLsabgH5C9okWi0dh2l9GKJl";
+
+    fn host_key_blob() -> Vec<u8> {
+        HOST_KEY.parse::<PublicKey>().unwrap().to_bytes().unwrap()
+    }
+
+    #[test]
+    fn test_resolve_plain_and_hashed() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "# comment").unwrap();
+        writeln!(file, "github.com,140.82.112.3 {}", HOST_KEY).unwrap();
+        writeln!(file, "[git.example.com]:2222 {}", HOST_KEY).unwrap();
+        // "prod.example.com" hashed by ssh-keygen -H
+        writeln!(
+            file,
+            "|1|muA0PQgmanHx3DMFR1kSqfmaCOA=|4XWudsAyjTlGdAxSqwvUN+5OFSI= {}",
+            HOST_KEY
+        )
+        .unwrap();
+        writeln!(file, "other.example.com ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIHUu2eEV0kRvK3dMRlSFwHxVoNxCfwjKmAZBlhkNjC4i").unwrap();
+
+        let host = KnownHost::resolve_in(&host_key_blob(), &[file.path().to_path_buf()]);
+        assert!(host.is_known());
+        assert!(host.fingerprint().starts_with("SHA256:"));
+        let matches = |pattern: &str| host.matches(&Pattern::new(pattern).unwrap());
+        assert_eq!(matches("github.com"), Some(true));
+        assert_eq!(matches("git.example.com"), Some(true));
+        assert_eq!(matches("prod.example.com"), Some(true));
+        assert_eq!(matches("other.example.com"), Some(false));
+        // A plain name decides a glob, but hashed names may hide a match
+        assert_eq!(matches("git.*"), Some(true));
+        assert_eq!(matches("prod.*"), None);
+    }
+
+    #[test]
+    fn test_resolve_unknown() {
+        let host = KnownHost::resolve_in(&host_key_blob(), &[PathBuf::from("/nonexistent")]);
+        assert!(!host.is_known());
+        assert_eq!(host.matches(&Pattern::new("*").unwrap()), None);
+    }
+}
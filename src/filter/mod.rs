@@ -7,22 +7,42 @@
 //! - Public key matching
 //! - Keyfile matching (authorized_keys format)
 //! - GitHub user keys matching
+//! - Sign request matching (remote user, service, algorithm, SSHSIG namespace)
+//! - Destination host matching (session-bind and known_hosts)
+//! - Source upstream agent matching
+//! - Client process matching (executable, ancestry)
 //! - Negation
 
+mod client;
 mod comment;
+mod context;
 mod evaluator;
 mod fingerprint;
 mod github;
+mod host;
 mod keyfile;
 mod keytype;
+mod known_hosts;
+mod pattern;
+mod process;
 mod pubkey;
 mod rule;
+mod sign;
+mod upstream;
 
+pub use client::{ClientField, ClientMatcher};
 pub use comment::CommentMatcher;
+pub use context::{FilterContext, SignContext};
 pub use evaluator::FilterEvaluator;
 pub use fingerprint::FingerprintMatcher;
 pub use github::GitHubKeysMatcher;
+pub use host::HostMatcher;
 pub use keyfile::KeyfileMatcher;
 pub use keytype::KeyTypeMatcher;
+pub use known_hosts::KnownHost;
+pub use pattern::Pattern;
+pub use process::{ClientProcess, ProcessInfo};
 pub use pubkey::PubkeyMatcher;
 pub use rule::{Filter, FilterRule};
+pub use sign::{SignField, SignMatcher};
+pub use upstream::UpstreamMatcher;
@@ -0,0 +1,99 @@
+//! String pattern matching shared by text-based filters
+
+use crate::error::{Error, Result};
+use globset::{Glob, GlobMatcher};
+use regex::Regex;
+
+/// Type of pattern matching
+#[derive(Debug, Clone)]
+enum MatchType {
+    /// Exact string match
+    Exact(String),
+    /// Glob pattern match
+    Glob(GlobMatcher),
+    /// Regular expression match
+    Regex(Regex),
+}
+
+/// A string pattern (exact, glob or regex)
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    /// The original pattern string
+    pattern: String,
+    /// The match type
+    match_type: MatchType,
+}
+
+impl Pattern {
+    /// Create a new pattern
+    ///
+    /// Pattern syntax:
+    /// - `~regex` - regular expression
+    /// - `*glob*` - glob pattern (if contains * or ?)
+    /// - `exact` - exact match
+    pub fn new(pattern: &str) -> Result<Self> {
+        let match_type = if let Some(regex_pattern) = pattern.strip_prefix('~') {
+            // Regex pattern
+            let regex = Regex::new(regex_pattern).map_err(|e| {
+                Error::Filter(format!("Invalid regex pattern '{}': {}", regex_pattern, e))
+            })?;
+            MatchType::Regex(regex)
+        } else if pattern.contains('*') || pattern.contains('?') {
+            // Glob pattern
+            let glob = Glob::new(pattern)
+                .map_err(|e| Error::Filter(format!("Invalid glob pattern '{}': {}", pattern, e)))?;
+            MatchType::Glob(glob.compile_matcher())
+        } else {
+            // Exact match
+            MatchType::Exact(pattern.to_string())
+        };
+
+        Ok(Self {
+            pattern: pattern.to_string(),
+            match_type,
+        })
+    }
+
+    /// Get the original pattern string
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+
+    /// Get the string an exact pattern matches, or `None` for glob/regex
+    pub fn literal(&self) -> Option<&str> {
+        match &self.match_type {
+            MatchType::Exact(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Check if the pattern matches the given string
+    pub fn is_match(&self, value: &str) -> bool {
+        match &self.match_type {
+            MatchType::Exact(s) => value == s,
+            MatchType::Glob(g) => g.is_match(value),
+            MatchType::Regex(r) => r.is_match(value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pattern_types() {
+        assert!(Pattern::new("git").unwrap().is_match("git"));
+        assert!(!Pattern::new("git").unwrap().is_match("gitlab"));
+        assert!(Pattern::new("rsa-*").unwrap().is_match("rsa-sha2-512"));
+        assert!(
+            Pattern::new("~^rsa-sha2-(256|512)$")
+                .unwrap()
+                .is_match("rsa-sha2-256")
+        );
+        assert!(Pattern::new("~[invalid").is_err());
+
+        assert_eq!(Pattern::new("git").unwrap().literal(), Some("git"));
+        assert_eq!(Pattern::new("git*").unwrap().literal(), None);
+    }
+}
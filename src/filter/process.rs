@@ -0,0 +1,137 @@
+//! Client process information from /proc
+//!
+//! The process connected to a socket is known by the pid from its peer
+//! credentials. Its executable, command line and parent chain are read from
+//! /proc when it connects; elsewhere (e.g. macOS) nothing is known about it.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Maximum number of ancestors followed (guards against pid loops)
+const MAX_ANCESTORS: usize = 64;
+
+/// A process on the local machine
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcessInfo {
+    /// Process ID
+    pub pid: i32,
+    /// Executable (None if it cannot be read, e.g. another user's process)
+    pub exe: Option<PathBuf>,
+    /// Command line arguments
+    pub cmdline: Vec<String>,
+}
+
+impl ProcessInfo {
+    /// Get the file name of the executable
+    pub fn exe_name(&self) -> Option<&str> {
+        self.exe.as_deref()?.file_name()?.to_str()
+    }
+}
+
+impl fmt::Display for ProcessInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.exe {
+            Some(exe) => write!(f, "{}[{}]", exe.display(), self.pid),
+            None => write!(f, "?[{}]", self.pid),
+        }
+    }
+}
+
+/// The process connected to a socket and its ancestors
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientProcess {
+    /// The connected process
+    pub process: ProcessInfo,
+    /// Parent, grandparent, ... up to (excluding) init
+    pub ancestors: Vec<ProcessInfo>,
+}
+
+impl ClientProcess {
+    /// Read a process and its parent chain
+    ///
+    /// Returns `None` if the process does not exist or process information
+    /// is not available on this platform.
+    pub fn resolve(pid: i32) -> Option<Self> {
+        let (process, mut parent) = read_process(pid)?;
+        let mut ancestors = Vec::new();
+        while parent > 1 && ancestors.len() < MAX_ANCESTORS {
+            let Some((ancestor, next)) = read_process(parent) else {
+                break;
+            };
+            ancestors.push(ancestor);
+            parent = next;
+        }
+        Some(Self { process, ancestors })
+    }
+
+    /// Get the executable of the connected process
+    pub fn exe(&self) -> Option<&Path> {
+        self.process.exe.as_deref()
+    }
+
+    /// Get the command line of the connected process, joined by spaces
+    pub fn cmdline(&self) -> String {
+        self.process.cmdline.join(" ")
+    }
+
+    /// Get the executables of the ancestors, nearest first (for logging)
+    pub fn ancestor_names(&self) -> Vec<String> {
+        self.ancestors.iter().map(ToString::to_string).collect()
+    }
+}
+
+/// Read a process and its parent pid from /proc
+#[cfg(target_os = "linux")]
+fn read_process(pid: i32) -> Option<(ProcessInfo, i32)> {
+    let dir = PathBuf::from(format!("/proc/{}", pid));
+    // The command name in stat may contain spaces and parentheses; the
+    // fields after it start behind the last ')'
+    let stat = std::fs::read_to_string(dir.join("stat")).ok()?;
+    let parent = stat
+        .rsplit_once(')')?
+        .1
+        .split_whitespace()
+        .nth(1)?
+        .parse()
+        .ok()?;
+    let cmdline = std::fs::read(dir.join("cmdline"))
+        .map(|raw| {
+            raw.split(|b| *b == 0)
+                .filter(|arg| !arg.is_empty())
+                .map(|arg| String::from_utf8_lossy(arg).into_owned())
+                .collect()
+        })
+        .unwrap_or_default();
+    let process = ProcessInfo {
+        pid,
+        exe: std::fs::read_link(dir.join("exe")).ok(),
+        cmdline,
+    };
+    Some((process, parent))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn read_process(_pid: i32) -> Option<(ProcessInfo, i32)> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_resolve_own_process() {
+        let client = ClientProcess::resolve(std::process::id() as i32).unwrap();
+        assert_eq!(client.exe(), std::env::current_exe().ok().as_deref());
+        assert!(!client.process.cmdline.is_empty());
+        // The test runner was started by something (cargo, a shell, ...)
+        assert!(!client.ancestors.is_empty());
+        assert!(client.ancestors.iter().all(|p| p.pid > 1));
+    }
+
+    #[test]
+    fn test_resolve_missing_process() {
+        assert!(ClientProcess::resolve(i32::MAX).is_none());
+    }
+}
@@ -2,8 +2,9 @@
 
 use crate::error::{Error, Result};
 use crate::filter::{
-    CommentMatcher, FingerprintMatcher, GitHubKeysMatcher, KeyTypeMatcher, KeyfileMatcher,
-    PubkeyMatcher,
+    ClientField, ClientMatcher, CommentMatcher, FilterContext, FingerprintMatcher,
+    GitHubKeysMatcher, HostMatcher, KeyTypeMatcher, KeyfileMatcher, PubkeyMatcher, SignField,
+    SignMatcher, UpstreamMatcher,
 };
 use crate::protocol::Identity;
 
@@ -22,21 +23,42 @@ pub enum Filter {
     KeyType(KeyTypeMatcher),
     /// Match by GitHub user keys
     GitHub(GitHubKeysMatcher),
+    /// Match by attributes of the data being signed
+    Sign(SignMatcher),
+    /// Match by destination host (session-bind@openssh.com)
+    Host(HostMatcher),
+    /// Match by the upstream agent holding the key
+    Upstream(UpstreamMatcher),
+    /// Match by the process connected to the socket
+    Client(ClientMatcher),
 }
 
 impl Filter {
-    /// Check if this filter matches the given identity
-    pub fn matches(&self, identity: &Identity) -> bool {
+    /// Evaluate this filter for the given identity and request context
+    ///
+    /// Returns `None` if the filter cannot be decided in this context
+    /// (e.g., a sign request filter while listing keys).
+    pub fn evaluate(&self, identity: &Identity, ctx: &FilterContext) -> Option<bool> {
         match self {
-            Filter::Fingerprint(m) => m.matches(identity),
-            Filter::Pubkey(m) => m.matches(identity),
-            Filter::Keyfile(m) => m.matches(identity),
-            Filter::Comment(m) => m.matches(identity),
-            Filter::KeyType(m) => m.matches(identity),
-            Filter::GitHub(m) => m.matches(identity),
+            Filter::Fingerprint(m) => Some(m.matches(identity)),
+            Filter::Pubkey(m) => Some(m.matches(identity)),
+            Filter::Keyfile(m) => Some(m.matches(identity)),
+            Filter::Comment(m) => Some(m.matches(identity)),
+            Filter::KeyType(m) => Some(m.matches(identity)),
+            Filter::GitHub(m) => Some(m.matches(identity)),
+            Filter::Sign(m) => m.evaluate(ctx),
+            Filter::Host(m) => m.evaluate(ctx),
+            Filter::Upstream(m) => m.evaluate(ctx),
+            Filter::Client(m) => m.evaluate(ctx),
         }
     }
 
+    /// Check if this filter denies access when it cannot be decided, even
+    /// while listing keys (client process filters)
+    pub fn fails_closed(&self) -> bool {
+        matches!(self, Filter::Client(_))
+    }
+
     /// Get a description of this filter for logging
     pub fn description(&self) -> String {
         match self {
@@ -46,6 +68,10 @@ impl Filter {
             Filter::Comment(m) => format!("comment={}", m.pattern()),
             Filter::KeyType(m) => format!("type={}", m.key_type()),
             Filter::GitHub(m) => format!("github={}", m.username()),
+            Filter::Sign(m) => format!("{}={}", m.field().name(), m.pattern()),
+            Filter::Host(m) => format!("host={}", m.pattern()),
+            Filter::Upstream(m) => format!("upstream={}", m.pattern()),
+            Filter::Client(m) => format!("{}={}", m.field().name(), m.pattern()),
         }
     }
 }
@@ -67,8 +93,20 @@ impl FilterRule {
 
     /// Check if this rule matches the given identity
     pub fn matches(&self, identity: &Identity) -> bool {
-        let result = self.filter.matches(identity);
-        if self.negated { !result } else { result }
+        self.matches_with(identity, &FilterContext::default())
+    }
+
+    /// Check if this rule matches the given identity in a request context
+    ///
+    /// Undecidable rules match when listing keys, so that they only restrict
+    /// requests carrying the information they need. At sign time they fail
+    /// (e.g., `host=` or `not-host=` without a known destination). Client
+    /// process rules always fail when undecidable.
+    pub fn matches_with(&self, identity: &Identity, ctx: &FilterContext) -> bool {
+        match self.filter.evaluate(identity, ctx) {
+            Some(result) => result != self.negated,
+            None => !ctx.is_sign() && !self.filter.fails_closed(),
+        }
     }
 
     /// Parse a filter rule from a string
@@ -109,6 +147,39 @@ impl FilterRule {
         if let Some(rest) = s.strip_prefix("github=") {
             return Ok(Filter::GitHub(GitHubKeysMatcher::new(rest)));
         }
+        if let Some(rest) = s.strip_prefix("ssh-user=") {
+            return Ok(Filter::Sign(SignMatcher::new(SignField::User, rest)?));
+        }
+        if let Some(rest) = s.strip_prefix("ssh-service=") {
+            return Ok(Filter::Sign(SignMatcher::new(SignField::Service, rest)?));
+        }
+        if let Some(rest) = s.strip_prefix("sign-alg=") {
+            return Ok(Filter::Sign(SignMatcher::new(SignField::Algorithm, rest)?));
+        }
+        if let Some(rest) = s.strip_prefix("host=") {
+            return Ok(Filter::Host(HostMatcher::new(rest)?));
+        }
+        if let Some(rest) = s.strip_prefix("namespace=") {
+            return Ok(Filter::Sign(SignMatcher::new(SignField::Namespace, rest)?));
+        }
+        if let Some(rest) = s.strip_prefix("upstream=") {
+            return Ok(Filter::Upstream(UpstreamMatcher::new(rest)?));
+        }
+        if let Some(rest) = s.strip_prefix("client-exe=") {
+            return Ok(Filter::Client(ClientMatcher::new(ClientField::Exe, rest)?));
+        }
+        if let Some(rest) = s.strip_prefix("client-ancestor=") {
+            return Ok(Filter::Client(ClientMatcher::new(
+                ClientField::Ancestor,
+                rest,
+            )?));
+        }
+        if let Some(rest) = s.strip_prefix("sign-hash=") {
+            return Ok(Filter::Sign(SignMatcher::new(
+                SignField::HashAlgorithm,
+                rest,
+            )?));
+        }
 
         Err(Error::Filter(format!("Unknown filter format: {}", s)))
     }
@@ -186,6 +257,110 @@ mod tests {
         assert!(matches!(rule.filter, Filter::GitHub(_)));
     }
 
+    #[test]
+    fn test_parse_sign_terms() {
+        let rule = FilterRule::parse("ssh-user=deploy").unwrap();
+        assert!(matches!(&rule.filter, Filter::Sign(m) if m.field() == SignField::User));
+        assert_eq!(rule.description(), "ssh-user=deploy");
+
+        let rule = FilterRule::parse("not-sign-alg=ssh-rsa").unwrap();
+        assert!(rule.negated);
+        assert!(matches!(&rule.filter, Filter::Sign(m) if m.field() == SignField::Algorithm));
+
+        let rule = FilterRule::parse("ssh-service=ssh-connection").unwrap();
+        assert!(matches!(&rule.filter, Filter::Sign(m) if m.field() == SignField::Service));
+
+        let rule = FilterRule::parse("namespace=git").unwrap();
+        assert!(matches!(&rule.filter, Filter::Sign(m) if m.field() == SignField::Namespace));
+        assert_eq!(rule.description(), "namespace=git");
+
+        let rule = FilterRule::parse("sign-hash=sha512").unwrap();
+        assert!(matches!(&rule.filter, Filter::Sign(m) if m.field() == SignField::HashAlgorithm));
+    }
+
+    #[test]
+    fn test_sign_terms_undecided_when_listing() {
+        let identity = Identity::new(bytes::Bytes::new(), String::new());
+        // Neither the term nor its negation restricts key listing
+        assert!(
+            FilterRule::parse("ssh-user=git")
+                .unwrap()
+                .matches(&identity)
+        );
+        assert!(
+            FilterRule::parse("not-ssh-user=git")
+                .unwrap()
+                .matches(&identity)
+        );
+    }
+
+    #[test]
+    fn test_host_undecided_fails_at_sign_time() {
+        use crate::filter::SignContext;
+        use crate::protocol::SignPayload;
+
+        let identity = Identity::new(bytes::Bytes::new(), String::new());
+        let sign = SignContext {
+            payload: SignPayload::Unknown,
+            algorithm: None,
+        };
+        let ctx = FilterContext::for_sign(&sign);
+        for rule in ["host=github.com", "not-host=github.com"] {
+            let rule = FilterRule::parse(rule).unwrap();
+            assert!(matches!(rule.filter, Filter::Host(_)));
+            assert!(
+                rule.matches(&identity),
+                "{} when listing",
+                rule.description()
+            );
+            assert!(
+                !rule.matches_with(&identity, &ctx),
+                "{} when signing",
+                rule.description()
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_upstream() {
+        let identity = Identity::new(bytes::Bytes::new(), String::new());
+        let rule = FilterRule::parse("not-upstream=gpg-agent").unwrap();
+        assert!(matches!(rule.filter, Filter::Upstream(_)));
+        assert_eq!(rule.filter.description(), "upstream=gpg-agent");
+        assert!(rule.matches_with(
+            &identity,
+            &FilterContext::default().with_upstream(Some("1password"))
+        ));
+        assert!(!rule.matches_with(
+            &identity,
+            &FilterContext::default().with_upstream(Some("gpg-agent"))
+        ));
+    }
+
+    #[test]
+    fn test_client_undecided_fails_closed() {
+        let identity = Identity::new(bytes::Bytes::new(), String::new());
+        for rule in [
+            "client-exe=git",
+            "not-client-exe=git",
+            "client-ancestor=code",
+        ] {
+            let rule = FilterRule::parse(rule).unwrap();
+            assert!(matches!(rule.filter, Filter::Client(_)));
+            assert!(
+                !rule.matches(&identity),
+                "{} without client",
+                rule.description()
+            );
+        }
+        assert_eq!(
+            FilterRule::parse("not-client-ancestor=code")
+                .unwrap()
+                .description(),
+            "-client-ancestor=code"
+        );
+    }
+
     #[test]
     fn test_parse_pubkey_auto() {
         // Use a valid ed25519 public key
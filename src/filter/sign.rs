@@ -0,0 +1,178 @@
+//! Sign request matching filters (remote user, service, algorithm, SSHSIG namespace)
+
+use crate::error::Result;
+use crate::filter::{FilterContext, Pattern};
+
+/// Sign request attribute inspected by a [`SignMatcher`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignField {
+    /// Remote user name of an SSH userauth request (`ssh-user=`)
+    User,
+    /// Service of an SSH userauth request (`ssh-service=`)
+    Service,
+    /// Signature algorithm (`sign-alg=`)
+    Algorithm,
+    /// Namespace of an SSHSIG signature (`namespace=`)
+    Namespace,
+    /// Message hash algorithm of an SSHSIG signature (`sign-hash=`)
+    HashAlgorithm,
+}
+
+impl SignField {
+    /// Get the filter term name
+    pub fn name(&self) -> &'static str {
+        match self {
+            SignField::User => "ssh-user",
+            SignField::Service => "ssh-service",
+            SignField::Algorithm => "sign-alg",
+            SignField::Namespace => "namespace",
+            SignField::HashAlgorithm => "sign-hash",
+        }
+    }
+}
+
+/// Matcher for attributes of the data being signed
+///
+/// Only decidable for sign requests; while listing keys it does not restrict
+/// which keys are shown.
+#[derive(Debug, Clone)]
+pub struct SignMatcher {
+    /// The attribute to match
+    field: SignField,
+    /// The pattern to match against
+    pattern: Pattern,
+}
+
+impl SignMatcher {
+    /// Create a new sign matcher (pattern: exact, `*glob*` or `~regex`)
+    pub fn new(field: SignField, pattern: &str) -> Result<Self> {
+        Ok(Self {
+            field,
+            pattern: Pattern::new(pattern)?,
+        })
+    }
+
+    /// Get the attribute being matched
+    pub fn field(&self) -> SignField {
+        self.field
+    }
+
+    /// Get the pattern being matched
+    pub fn pattern(&self) -> &str {
+        self.pattern.as_str()
+    }
+
+    /// Evaluate against the request context
+    ///
+    /// Returns `None` if the context has no sign request. A sign request
+    /// without the attribute (e.g., `namespace=` for an SSH login) does not
+    /// match.
+    pub fn evaluate(&self, ctx: &FilterContext) -> Option<bool> {
+        let sign = ctx.sign?;
+        let userauth = sign.payload.userauth();
+        let sshsig = sign.payload.sshsig();
+        let value = match self.field {
+            SignField::User => userauth.map(|u| u.user.as_str()),
+            SignField::Service => userauth.map(|u| u.service.as_str()),
+            SignField::Algorithm => sign.algorithm.as_deref(),
+            SignField::Namespace => sshsig.map(|s| s.namespace.as_str()),
+            SignField::HashAlgorithm => sshsig.map(|s| s.hash_algorithm.as_str()),
+        };
+        Some(value.is_some_and(|v| self.pattern.is_match(v)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::SignContext;
+    use crate::protocol::{SignPayload, SshSigRequest, UserAuthRequest};
+    use bytes::Bytes;
+
+    fn eval(matcher: &SignMatcher, sign: SignContext) -> Option<bool> {
+        matcher.evaluate(&FilterContext::for_sign(&sign))
+    }
+
+    fn userauth_sign(user: &str, algorithm: &str) -> SignContext {
+        SignContext {
+            payload: SignPayload::UserAuth(UserAuthRequest {
+                session_id: Bytes::new(),
+                user: user.to_string(),
+                service: "ssh-connection".to_string(),
+                method: "publickey".to_string(),
+                algorithm: algorithm.to_string(),
+                key_blob: Bytes::new(),
+                host_key: None,
+            }),
+            algorithm: Some(algorithm.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_undecided_without_sign_request() {
+        let matcher = SignMatcher::new(SignField::User, "git").unwrap();
+        assert_eq!(matcher.evaluate(&FilterContext::default()), None);
+    }
+
+    #[test]
+    fn test_user_match() {
+        let matcher = SignMatcher::new(SignField::User, "git").unwrap();
+        assert_eq!(
+            eval(&matcher, userauth_sign("git", "ssh-ed25519")),
+            Some(true)
+        );
+        assert_eq!(
+            eval(&matcher, userauth_sign("root", "ssh-ed25519")),
+            Some(false)
+        );
+    }
+
+    #[test]
+    fn test_algorithm_match() {
+        let matcher = SignMatcher::new(SignField::Algorithm, "rsa-sha2-*").unwrap();
+        assert_eq!(
+            eval(&matcher, userauth_sign("git", "rsa-sha2-512")),
+            Some(true)
+        );
+        assert_eq!(eval(&matcher, userauth_sign("git", "ssh-rsa")), Some(false));
+    }
+
+    fn sshsig_sign(namespace: &str) -> SignContext {
+        SignContext {
+            payload: SignPayload::SshSig(SshSigRequest {
+                namespace: namespace.to_string(),
+                hash_algorithm: "sha512".to_string(),
+                hash: Bytes::new(),
+            }),
+            algorithm: Some("ssh-ed25519".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_namespace_match() {
+        let matcher = SignMatcher::new(SignField::Namespace, "git").unwrap();
+        assert_eq!(eval(&matcher, sshsig_sign("git")), Some(true));
+        assert_eq!(eval(&matcher, sshsig_sign("file")), Some(false));
+        // SSH logins have no namespace
+        assert_eq!(
+            eval(&matcher, userauth_sign("git", "ssh-ed25519")),
+            Some(false)
+        );
+        // and SSHSIG signatures have no remote user
+        let user = SignMatcher::new(SignField::User, "*").unwrap();
+        assert_eq!(eval(&user, sshsig_sign("git")), Some(false));
+
+        let hash = SignMatcher::new(SignField::HashAlgorithm, "sha512").unwrap();
+        assert_eq!(eval(&hash, sshsig_sign("git")), Some(true));
+    }
+
+    #[test]
+    fn test_user_on_non_userauth_data() {
+        let sign = SignContext {
+            payload: SignPayload::Unknown,
+            algorithm: Some("ssh-ed25519".to_string()),
+        };
+        let matcher = SignMatcher::new(SignField::User, "*").unwrap();
+        assert_eq!(eval(&matcher, sign), Some(false));
+    }
+}
@@ -0,0 +1,59 @@
+//! Source upstream agent matching filter
+
+use crate::error::Result;
+use crate::filter::{FilterContext, Pattern};
+
+/// Matcher for the upstream agent a key comes from
+///
+/// Only meaningful for sockets merging several upstream agents; the name is
+/// the one given in `[upstreams]`, or the socket path.
+#[derive(Debug, Clone)]
+pub struct UpstreamMatcher {
+    /// The upstream name pattern to match
+    pattern: Pattern,
+}
+
+impl UpstreamMatcher {
+    /// Create a new upstream matcher (pattern: exact, `*glob*` or `~regex`)
+    pub fn new(pattern: &str) -> Result<Self> {
+        Ok(Self {
+            pattern: Pattern::new(pattern)?,
+        })
+    }
+
+    /// Get the pattern being matched
+    pub fn pattern(&self) -> &str {
+        self.pattern.as_str()
+    }
+
+    /// Evaluate against the request context
+    ///
+    /// Returns `None` if the source upstream is unknown.
+    pub fn evaluate(&self, ctx: &FilterContext) -> Option<bool> {
+        ctx.upstream.map(|name| self.pattern.is_match(name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_upstream_match() {
+        let ctx = FilterContext::default().with_upstream(Some("1password"));
+        assert_eq!(
+            UpstreamMatcher::new("1password").unwrap().evaluate(&ctx),
+            Some(true)
+        );
+        assert_eq!(
+            UpstreamMatcher::new("gpg*").unwrap().evaluate(&ctx),
+            Some(false)
+        );
+        assert_eq!(
+            UpstreamMatcher::new("1password")
+                .unwrap()
+                .evaluate(&FilterContext::default()),
+            None
+        );
+    }
+}
@@ -21,8 +21,9 @@ async fn main() -> std::process::ExitCode {
         return ExitCode::Success.into();
     }
 
-    // Initialize logging
-    init_logging(cli.verbose, cli.quiet);
+    // Initialize logging (stdout carries the agent protocol in stdio mode)
+    let log_to_stderr = matches!(cli.command, Some(Commands::Stdio(_)));
+    init_logging(cli.verbose, cli.quiet, log_to_stderr);
 
     let result = run(cli).await;
 
@@ -43,7 +44,10 @@ async fn run(cli: Cli) -> Result<(), (ExitCode, anyhow::Error)> {
     };
 
     match command {
-        Commands::Run(args) => authsock_filter::cli::commands::run::execute(args, cli.config)
+        Commands::Run(args) => authsock_filter::cli::commands::run::execute(*args, cli.config)
+            .await
+            .map_err(|e| (classify_error(&e), e))?,
+        Commands::Stdio(args) => authsock_filter::cli::commands::stdio::execute(args, cli.config)
             .await
             .map_err(|e| (classify_error(&e), e))?,
         Commands::Config { command } => {
@@ -99,7 +103,7 @@ fn classify_error(err: &anyhow::Error) -> ExitCode {
 }
 
 /// Initialize logging with tracing-subscriber
-fn init_logging(verbose: bool, quiet: bool) {
+fn init_logging(verbose: bool, quiet: bool, stderr: bool) {
     let filter = if verbose {
         EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("debug"))
     } else if quiet {
@@ -108,8 +112,12 @@ fn init_logging(verbose: bool, quiet: bool) {
         EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"))
     };
 
-    tracing_subscriber::fmt()
+    let subscriber = tracing_subscriber::fmt()
         .with_env_filter(filter)
-        .with_target(false)
-        .init();
+        .with_target(false);
+    if stderr {
+        subscriber.with_writer(std::io::stderr).init();
+    } else {
+        subscriber.init();
+    }
 }
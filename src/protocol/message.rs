@@ -1,6 +1,8 @@
 //! SSH Agent Protocol message types and parsing
 
 use crate::error::{Error, Result};
+use crate::protocol::wire::WireReader;
+use crate::protocol::{SESSION_BIND_EXTENSION, SessionBind, SignRequest};
 use bytes::{Buf, BufMut, Bytes, BytesMut};
 use ssh_key::{Fingerprint, HashAlg, PublicKey};
 
@@ -320,6 +322,46 @@ impl AgentMessage {
         Ok(Bytes::copy_from_slice(&buf[..key_len]))
     }
 
+    /// Parse a SignRequest message (key blob, data and flags)
+    pub fn parse_sign_request(&self) -> Result<SignRequest> {
+        if self.msg_type != MessageType::SignRequest {
+            return Err(Error::InvalidMessage(format!(
+                "Expected SignRequest, got {:?}",
+                self.msg_type
+            )));
+        }
+        SignRequest::parse(&self.payload)
+    }
+
+    /// Parse an Extension message as session-bind@openssh.com
+    ///
+    /// Returns `None` for other extensions.
+    pub fn parse_session_bind(&self) -> Result<Option<SessionBind>> {
+        if self.msg_type != MessageType::Extension {
+            return Err(Error::InvalidMessage(format!(
+                "Expected Extension, got {:?}",
+                self.msg_type
+            )));
+        }
+        let mut reader = WireReader::new(&self.payload);
+        if reader.read_string()?.as_ref() != SESSION_BIND_EXTENSION.as_bytes() {
+            return Ok(None);
+        }
+        let contents = &self.payload[self.payload.len() - reader.remaining()..];
+        SessionBind::parse(contents).map(Some)
+    }
+
+    /// Parse the passphrase of a Lock or Unlock message
+    pub fn parse_lock_passphrase(&self) -> Result<Bytes> {
+        if !matches!(self.msg_type, MessageType::Lock | MessageType::Unlock) {
+            return Err(Error::InvalidMessage(format!(
+                "Expected Lock or Unlock, got {:?}",
+                self.msg_type
+            )));
+        }
+        WireReader::new(&self.payload).read_string()
+    }
+
     /// Encode the message to bytes (including length prefix)
     pub fn encode(&self) -> Bytes {
         let total_len = 1 + self.payload.len();
@@ -460,4 +502,18 @@ mod tests {
         assert!(result.is_err());
         assert!(result.unwrap_err().to_string().contains("exceeds maximum"));
     }
+
+    #[test]
+    fn test_parse_lock_passphrase() {
+        let mut payload = BytesMut::new();
+        payload.put_u32(6);
+        payload.put_slice(b"secret");
+        let msg = AgentMessage::new(MessageType::Unlock, payload.freeze());
+        assert_eq!(msg.parse_lock_passphrase().unwrap().as_ref(), b"secret");
+
+        let msg = AgentMessage::new(MessageType::Lock, Bytes::from_static(&[0, 0]));
+        assert!(msg.parse_lock_passphrase().is_err());
+        let msg = AgentMessage::new(MessageType::RequestIdentities, Bytes::new());
+        assert!(msg.parse_lock_passphrase().is_err());
+    }
 }
@@ -5,6 +5,11 @@
 
 pub mod codec;
 pub mod message;
+pub mod session_bind;
+pub mod sign;
+pub mod wire;
 
 pub use codec::AgentCodec;
 pub use message::{AgentMessage, Identity, MessageType};
+pub use session_bind::{SESSION_BIND_EXTENSION, SessionBind};
+pub use sign::{SignPayload, SignRequest, SshSigRequest, UserAuthRequest};
@@ -0,0 +1,109 @@
+//! Parsing of the session-bind@openssh.com agent extension
+//!
+//! OpenSSH 8.9+ binds an agent connection to the SSH session before
+//! authenticating: the client sends the server host key, the session
+//! identifier and the server's signature over it (OpenSSH PROTOCOL.agent).
+
+use crate::error::Result;
+use crate::protocol::wire::WireReader;
+use bytes::Bytes;
+use signature::Verifier;
+use ssh_key::{PublicKey, Signature};
+
+/// Extension name of session binding
+pub const SESSION_BIND_EXTENSION: &str = "session-bind@openssh.com";
+
+/// Parsed session-bind@openssh.com extension request
+#[derive(Debug, Clone)]
+pub struct SessionBind {
+    /// Server host key blob
+    pub host_key: Bytes,
+    /// Session identifier of the SSH connection
+    pub session_id: Bytes,
+    /// Host key signature over the session identifier
+    pub signature: Bytes,
+    /// Whether the agent connection is being forwarded to this host
+    pub is_forwarding: bool,
+}
+
+impl SessionBind {
+    /// Parse the extension contents (after the extension name)
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let mut reader = WireReader::new(data);
+        let host_key = reader.read_string()?;
+        let session_id = reader.read_string()?;
+        let signature = reader.read_string()?;
+        let is_forwarding = reader.read_bool()?;
+        if !reader.is_empty() {
+            return Err(crate::Error::InvalidMessage(
+                "Trailing data after session-bind".to_string(),
+            ));
+        }
+
+        Ok(Self {
+            host_key,
+            session_id,
+            signature,
+            is_forwarding,
+        })
+    }
+
+    /// Check that the signature was made by the host key over the session id
+    pub fn verify(&self) -> bool {
+        let Ok(key) = PublicKey::from_bytes(&self.host_key) else {
+            return false;
+        };
+        let Ok(signature) = Signature::try_from(self.signature.as_ref()) else {
+            return false;
+        };
+        Verifier::verify(key.key_data(), &self.session_id, &signature).is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::{BufMut, BytesMut};
+    use signature::Signer;
+    use ssh_key::{Algorithm, PrivateKey};
+
+    fn put_string(buf: &mut BytesMut, s: &[u8]) {
+        buf.put_u32(s.len() as u32);
+        buf.put_slice(s);
+    }
+
+    fn build(host_key: &PrivateKey, session_id: &[u8], signed: &[u8]) -> Bytes {
+        let signature: Signature = host_key.try_sign(signed).unwrap();
+        let mut buf = BytesMut::new();
+        put_string(&mut buf, &host_key.public_key().to_bytes().unwrap());
+        put_string(&mut buf, session_id);
+        put_string(&mut buf, &Vec::<u8>::try_from(signature).unwrap());
+        buf.put_u8(0);
+        buf.freeze()
+    }
+
+    #[test]
+    fn test_parse_and_verify() {
+        let host_key = PrivateKey::random(&mut rand::thread_rng(), Algorithm::Ed25519).unwrap();
+        let data = build(&host_key, b"session-id", b"session-id");
+
+        let bind = SessionBind::parse(&data).unwrap();
+        assert_eq!(bind.session_id.as_ref(), b"session-id");
+        assert!(!bind.is_forwarding);
+        assert!(bind.verify());
+    }
+
+    #[test]
+    fn test_verify_rejects_wrong_signature() {
+        let host_key = PrivateKey::random(&mut rand::thread_rng(), Algorithm::Ed25519).unwrap();
+        let data = build(&host_key, b"session-id", b"other-session");
+
+        let bind = SessionBind::parse(&data).unwrap();
+        assert!(!bind.verify());
+    }
+
+    #[test]
+    fn test_parse_truncated() {
+        assert!(SessionBind::parse(&[0, 0, 0, 1]).is_err());
+    }
+}
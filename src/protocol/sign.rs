@@ -0,0 +1,366 @@
+//! Parsing of SSH_AGENTC_SIGN_REQUEST contents
+//!
+//! A sign request carries the key blob, the data to be signed and flags.
+//! For SSH authentication the data is an SSH_MSG_USERAUTH_REQUEST
+//! (RFC 4252 section 7) prefixed with the session identifier.
+//! For `ssh-keygen -Y sign` (git/file signing) it is an SSHSIG signed-data
+//! blob (OpenSSH PROTOCOL.sshsig).
+
+use crate::error::Result;
+use crate::protocol::wire::WireReader;
+use bytes::Bytes;
+use ssh_key::PublicKey;
+
+/// Sign request flag: use rsa-sha2-256 for RSA keys
+pub const SSH_AGENT_RSA_SHA2_256: u32 = 0x02;
+
+/// Sign request flag: use rsa-sha2-512 for RSA keys
+pub const SSH_AGENT_RSA_SHA2_512: u32 = 0x04;
+
+/// SSH_MSG_USERAUTH_REQUEST message number
+const SSH_MSG_USERAUTH_REQUEST: u8 = 50;
+
+/// Magic preamble of SSHSIG signed data
+const SSHSIG_MAGIC: &[u8] = b"SSHSIG";
+
+/// Parsed SSH_AGENTC_SIGN_REQUEST
+#[derive(Debug, Clone)]
+pub struct SignRequest {
+    /// Raw public key blob of the key to sign with
+    pub key_blob: Bytes,
+    /// Data to be signed
+    pub data: Bytes,
+    /// Signature flags (SSH_AGENT_RSA_SHA2_*)
+    pub flags: u32,
+}
+
+impl SignRequest {
+    /// Parse a sign request from the message payload (excluding type byte)
+    pub fn parse(payload: &[u8]) -> Result<Self> {
+        let mut reader = WireReader::new(payload);
+        let key_blob = reader.read_string()?;
+        let data = reader.read_string()?;
+        let flags = reader.read_u32()?;
+        Ok(Self {
+            key_blob,
+            data,
+            flags,
+        })
+    }
+
+    /// Get the signature algorithm the agent will use
+    ///
+    /// For RSA keys this depends on the request flags; for other keys it is
+    /// the key algorithm itself.
+    pub fn signature_algorithm(&self) -> Option<String> {
+        let key = PublicKey::from_bytes(&self.key_blob).ok()?;
+        let algorithm = key.algorithm();
+        if matches!(algorithm, ssh_key::Algorithm::Rsa { .. }) {
+            if self.flags & SSH_AGENT_RSA_SHA2_512 != 0 {
+                return Some("rsa-sha2-512".to_string());
+            }
+            if self.flags & SSH_AGENT_RSA_SHA2_256 != 0 {
+                return Some("rsa-sha2-256".to_string());
+            }
+        }
+        Some(algorithm.as_str().to_string())
+    }
+
+    /// Classify the data being signed
+    pub fn payload(&self) -> SignPayload {
+        SignPayload::classify(&self.data)
+    }
+}
+
+/// The kind of data a sign request asks to sign
+#[derive(Debug, Clone)]
+pub enum SignPayload {
+    /// SSH public key user authentication
+    UserAuth(UserAuthRequest),
+    /// SSHSIG file/commit signature
+    SshSig(SshSigRequest),
+    /// Anything else
+    Unknown,
+}
+
+impl SignPayload {
+    /// Classify the data of a sign request
+    pub fn classify(data: &[u8]) -> Self {
+        if data.starts_with(SSHSIG_MAGIC) {
+            return match SshSigRequest::parse(data) {
+                Ok(req) => SignPayload::SshSig(req),
+                Err(_) => SignPayload::Unknown,
+            };
+        }
+        match UserAuthRequest::parse(data) {
+            Ok(req) => SignPayload::UserAuth(req),
+            Err(_) => SignPayload::Unknown,
+        }
+    }
+
+    /// Get the payload classification name for logging
+    pub fn kind(&self) -> &'static str {
+        match self {
+            SignPayload::UserAuth(_) => "userauth",
+            SignPayload::SshSig(_) => "sshsig",
+            SignPayload::Unknown => "unknown",
+        }
+    }
+
+    /// Check if the payload is neither an SSH login nor an SSHSIG signature
+    pub fn is_unknown(&self) -> bool {
+        matches!(self, SignPayload::Unknown)
+    }
+
+    /// Get the userauth request, if this is one
+    pub fn userauth(&self) -> Option<&UserAuthRequest> {
+        match self {
+            SignPayload::UserAuth(req) => Some(req),
+            _ => None,
+        }
+    }
+
+    /// Get the SSHSIG request, if this is one
+    pub fn sshsig(&self) -> Option<&SshSigRequest> {
+        match self {
+            SignPayload::SshSig(req) => Some(req),
+            _ => None,
+        }
+    }
+}
+
+/// SSH_MSG_USERAUTH_REQUEST for the "publickey" method, as signed by the agent
+#[derive(Debug, Clone)]
+pub struct UserAuthRequest {
+    /// Session identifier of the SSH connection
+    pub session_id: Bytes,
+    /// Remote user name
+    pub user: String,
+    /// Requested service (normally "ssh-connection")
+    pub service: String,
+    /// Authentication method ("publickey" or "publickey-hostbound-v00@openssh.com")
+    pub method: String,
+    /// Public key algorithm name
+    pub algorithm: String,
+    /// Public key blob
+    pub key_blob: Bytes,
+    /// Server host key (hostbound method only)
+    pub host_key: Option<Bytes>,
+}
+
+impl UserAuthRequest {
+    /// Parse the data of a sign request as a publickey userauth request
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let invalid = |msg: &str| crate::Error::InvalidMessage(msg.to_string());
+
+        let mut reader = WireReader::new(data);
+        let session_id = reader.read_string()?;
+        if reader.read_u8()? != SSH_MSG_USERAUTH_REQUEST {
+            return Err(invalid("Not an SSH_MSG_USERAUTH_REQUEST"));
+        }
+        let user = reader.read_utf8()?;
+        let service = reader.read_utf8()?;
+        let method = reader.read_utf8()?;
+        let hostbound = match method.as_str() {
+            "publickey" => false,
+            "publickey-hostbound-v00@openssh.com" => true,
+            _ => return Err(invalid("Unsupported userauth method")),
+        };
+        if !reader.read_bool()? {
+            return Err(invalid("Userauth request without signature"));
+        }
+        let algorithm = reader.read_utf8()?;
+        let key_blob = reader.read_string()?;
+        let host_key = if hostbound {
+            Some(reader.read_string()?)
+        } else {
+            None
+        };
+        if !reader.is_empty() {
+            return Err(invalid("Trailing data after userauth request"));
+        }
+
+        Ok(Self {
+            session_id,
+            user,
+            service,
+            method,
+            algorithm,
+            key_blob,
+            host_key,
+        })
+    }
+}
+
+/// SSHSIG signed data, as produced by `ssh-keygen -Y sign`
+#[derive(Debug, Clone)]
+pub struct SshSigRequest {
+    /// Signature namespace (e.g., "git", "file")
+    pub namespace: String,
+    /// Hash algorithm of the message ("sha256" or "sha512")
+    pub hash_algorithm: String,
+    /// Hash of the signed message
+    pub hash: Bytes,
+}
+
+impl SshSigRequest {
+    /// Parse the data of a sign request as SSHSIG signed data
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let invalid = |msg: &str| crate::Error::InvalidMessage(msg.to_string());
+
+        let rest = data
+            .strip_prefix(SSHSIG_MAGIC)
+            .ok_or_else(|| invalid("Missing SSHSIG preamble"))?;
+        let mut reader = WireReader::new(rest);
+        let namespace = reader.read_utf8()?;
+        if namespace.is_empty() {
+            return Err(invalid("Empty SSHSIG namespace"));
+        }
+        let _reserved = reader.read_string()?;
+        let hash_algorithm = reader.read_utf8()?;
+        let hash = reader.read_string()?;
+        if !reader.is_empty() {
+            return Err(invalid("Trailing data after SSHSIG data"));
+        }
+
+        Ok(Self {
+            namespace,
+            hash_algorithm,
+            hash,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::{BufMut, BytesMut};
+
+    const RSA_KEY: &str = "ssh-rsa AAAAB3NzaC1yc2EAAAADAQABAAAAgQDPHvhuo0qZ+K1Mpdwl8sGfaTLjkKMDB+rDm++0/FzhEeDpcjupFl5KExP09BmFmjnWdyZn69RdHXTNer12SfWPWtPX20V/SuVhCpBIqx9RNl/oOdT0/zjijDb2I/wjkywMiefrQ5xG4hyQ5KqFDtdvngjVcZdRMhw2fkz2BosNBQ== test@example.com";
+
+    fn put_string(buf: &mut BytesMut, s: &[u8]) {
+        buf.put_u32(s.len() as u32);
+        buf.put_slice(s);
+    }
+
+    /// Build userauth data as signed by ssh(1)
+    fn build_userauth(user: &str, method: &str, host_key: Option<&[u8]>) -> Bytes {
+        let mut buf = BytesMut::new();
+        put_string(&mut buf, b"session-id");
+        buf.put_u8(SSH_MSG_USERAUTH_REQUEST);
+        put_string(&mut buf, user.as_bytes());
+        put_string(&mut buf, b"ssh-connection");
+        put_string(&mut buf, method.as_bytes());
+        buf.put_u8(1);
+        put_string(&mut buf, b"ssh-ed25519");
+        put_string(&mut buf, b"key-blob");
+        if let Some(host_key) = host_key {
+            put_string(&mut buf, host_key);
+        }
+        buf.freeze()
+    }
+
+    #[test]
+    fn test_parse_userauth() {
+        let data = build_userauth("git", "publickey", None);
+        let req = UserAuthRequest::parse(&data).unwrap();
+        assert_eq!(req.session_id.as_ref(), b"session-id");
+        assert_eq!(req.user, "git");
+        assert_eq!(req.service, "ssh-connection");
+        assert_eq!(req.algorithm, "ssh-ed25519");
+        assert_eq!(req.key_blob.as_ref(), b"key-blob");
+        assert!(req.host_key.is_none());
+    }
+
+    #[test]
+    fn test_parse_userauth_hostbound() {
+        let data = build_userauth(
+            "deploy",
+            "publickey-hostbound-v00@openssh.com",
+            Some(b"host-key"),
+        );
+        let req = UserAuthRequest::parse(&data).unwrap();
+        assert_eq!(req.user, "deploy");
+        assert_eq!(req.host_key.as_deref(), Some(b"host-key".as_ref()));
+    }
+
+    #[test]
+    fn test_parse_userauth_rejects_other_data() {
+        assert!(UserAuthRequest::parse(b"arbitrary bytes").is_err());
+        let payload = SignPayload::classify(b"arbitrary bytes");
+        assert!(payload.is_unknown());
+        assert_eq!(payload.kind(), "unknown");
+
+        // Trailing data is not a valid userauth request
+        let mut data = BytesMut::from(build_userauth("git", "publickey", None).as_ref());
+        data.put_u8(0);
+        assert!(UserAuthRequest::parse(&data).is_err());
+    }
+
+    /// Build SSHSIG signed data as produced by ssh-keygen -Y sign
+    fn build_sshsig(namespace: &str, hash_algorithm: &str) -> Bytes {
+        let mut buf = BytesMut::new();
+        buf.put_slice(SSHSIG_MAGIC);
+        put_string(&mut buf, namespace.as_bytes());
+        put_string(&mut buf, b"");
+        put_string(&mut buf, hash_algorithm.as_bytes());
+        put_string(&mut buf, &[0xab; 64]);
+        buf.freeze()
+    }
+
+    #[test]
+    fn test_parse_sshsig() {
+        let data = build_sshsig("git", "sha512");
+        let req = SshSigRequest::parse(&data).unwrap();
+        assert_eq!(req.namespace, "git");
+        assert_eq!(req.hash_algorithm, "sha512");
+        assert_eq!(req.hash.len(), 64);
+
+        let payload = SignPayload::classify(&data);
+        assert_eq!(payload.sshsig().map(|r| r.namespace.as_str()), Some("git"));
+        assert!(payload.userauth().is_none());
+        assert_eq!(payload.kind(), "sshsig");
+    }
+
+    #[test]
+    fn test_parse_sshsig_rejects_malformed() {
+        // Empty namespace is not allowed
+        assert!(SshSigRequest::parse(&build_sshsig("", "sha512")).is_err());
+        // Truncated data is classified as unknown
+        let data = build_sshsig("file", "sha256");
+        assert!(matches!(
+            SignPayload::classify(&data[..data.len() - 1]),
+            SignPayload::Unknown
+        ));
+    }
+
+    #[test]
+    fn test_signature_algorithm() {
+        let key: PublicKey = RSA_KEY.parse().unwrap();
+        let key_blob = Bytes::from(key.to_bytes().unwrap());
+
+        let mut req = SignRequest {
+            key_blob,
+            data: Bytes::new(),
+            flags: 0,
+        };
+        assert_eq!(req.signature_algorithm().as_deref(), Some("ssh-rsa"));
+        req.flags = SSH_AGENT_RSA_SHA2_256;
+        assert_eq!(req.signature_algorithm().as_deref(), Some("rsa-sha2-256"));
+        req.flags = SSH_AGENT_RSA_SHA2_512;
+        assert_eq!(req.signature_algorithm().as_deref(), Some("rsa-sha2-512"));
+    }
+
+    #[test]
+    fn test_parse_sign_request() {
+        let mut payload = BytesMut::new();
+        put_string(&mut payload, b"key");
+        put_string(&mut payload, b"data");
+        payload.put_u32(SSH_AGENT_RSA_SHA2_512);
+
+        let req = SignRequest::parse(&payload).unwrap();
+        assert_eq!(req.key_blob.as_ref(), b"key");
+        assert_eq!(req.data.as_ref(), b"data");
+        assert_eq!(req.flags, SSH_AGENT_RSA_SHA2_512);
+    }
+}
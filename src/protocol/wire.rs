@@ -0,0 +1,111 @@
+//! SSH wire format primitives (RFC 4251 section 5)
+
+use crate::error::{Error, Result};
+use bytes::{Buf, Bytes};
+
+/// Maximum size of a single string field (16 MB).
+/// Prevents memory exhaustion from malicious length fields.
+const MAX_STRING_SIZE: u32 = 16 * 1024 * 1024;
+
+/// Cursor over SSH wire-encoded data
+#[derive(Debug, Clone)]
+pub struct WireReader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> WireReader<'a> {
+    /// Create a reader over the given bytes
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self { buf }
+    }
+
+    /// Number of unread bytes
+    pub fn remaining(&self) -> usize {
+        self.buf.remaining()
+    }
+
+    /// Check if all bytes have been consumed
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    /// Read a single byte
+    pub fn read_u8(&mut self) -> Result<u8> {
+        if self.buf.remaining() < 1 {
+            return Err(Error::InvalidMessage("Unexpected end of data".to_string()));
+        }
+        Ok(self.buf.get_u8())
+    }
+
+    /// Read a boolean (one byte, non-zero is true)
+    pub fn read_bool(&mut self) -> Result<bool> {
+        Ok(self.read_u8()? != 0)
+    }
+
+    /// Read a big-endian uint32
+    pub fn read_u32(&mut self) -> Result<u32> {
+        if self.buf.remaining() < 4 {
+            return Err(Error::InvalidMessage("Unexpected end of data".to_string()));
+        }
+        Ok(self.buf.get_u32())
+    }
+
+    /// Read a length-prefixed byte string
+    pub fn read_string(&mut self) -> Result<Bytes> {
+        let len_u32 = self.read_u32()?;
+        if len_u32 > MAX_STRING_SIZE {
+            return Err(Error::InvalidMessage(format!(
+                "String size {} exceeds maximum allowed {}",
+                len_u32, MAX_STRING_SIZE
+            )));
+        }
+        let len = usize::try_from(len_u32).map_err(|_| {
+            Error::InvalidMessage(format!(
+                "String length {} cannot be converted to usize",
+                len_u32
+            ))
+        })?;
+        if self.buf.remaining() < len {
+            return Err(Error::InvalidMessage("String truncated".to_string()));
+        }
+        let value = Bytes::copy_from_slice(&self.buf[..len]);
+        self.buf.advance(len);
+        Ok(value)
+    }
+
+    /// Read a length-prefixed string as UTF-8 (lossy)
+    pub fn read_utf8(&mut self) -> Result<String> {
+        let bytes = self.read_string()?;
+        Ok(String::from_utf8_lossy(&bytes).to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_fields() {
+        let data = [0, 0, 0, 3, b'a', b'b', b'c', 1, 0, 0, 0, 42];
+        let mut reader = WireReader::new(&data);
+        assert_eq!(reader.read_utf8().unwrap(), "abc");
+        assert!(reader.read_bool().unwrap());
+        assert_eq!(reader.read_u32().unwrap(), 42);
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn test_read_string_truncated() {
+        let data = [0, 0, 0, 10, b'a'];
+        let mut reader = WireReader::new(&data);
+        assert!(reader.read_string().is_err());
+    }
+
+    #[test]
+    fn test_read_string_oversized() {
+        let data = (MAX_STRING_SIZE + 1).to_be_bytes();
+        let mut reader = WireReader::new(&data);
+        let err = reader.read_string().unwrap_err().to_string();
+        assert!(err.contains("exceeds maximum"));
+    }
+}
@@ -1,6 +1,16 @@
 //! Path expansion utilities
 
+use std::io::{self, BufRead, BufReader, Read};
 use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+/// Time to wait for a command printing a path
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Longest first line of output read from a command printing a path
+const MAX_OUTPUT_LINE: u64 = 4096;
 
 /// Expand environment variables and tilde in a path string
 pub fn expand_path(path: &str) -> crate::Result<String> {
@@ -14,6 +24,85 @@ pub fn expand_to_pathbuf(path: &str) -> crate::Result<PathBuf> {
     expand_path(path).map(PathBuf::from)
 }
 
+/// Run a command that prints a path (e.g., `gpgconf --list-dirs agent-ssh-socket`)
+///
+/// The command line is split like a shell would (without running a shell),
+/// and environment variables and tilde are expanded in each argument. The
+/// first line of output is the path, also expanded.
+pub fn command_path(command: &str) -> crate::Result<PathBuf> {
+    let args = split_command(command)?;
+    let mut child = Command::new(&args[0])
+        .args(&args[1..])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| crate::Error::Config(format!("Failed to run '{}': {}", command, e)))?;
+
+    // Read the output while the command runs, so that it cannot block on a
+    // full pipe. The rest of the output is discarded; the reader ends when
+    // every process holding the pipe (e.g. a daemon it started) closes it.
+    let (tx, rx) = mpsc::channel();
+    if let Some(stdout) = child.stdout.take() {
+        std::thread::spawn(move || {
+            let mut reader = BufReader::new(stdout);
+            let mut line = Vec::new();
+            let result = (&mut reader)
+                .take(MAX_OUTPUT_LINE)
+                .read_until(b'\n', &mut line)
+                .map(|_| line);
+            let _ = tx.send(result);
+            let _ = io::copy(&mut reader, &mut io::sink());
+        });
+    }
+
+    let deadline = Instant::now() + COMMAND_TIMEOUT;
+    let timed_out = || {
+        crate::Error::Config(format!(
+            "Command '{}' timed out after {:?}",
+            command, COMMAND_TIMEOUT
+        ))
+    };
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(timed_out());
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    };
+    if !status.success() {
+        return Err(crate::Error::Config(format!(
+            "Command '{}' failed: {}",
+            command, status
+        )));
+    }
+
+    let output = rx
+        .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+        .map_err(|_| timed_out())??;
+    let output = String::from_utf8_lossy(&output);
+    let line = output.lines().next().map(str::trim).unwrap_or_default();
+    if line.is_empty() {
+        return Err(crate::Error::Config(format!(
+            "Command '{}' printed no path",
+            command
+        )));
+    }
+    expand_to_pathbuf(line)
+}
+
+/// Split a command line into expanded arguments
+pub fn split_command(command: &str) -> crate::Result<Vec<String>> {
+    let args = shlex::split(command)
+        .filter(|args| !args.is_empty())
+        .ok_or_else(|| crate::Error::Config(format!("Invalid command: '{}'", command)))?;
+    args.iter().map(|arg| expand_path(arg)).collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -56,4 +145,33 @@ mod tests {
         let result = expand_to_pathbuf("/tmp/test.sock").unwrap();
         assert_eq!(result, PathBuf::from("/tmp/test.sock"));
     }
+
+    #[test]
+    fn test_command_path() {
+        let result = command_path("echo '~/agent.sock'").unwrap();
+        assert!(result.is_absolute());
+        assert!(result.ends_with("agent.sock"));
+
+        let result = command_path("printf '/tmp/first.sock\\n/tmp/second.sock\\n'").unwrap();
+        assert_eq!(result, PathBuf::from("/tmp/first.sock"));
+
+        assert!(command_path("false").is_err());
+        assert!(command_path("true").is_err());
+        assert!(command_path("/nonexistent/command").is_err());
+        assert!(command_path("").is_err());
+        assert!(command_path("echo 'unterminated").is_err());
+    }
+
+    #[test]
+    fn test_command_path_does_not_wait_for_output() {
+        // More output than fits in a pipe
+        let result = command_path("sh -c 'echo /tmp/big.sock; head -c 1000000 /dev/zero'").unwrap();
+        assert_eq!(result, PathBuf::from("/tmp/big.sock"));
+
+        // A background process keeps the output open after the command exits
+        let started = Instant::now();
+        let result = command_path("sh -c 'echo /tmp/daemon.sock; sleep 10 &'").unwrap();
+        assert_eq!(result, PathBuf::from("/tmp/daemon.sock"));
+        assert!(started.elapsed() < COMMAND_TIMEOUT);
+    }
 }
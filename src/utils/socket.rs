@@ -2,11 +2,19 @@
 //!
 //! Provides common operations for Unix socket management including
 //! safe removal, directory creation, and permission setting.
+//!
+//! A path starting with `@` (e.g. `@authsock-work`) names a socket in the
+//! Linux abstract namespace. Such sockets have no file: there is nothing to
+//! remove, create or chmod, and only the peer credential checks of
+//! `agent::PeerPolicy` keep other users out.
 
+use std::ffi::CString;
 use std::fs;
 use std::io;
+use std::os::unix::ffi::OsStrExt;
 use std::os::unix::fs::PermissionsExt;
 use std::path::Path;
+use tokio::net::UnixListener;
 
 /// Error type for socket operations
 #[derive(Debug, thiserror::Error)]
@@ -25,10 +33,123 @@ pub enum SocketError {
 
     #[error("Failed to set permissions on socket at {path}: {source}")]
     PermissionError { path: String, source: io::Error },
+
+    #[error("Failed to change group of {path}: {source}")]
+    OwnerError { path: String, source: io::Error },
+}
+
+/// Default socket file mode (owner read/write only)
+pub const DEFAULT_SOCKET_MODE: u32 = 0o600;
+
+/// Permissions and ownership applied to a socket file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SocketPermissions {
+    /// File mode of the socket (default: 0600)
+    pub mode: u32,
+    /// Group owning the socket (and its parent directory if `dir_mode` is set)
+    pub group: Option<u32>,
+    /// Mode of the socket's parent directory (left alone if `None`)
+    pub dir_mode: Option<u32>,
+}
+
+impl Default for SocketPermissions {
+    fn default() -> Self {
+        Self {
+            mode: DEFAULT_SOCKET_MODE,
+            group: None,
+            dir_mode: None,
+        }
+    }
+}
+
+impl SocketPermissions {
+    /// Check if users other than the owner and group can connect
+    pub fn is_world_accessible(&self) -> bool {
+        self.mode & 0o007 != 0
+    }
+
+    /// Check if users other than the owner and group can replace the socket
+    pub fn is_dir_world_writable(&self) -> bool {
+        self.dir_mode.is_some_and(|mode| mode & 0o002 != 0)
+    }
+}
+
+/// Parse an octal permission string (e.g. `0660` or `660`)
+pub fn parse_mode(mode: &str) -> Option<u32> {
+    let digits = mode.strip_prefix("0o").unwrap_or(mode);
+    u32::from_str_radix(digits, 8)
+        .ok()
+        .filter(|mode| *mode <= 0o777)
+}
+
+/// Look up a group by name or numeric gid
+pub fn group_id(group: &str) -> Option<u32> {
+    if let Ok(gid) = group.parse() {
+        return Some(gid);
+    }
+    let name = CString::new(group).ok()?;
+    let mut buf = vec![0 as libc::c_char; 1024];
+    loop {
+        // SAFETY: all pointers are valid for the duration of the call and
+        // buf.len() is the size of buf
+        let mut entry: libc::group = unsafe { std::mem::zeroed() };
+        let mut result = std::ptr::null_mut();
+        let rc = unsafe {
+            libc::getgrnam_r(
+                name.as_ptr(),
+                &mut entry,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut result,
+            )
+        };
+        if rc == libc::ERANGE && buf.len() < 1 << 20 {
+            buf.resize(buf.len() * 2, 0);
+            continue;
+        }
+        return (rc == 0 && !result.is_null()).then_some(entry.gr_gid);
+    }
+}
+
+/// Get the name of an abstract-namespace socket (`@name`), if the path is one
+pub fn abstract_name(path: &Path) -> Option<&[u8]> {
+    path.as_os_str().as_bytes().strip_prefix(b"@")
+}
+
+/// Check if a path names an abstract-namespace socket (`@name`)
+pub fn is_abstract(path: &Path) -> bool {
+    abstract_name(path).is_some()
+}
+
+/// Bind a listener on a socket path or in the abstract namespace
+pub fn bind_listener(path: &Path) -> io::Result<UnixListener> {
+    match abstract_name(path) {
+        Some(name) => bind_abstract(name),
+        None => UnixListener::bind(path),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn bind_abstract(name: &[u8]) -> io::Result<UnixListener> {
+    use std::os::linux::net::SocketAddrExt;
+    let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+    let listener = std::os::unix::net::UnixListener::bind_addr(&addr)?;
+    listener.set_nonblocking(true)?;
+    UnixListener::from_std(listener)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn bind_abstract(_name: &[u8]) -> io::Result<UnixListener> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "abstract sockets (@name) are only supported on Linux",
+    ))
 }
 
 /// Safely remove an existing socket file if present.
 ///
+/// Does nothing for abstract sockets.
+///
 /// This function uses `symlink_metadata` instead of `exists` to prevent
 /// TOCTOU race conditions. If the path is a symlink, it returns an error
 /// to prevent potential symlink attacks.
@@ -38,6 +159,9 @@ pub enum SocketError {
 /// - Returns `MetadataError` if metadata cannot be read (except NotFound)
 /// - Returns `RemoveError` if the file cannot be removed
 pub fn remove_existing_socket(path: &Path) -> Result<(), SocketError> {
+    if is_abstract(path) {
+        return Ok(());
+    }
     match fs::symlink_metadata(path) {
         Ok(metadata) => {
             // Check if it's a symlink (potential attack)
@@ -82,15 +206,52 @@ pub fn ensure_parent_dir(path: &Path) -> Result<(), SocketError> {
     Ok(())
 }
 
-/// Set socket permissions to owner read/write only (0600).
+/// Set the mode and group of the parent directory, if configured.
+///
+/// Creates the directory first if it does not exist.
+///
+/// # Errors
+/// Returns `CreateDirError`, `PermissionError` or `OwnerError` if the
+/// directory cannot be set up.
+pub fn set_dir_permissions(
+    path: &Path,
+    permissions: &SocketPermissions,
+) -> Result<(), SocketError> {
+    let (Some(dir_mode), Some(parent)) = (permissions.dir_mode, path.parent()) else {
+        return Ok(());
+    };
+    ensure_parent_dir(path)?;
+    set_mode_and_group(parent, dir_mode, permissions.group)
+}
+
+/// Set socket permissions (owner read/write only (0600) by default).
 ///
 /// This should be called immediately after binding a Unix socket
-/// to ensure only the owner can connect.
+/// to ensure only the owner (and the configured group) can connect.
+/// Abstract sockets have no permissions; only peer credential checks
+/// apply to them.
 ///
 /// # Errors
-/// Returns `PermissionError` if permissions cannot be set.
-pub fn set_socket_permissions(path: &Path) -> Result<(), SocketError> {
-    fs::set_permissions(path, fs::Permissions::from_mode(0o600)).map_err(|e| {
+/// Returns `PermissionError` if permissions cannot be set, or `OwnerError`
+/// if the group cannot be changed.
+pub fn set_socket_permissions(
+    path: &Path,
+    permissions: &SocketPermissions,
+) -> Result<(), SocketError> {
+    if is_abstract(path) {
+        return Ok(());
+    }
+    set_mode_and_group(path, permissions.mode, permissions.group)
+}
+
+fn set_mode_and_group(path: &Path, mode: u32, group: Option<u32>) -> Result<(), SocketError> {
+    if let Some(gid) = group {
+        std::os::unix::fs::chown(path, None, Some(gid)).map_err(|e| SocketError::OwnerError {
+            path: path.display().to_string(),
+            source: e,
+        })?;
+    }
+    fs::set_permissions(path, fs::Permissions::from_mode(mode)).map_err(|e| {
         SocketError::PermissionError {
             path: path.display().to_string(),
             source: e,
@@ -103,14 +264,22 @@ pub fn set_socket_permissions(path: &Path) -> Result<(), SocketError> {
 /// This is a convenience function that:
 /// 1. Removes any existing socket file (with symlink protection)
 /// 2. Creates the parent directory if needed
+/// 3. Sets the parent directory's mode and group if configured
 ///
 /// Call `set_socket_permissions` after binding the socket.
 ///
 /// # Errors
 /// Returns an error if any of the preparation steps fail.
-pub fn prepare_socket_path(path: &Path) -> Result<(), SocketError> {
+pub fn prepare_socket_path(
+    path: &Path,
+    permissions: &SocketPermissions,
+) -> Result<(), SocketError> {
+    if is_abstract(path) {
+        return Ok(());
+    }
     remove_existing_socket(path)?;
     ensure_parent_dir(path)?;
+    set_dir_permissions(path, permissions)?;
     Ok(())
 }
 
@@ -172,11 +341,66 @@ mod tests {
         let dir = tempdir().unwrap();
         let path = dir.path().join("test.sock");
         fs::write(&path, b"test").unwrap();
-        assert!(set_socket_permissions(&path).is_ok());
+        assert!(set_socket_permissions(&path, &SocketPermissions::default()).is_ok());
         let perms = fs::metadata(&path).unwrap().permissions();
         assert_eq!(perms.mode() & 0o777, 0o600);
     }
 
+    #[test]
+    fn test_set_socket_group_permissions() {
+        use std::os::unix::fs::MetadataExt;
+
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("shared/test.sock");
+        // SAFETY: getegid has no preconditions and cannot fail
+        let gid = unsafe { libc::getegid() };
+        let permissions = SocketPermissions {
+            mode: 0o660,
+            group: Some(gid),
+            dir_mode: Some(0o750),
+        };
+        assert!(prepare_socket_path(&path, &permissions).is_ok());
+        fs::write(&path, b"test").unwrap();
+        assert!(set_socket_permissions(&path, &permissions).is_ok());
+
+        let meta = fs::metadata(&path).unwrap();
+        assert_eq!(meta.mode() & 0o777, 0o660);
+        assert_eq!(meta.gid(), gid);
+        let meta = fs::metadata(path.parent().unwrap()).unwrap();
+        assert_eq!(meta.mode() & 0o777, 0o750);
+    }
+
+    #[test]
+    fn test_parse_mode() {
+        assert_eq!(parse_mode("0660"), Some(0o660));
+        assert_eq!(parse_mode("600"), Some(0o600));
+        assert_eq!(parse_mode("0o640"), Some(0o640));
+        assert_eq!(parse_mode("4755"), None);
+        assert_eq!(parse_mode("0688"), None);
+        assert!(
+            SocketPermissions {
+                mode: 0o666,
+                ..Default::default()
+            }
+            .is_world_accessible()
+        );
+        assert!(
+            !SocketPermissions {
+                mode: 0o660,
+                ..Default::default()
+            }
+            .is_world_accessible()
+        );
+    }
+
+    #[test]
+    fn test_group_id() {
+        assert_eq!(group_id("1234"), Some(1234));
+        assert_eq!(group_id("no-such-group-authsock"), None);
+        #[cfg(target_os = "linux")]
+        assert_eq!(group_id("root"), Some(0));
+    }
+
     #[test]
     fn test_prepare_socket_path() {
         let dir = tempdir().unwrap();
@@ -185,8 +409,43 @@ mod tests {
         fs::create_dir(dir.path().join("subdir")).unwrap();
         fs::write(&path, b"old").unwrap();
 
-        assert!(prepare_socket_path(&path).is_ok());
+        assert!(prepare_socket_path(&path, &SocketPermissions::default()).is_ok());
         assert!(!path.exists()); // Old file removed
         assert!(dir.path().join("subdir").exists()); // Dir still exists
     }
+
+    #[test]
+    fn test_abstract_name() {
+        assert_eq!(
+            abstract_name(Path::new("@authsock-work")),
+            Some(&b"authsock-work"[..])
+        );
+        assert!(!is_abstract(Path::new("/tmp/@work.sock")));
+        assert!(!is_abstract(Path::new("work.sock")));
+    }
+
+    #[test]
+    fn test_prepare_abstract_socket() {
+        let path = Path::new("@authsock-test-prepare");
+        let permissions = SocketPermissions::default();
+        assert!(prepare_socket_path(path, &permissions).is_ok());
+        assert!(set_socket_permissions(path, &permissions).is_ok());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_bind_abstract_socket() {
+        use std::os::linux::net::SocketAddrExt;
+
+        let name = format!("authsock-filter-test-{}", std::process::id());
+        let path = format!("@{}", name);
+        let listener = bind_listener(Path::new(&path)).unwrap();
+        assert!(!Path::new(&path).exists());
+
+        let addr = std::os::unix::net::SocketAddr::from_abstract_name(name.as_bytes()).unwrap();
+        let client = std::os::unix::net::UnixStream::connect_addr(&addr).unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+        assert!(server.peer_cred().is_ok());
+        drop(client);
+    }
 }
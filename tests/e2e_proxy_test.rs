@@ -1,10 +1,11 @@
 //! End-to-end proxy filtering tests with mock SSH agent
 
-use authsock_filter::agent::{Proxy, Upstream};
+use authsock_filter::agent::{AccessPolicy, PoolConfig, Proxy, Upstream};
 use authsock_filter::filter::FilterEvaluator;
 use authsock_filter::protocol::{AgentCodec, AgentMessage, Identity, MessageType};
-use bytes::Bytes;
-use ssh_key::PublicKey;
+use bytes::{BufMut, Bytes, BytesMut};
+use signature::Signer;
+use ssh_key::{Algorithm, PrivateKey, PublicKey};
 use std::sync::Arc;
 use tempfile::TempDir;
 use tokio::net::{UnixListener, UnixStream};
@@ -45,6 +46,12 @@ async fn start_mock_agent(socket_path: &std::path::Path, identities: Vec<Identit
                         MessageType::RequestIdentities => {
                             AgentMessage::build_identities_answer(&identities)
                         }
+                        MessageType::RemoveAllIdentities | MessageType::Lock => {
+                            AgentMessage::success()
+                        }
+                        MessageType::SignRequest => {
+                            AgentMessage::new(MessageType::SignResponse, Bytes::new())
+                        }
                         _ => AgentMessage::failure(),
                     };
 
@@ -330,6 +337,757 @@ async fn test_proxy_excludes_by_key_type() {
     );
 }
 
+/// Connect to an agent and send a request without payload
+async fn send_simple_request(socket_path: &std::path::Path, msg_type: MessageType) -> MessageType {
+    let mut stream = UnixStream::connect(socket_path).await.unwrap();
+    let (mut reader, mut writer) = stream.split();
+
+    let request = AgentMessage::new(msg_type, Bytes::new());
+    AgentCodec::write(&mut writer, &request).await.unwrap();
+
+    let response = AgentCodec::read(&mut reader).await.unwrap().unwrap();
+    response.msg_type
+}
+
+#[tokio::test]
+async fn test_proxy_denies_disallowed_messages() {
+    let temp_dir = TempDir::new().unwrap();
+    let upstream_path = temp_dir.path().join("upstream.sock");
+    let proxy_path = temp_dir.path().join("proxy.sock");
+
+    start_mock_agent(&upstream_path, vec![make_identity(ED25519_KEY_WORK)]).await;
+
+    // Deny key removal, allow everything else
+    let filter = FilterEvaluator::parse(&[]).unwrap();
+    let upstream = Upstream::new(upstream_path.to_str().unwrap());
+    let proxy = Arc::new(
+        Proxy::new(upstream, filter).with_access_policy(AccessPolicy {
+            allow_remove: Some(false),
+            ..Default::default()
+        }),
+    );
+
+    start_proxy_server(&proxy_path, proxy).await;
+
+    // Denied message is answered by the proxy with FAILURE
+    assert_eq!(
+        send_simple_request(&proxy_path, MessageType::RemoveAllIdentities).await,
+        MessageType::Failure,
+        "REMOVE_ALL_IDENTITIES should be denied"
+    );
+
+    // Allowed message is forwarded to upstream
+    assert_eq!(
+        send_simple_request(&proxy_path, MessageType::Lock).await,
+        MessageType::Success,
+        "LOCK should be forwarded"
+    );
+}
+
+fn put_string(buf: &mut BytesMut, s: &[u8]) {
+    buf.put_u32(s.len() as u32);
+    buf.put_slice(s);
+}
+
+/// Build SSH_MSG_USERAUTH_REQUEST data as signed by ssh(1)
+fn build_userauth_data(user: &str, identity: &Identity) -> Bytes {
+    build_session_userauth_data(b"session-id", user, identity)
+}
+
+/// Build SSH_MSG_USERAUTH_REQUEST data for the given SSH session
+fn build_session_userauth_data(session_id: &[u8], user: &str, identity: &Identity) -> Bytes {
+    let mut buf = BytesMut::new();
+    put_string(&mut buf, session_id);
+    buf.put_u8(50); // SSH_MSG_USERAUTH_REQUEST
+    put_string(&mut buf, user.as_bytes());
+    put_string(&mut buf, b"ssh-connection");
+    put_string(&mut buf, b"publickey");
+    buf.put_u8(1);
+    put_string(&mut buf, b"ssh-ed25519");
+    put_string(&mut buf, &identity.key_blob);
+    buf.freeze()
+}
+
+/// Build an SSH_AGENTC_SIGN_REQUEST
+fn build_sign_request(identity: &Identity, data: &[u8]) -> AgentMessage {
+    let mut payload = BytesMut::new();
+    put_string(&mut payload, &identity.key_blob);
+    put_string(&mut payload, data);
+    payload.put_u32(0);
+    AgentMessage::new(MessageType::SignRequest, payload.freeze())
+}
+
+/// Send a request on an open connection and read the response
+async fn roundtrip(stream: &mut UnixStream, request: &AgentMessage) -> AgentMessage {
+    let (mut reader, mut writer) = stream.split();
+    AgentCodec::write(&mut writer, request).await.unwrap();
+    AgentCodec::read(&mut reader).await.unwrap().unwrap()
+}
+
+/// Request identities on an open connection
+async fn list_on(stream: &mut UnixStream) -> Vec<Identity> {
+    let request = AgentMessage::new(MessageType::RequestIdentities, Bytes::new());
+    roundtrip(stream, &request)
+        .await
+        .parse_identities()
+        .unwrap()
+}
+
+/// Connect to an agent and send a sign request
+async fn sign(socket_path: &std::path::Path, identity: &Identity, data: &[u8]) -> MessageType {
+    let mut stream = UnixStream::connect(socket_path).await.unwrap();
+    roundtrip(&mut stream, &build_sign_request(identity, data))
+        .await
+        .msg_type
+}
+
+#[tokio::test]
+async fn test_proxy_filters_sign_by_remote_user() {
+    let temp_dir = TempDir::new().unwrap();
+    let upstream_path = temp_dir.path().join("upstream.sock");
+    let proxy_path = temp_dir.path().join("proxy.sock");
+
+    let work_key = make_identity(ED25519_KEY_WORK);
+    start_mock_agent(&upstream_path, vec![work_key.clone()]).await;
+
+    let filter = FilterEvaluator::parse(&[vec![
+        "comment=*@work.example.com".to_string(),
+        "ssh-user=deploy".to_string(),
+    ]])
+    .unwrap();
+    let upstream = Upstream::new(upstream_path.to_str().unwrap());
+    let proxy = Arc::new(Proxy::new(upstream, filter));
+
+    start_proxy_server(&proxy_path, proxy).await;
+
+    // Sign request filters do not hide the key from listing; signing on the
+    // listing connection sees the key's comment
+    let mut stream = UnixStream::connect(&proxy_path).await.unwrap();
+    assert_eq!(list_on(&mut stream).await.len(), 1);
+
+    let mut sign_on = async |data: &[u8]| {
+        roundtrip(&mut stream, &build_sign_request(&work_key, data))
+            .await
+            .msg_type
+    };
+    assert_eq!(
+        sign_on(&build_userauth_data("deploy", &work_key)).await,
+        MessageType::SignResponse,
+        "login as deploy should be signed"
+    );
+    assert_eq!(
+        sign_on(&build_userauth_data("root", &work_key)).await,
+        MessageType::Failure,
+        "login as root should be denied"
+    );
+    assert_eq!(
+        sign_on(b"arbitrary data").await,
+        MessageType::Failure,
+        "non-userauth data should be denied"
+    );
+}
+
+/// Build SSHSIG signed data as produced by ssh-keygen -Y sign
+fn build_sshsig_data(namespace: &str) -> Bytes {
+    let mut buf = BytesMut::new();
+    buf.put_slice(b"SSHSIG");
+    put_string(&mut buf, namespace.as_bytes());
+    put_string(&mut buf, b"");
+    put_string(&mut buf, b"sha512");
+    put_string(&mut buf, &[0u8; 64]);
+    buf.freeze()
+}
+
+#[tokio::test]
+async fn test_proxy_filters_sign_by_sshsig_namespace() {
+    let temp_dir = TempDir::new().unwrap();
+    let upstream_path = temp_dir.path().join("upstream.sock");
+    let proxy_path = temp_dir.path().join("proxy.sock");
+
+    let work_key = make_identity(ED25519_KEY_WORK);
+    start_mock_agent(&upstream_path, vec![work_key.clone()]).await;
+
+    let filter = FilterEvaluator::parse(&[vec!["namespace=git".to_string()]]).unwrap();
+    let upstream = Upstream::new(upstream_path.to_str().unwrap());
+    let proxy = Arc::new(Proxy::new(upstream, filter));
+
+    start_proxy_server(&proxy_path, proxy).await;
+
+    assert_eq!(
+        sign(&proxy_path, &work_key, &build_sshsig_data("git")).await,
+        MessageType::SignResponse,
+        "git commit signing should be allowed"
+    );
+    assert_eq!(
+        sign(&proxy_path, &work_key, &build_sshsig_data("file")).await,
+        MessageType::Failure,
+        "file signing should be denied"
+    );
+    assert_eq!(
+        sign(
+            &proxy_path,
+            &work_key,
+            &build_userauth_data("git", &work_key)
+        )
+        .await,
+        MessageType::Failure,
+        "SSH authentication should be denied"
+    );
+}
+
+#[tokio::test]
+async fn test_proxy_denies_unknown_sign_data() {
+    let temp_dir = TempDir::new().unwrap();
+    let upstream_path = temp_dir.path().join("upstream.sock");
+    let strict_path = temp_dir.path().join("strict.sock");
+    let permissive_path = temp_dir.path().join("permissive.sock");
+
+    let work_key = make_identity(ED25519_KEY_WORK);
+    start_mock_agent(&upstream_path, vec![work_key.clone()]).await;
+
+    let strict = Arc::new(Proxy::new(
+        Upstream::new(upstream_path.to_str().unwrap()),
+        FilterEvaluator::default(),
+    ));
+    let permissive = Arc::new(
+        Proxy::new(
+            Upstream::new(upstream_path.to_str().unwrap()),
+            FilterEvaluator::default(),
+        )
+        .with_access_policy(AccessPolicy {
+            allow_unknown_sign: Some(true),
+            ..Default::default()
+        }),
+    );
+
+    start_proxy_server(&strict_path, strict).await;
+    start_proxy_server(&permissive_path, permissive).await;
+
+    // Recognised payloads are signed by default
+    assert_eq!(
+        sign(
+            &strict_path,
+            &work_key,
+            &build_userauth_data("git", &work_key)
+        )
+        .await,
+        MessageType::SignResponse,
+    );
+    assert_eq!(
+        sign(&strict_path, &work_key, &build_sshsig_data("git")).await,
+        MessageType::SignResponse,
+    );
+
+    // Arbitrary data only where explicitly permitted
+    assert_eq!(
+        sign(&strict_path, &work_key, b"arbitrary data").await,
+        MessageType::Failure,
+        "unknown data should be denied by default"
+    );
+    assert_eq!(
+        sign(&permissive_path, &work_key, b"arbitrary data").await,
+        MessageType::SignResponse,
+        "unknown data should be signed with allow_unknown_sign"
+    );
+}
+
+/// Build a session-bind@openssh.com extension signed by the host key
+fn build_session_bind(host_key: &PrivateKey, session_id: &[u8]) -> AgentMessage {
+    let signature: ssh_key::Signature = host_key.try_sign(session_id).unwrap();
+    let mut payload = BytesMut::new();
+    put_string(&mut payload, b"session-bind@openssh.com");
+    put_string(&mut payload, &host_key.public_key().to_bytes().unwrap());
+    put_string(&mut payload, session_id);
+    put_string(&mut payload, &Vec::<u8>::try_from(signature).unwrap());
+    payload.put_u8(0);
+    AgentMessage::new(MessageType::Extension, payload.freeze())
+}
+
+#[tokio::test]
+async fn test_proxy_filters_by_bound_host() {
+    let temp_dir = TempDir::new().unwrap();
+    let upstream_path = temp_dir.path().join("upstream.sock");
+    let proxy_path = temp_dir.path().join("proxy.sock");
+
+    let work_key = make_identity(ED25519_KEY_WORK);
+    start_mock_agent(&upstream_path, vec![work_key.clone()]).await;
+
+    let mut rng = rand::thread_rng();
+    let github = PrivateKey::random(&mut rng, Algorithm::Ed25519).unwrap();
+    let gitlab = PrivateKey::random(&mut rng, Algorithm::Ed25519).unwrap();
+    let known_hosts = temp_dir.path().join("known_hosts");
+    std::fs::write(
+        &known_hosts,
+        format!(
+            "github.com {}\ngitlab.com {}\n",
+            github.public_key().to_openssh().unwrap(),
+            gitlab.public_key().to_openssh().unwrap()
+        ),
+    )
+    .unwrap();
+
+    let filter = FilterEvaluator::parse(&[vec!["host=github.com".to_string()]]).unwrap();
+    let upstream = Upstream::new(upstream_path.to_str().unwrap());
+    let proxy = Arc::new(Proxy::new(upstream, filter).with_known_hosts(vec![known_hosts]));
+
+    start_proxy_server(&proxy_path, proxy).await;
+
+    // Bound to github.com: the key is listed and signs for that session only
+    let mut stream = UnixStream::connect(&proxy_path).await.unwrap();
+    roundtrip(&mut stream, &build_session_bind(&github, b"github-session")).await;
+    let listed = roundtrip(
+        &mut stream,
+        &AgentMessage::new(MessageType::RequestIdentities, Bytes::new()),
+    )
+    .await;
+    assert_eq!(listed.parse_identities().unwrap().len(), 1);
+    let data = build_session_userauth_data(b"github-session", "git", &work_key);
+    assert_eq!(
+        roundtrip(&mut stream, &build_sign_request(&work_key, &data))
+            .await
+            .msg_type,
+        MessageType::SignResponse,
+        "login to the bound github.com session should be signed"
+    );
+    let data = build_session_userauth_data(b"unbound-session", "git", &work_key);
+    assert_eq!(
+        roundtrip(&mut stream, &build_sign_request(&work_key, &data))
+            .await
+            .msg_type,
+        MessageType::Failure,
+        "login to an unbound session should be denied"
+    );
+
+    // Bound to gitlab.com: the key is neither listed nor used
+    let mut stream = UnixStream::connect(&proxy_path).await.unwrap();
+    roundtrip(&mut stream, &build_session_bind(&gitlab, b"gitlab-session")).await;
+    let listed = roundtrip(
+        &mut stream,
+        &AgentMessage::new(MessageType::RequestIdentities, Bytes::new()),
+    )
+    .await;
+    assert!(listed.parse_identities().unwrap().is_empty());
+    let data = build_session_userauth_data(b"gitlab-session", "git", &work_key);
+    assert_eq!(
+        roundtrip(&mut stream, &build_sign_request(&work_key, &data))
+            .await
+            .msg_type,
+        MessageType::Failure,
+        "login to gitlab.com should be denied"
+    );
+
+    // A forged binding is rejected and not recorded
+    let mut stream = UnixStream::connect(&proxy_path).await.unwrap();
+    let mut forged = build_session_bind(&github, b"github-session");
+    let mut payload = forged.payload.to_vec();
+    let last = payload.len() - 2;
+    payload[last] ^= 0xff;
+    forged.payload = Bytes::from(payload);
+    assert_eq!(
+        roundtrip(&mut stream, &forged).await.msg_type,
+        MessageType::Failure
+    );
+    let data = build_session_userauth_data(b"github-session", "git", &work_key);
+    assert_eq!(
+        roundtrip(&mut stream, &build_sign_request(&work_key, &data))
+            .await
+            .msg_type,
+        MessageType::Failure,
+        "login without a verified binding should be denied"
+    );
+}
+
+#[tokio::test]
+async fn test_proxy_ignores_binding_when_extensions_denied() {
+    let temp_dir = TempDir::new().unwrap();
+    let upstream_path = temp_dir.path().join("upstream.sock");
+    let proxy_path = temp_dir.path().join("proxy.sock");
+
+    let work_key = make_identity(ED25519_KEY_WORK);
+    start_mock_agent(&upstream_path, vec![work_key.clone()]).await;
+
+    let mut rng = rand::thread_rng();
+    let github = PrivateKey::random(&mut rng, Algorithm::Ed25519).unwrap();
+    let known_hosts = temp_dir.path().join("known_hosts");
+    std::fs::write(
+        &known_hosts,
+        format!("github.com {}\n", github.public_key().to_openssh().unwrap()),
+    )
+    .unwrap();
+
+    let filter = FilterEvaluator::parse(&[vec!["host=github.com".to_string()]]).unwrap();
+    let upstream = Upstream::new(upstream_path.to_str().unwrap());
+    let proxy = Arc::new(
+        Proxy::new(upstream, filter)
+            .with_known_hosts(vec![known_hosts])
+            .with_access_policy(AccessPolicy {
+                allow_extension: Some(false),
+                ..Default::default()
+            }),
+    );
+
+    start_proxy_server(&proxy_path, proxy).await;
+
+    // The denied extension is refused and its binding not recorded
+    let mut stream = UnixStream::connect(&proxy_path).await.unwrap();
+    assert_eq!(
+        roundtrip(&mut stream, &build_session_bind(&github, b"github-session"))
+            .await
+            .msg_type,
+        MessageType::Failure
+    );
+    let data = build_session_userauth_data(b"github-session", "git", &work_key);
+    assert_eq!(
+        roundtrip(&mut stream, &build_sign_request(&work_key, &data))
+            .await
+            .msg_type,
+        MessageType::Failure,
+        "login should be denied without a recorded binding"
+    );
+}
+
+/// Start a mock SSH agent that closes each connection after `max_messages`
+/// requests and records the request types received on each connection
+async fn start_recording_agent(
+    socket_path: &std::path::Path,
+    identities: Vec<Identity>,
+    max_messages: usize,
+) -> Arc<std::sync::Mutex<Vec<Vec<MessageType>>>> {
+    let listener = UnixListener::bind(socket_path).unwrap();
+    let connections = Arc::new(std::sync::Mutex::new(Vec::new()));
+
+    let recorded = connections.clone();
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = match listener.accept().await {
+                Ok(conn) => conn,
+                Err(_) => break,
+            };
+
+            let identities = identities.clone();
+            let index = {
+                let mut connections = recorded.lock().unwrap();
+                connections.push(Vec::new());
+                connections.len() - 1
+            };
+            let recorded = recorded.clone();
+            tokio::spawn(async move {
+                let (mut reader, mut writer) = stream.split();
+                for _ in 0..max_messages {
+                    let msg = match AgentCodec::read(&mut reader).await {
+                        Ok(Some(msg)) => msg,
+                        _ => break,
+                    };
+                    recorded.lock().unwrap()[index].push(msg.msg_type);
+
+                    let response = match msg.msg_type {
+                        MessageType::RequestIdentities => {
+                            AgentMessage::build_identities_answer(&identities)
+                        }
+                        MessageType::Extension => AgentMessage::success(),
+                        MessageType::SignRequest => {
+                            AgentMessage::new(MessageType::SignResponse, Bytes::new())
+                        }
+                        _ => AgentMessage::failure(),
+                    };
+                    if AgentCodec::write(&mut writer, &response).await.is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+    });
+
+    tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+    connections
+}
+
+#[tokio::test]
+async fn test_proxy_reuses_upstream_connection_per_client() {
+    let temp_dir = TempDir::new().unwrap();
+    let upstream_path = temp_dir.path().join("upstream.sock");
+    let proxy_path = temp_dir.path().join("proxy.sock");
+
+    let connections =
+        start_recording_agent(&upstream_path, vec![make_identity(ED25519_KEY_WORK)], 100).await;
+
+    let upstream = Upstream::new(upstream_path.to_str().unwrap());
+    let proxy = Arc::new(Proxy::new(upstream, FilterEvaluator::default()));
+    start_proxy_server(&proxy_path, proxy).await;
+
+    let list = AgentMessage::new(MessageType::RequestIdentities, Bytes::new());
+    let mut first = UnixStream::connect(&proxy_path).await.unwrap();
+    for _ in 0..3 {
+        assert_eq!(
+            roundtrip(&mut first, &list).await.msg_type,
+            MessageType::IdentitiesAnswer
+        );
+    }
+    let mut second = UnixStream::connect(&proxy_path).await.unwrap();
+    roundtrip(&mut second, &list).await;
+    roundtrip(&mut first, &list).await;
+
+    let connections = connections.lock().unwrap();
+    assert_eq!(connections.len(), 2, "one upstream connection per client");
+    assert_eq!(connections[0].len(), 4);
+    assert_eq!(connections[1].len(), 1);
+}
+
+#[tokio::test]
+async fn test_proxy_pools_listing_connections_across_clients() {
+    let temp_dir = TempDir::new().unwrap();
+    let upstream_path = temp_dir.path().join("upstream.sock");
+    let proxy_path = temp_dir.path().join("proxy.sock");
+
+    let connections =
+        start_recording_agent(&upstream_path, vec![make_identity(ED25519_KEY_WORK)], 100).await;
+
+    let upstream = Arc::new(
+        Upstream::new(upstream_path.to_str().unwrap()).with_pool(PoolConfig {
+            max_size: 2,
+            idle_timeout: std::time::Duration::from_secs(60),
+        }),
+    );
+    let proxy = Arc::new(Proxy::new_shared(
+        upstream.clone(),
+        Arc::new(FilterEvaluator::default()),
+    ));
+    start_proxy_server(&proxy_path, proxy).await;
+
+    let list = AgentMessage::new(MessageType::RequestIdentities, Bytes::new());
+    for _ in 0..3 {
+        let mut client = UnixStream::connect(&proxy_path).await.unwrap();
+        assert_eq!(
+            roundtrip(&mut client, &list).await.msg_type,
+            MessageType::IdentitiesAnswer
+        );
+    }
+
+    // A bound client keeps listing on its own connection
+    let host_key = PrivateKey::random(&mut rand::thread_rng(), Algorithm::Ed25519).unwrap();
+    let mut bound = UnixStream::connect(&proxy_path).await.unwrap();
+    roundtrip(&mut bound, &build_session_bind(&host_key, b"session")).await;
+    roundtrip(&mut bound, &list).await;
+
+    let connections = connections.lock().unwrap();
+    assert_eq!(connections.len(), 2);
+    assert_eq!(connections[0], vec![MessageType::RequestIdentities; 3]);
+    assert_eq!(
+        connections[1],
+        vec![MessageType::Extension, MessageType::RequestIdentities]
+    );
+
+    let stats = upstream.pool_stats().unwrap();
+    assert_eq!(stats.created, 1);
+    assert_eq!(stats.reused, 2);
+}
+
+#[tokio::test]
+async fn test_proxy_merges_upstreams_and_routes_sign_requests() {
+    let temp_dir = TempDir::new().unwrap();
+    let system_path = temp_dir.path().join("system.sock");
+    let vault_path = temp_dir.path().join("vault.sock");
+    let proxy_path = temp_dir.path().join("proxy.sock");
+
+    let system = start_recording_agent(
+        &system_path,
+        vec![
+            make_identity(ED25519_KEY_WORK),
+            make_identity(ED25519_KEY_DEV),
+        ],
+        100,
+    )
+    .await;
+    let vault = start_recording_agent(
+        &vault_path,
+        vec![
+            make_identity(ED25519_KEY_PERSONAL),
+            make_identity(ED25519_KEY_WORK),
+        ],
+        100,
+    )
+    .await;
+
+    // Everything from the system agent, only personal keys from the vault
+    let filter = FilterEvaluator::parse(&[
+        vec!["upstream=system".to_string()],
+        vec![
+            "upstream=vault".to_string(),
+            "comment=*@personal*".to_string(),
+        ],
+    ])
+    .unwrap();
+    let proxy = Proxy::new_shared(
+        Arc::new(Upstream::new(&system_path).with_name("system")),
+        Arc::new(filter),
+    )
+    .with_merged_upstream(Arc::new(Upstream::new(&vault_path).with_name("vault")));
+    start_proxy_server(&proxy_path, Arc::new(proxy)).await;
+
+    // Signing with a key that was never listed locates its upstream first
+    let personal = make_identity(ED25519_KEY_PERSONAL);
+    assert_eq!(
+        sign(
+            &proxy_path,
+            &personal,
+            &build_userauth_data("git", &personal)
+        )
+        .await,
+        MessageType::SignResponse
+    );
+
+    // Identities are concatenated in upstream order without duplicates
+    let identities = request_identities(&proxy_path).await;
+    let comments: Vec<&str> = identities.iter().map(|i| i.comment.as_str()).collect();
+    assert_eq!(
+        comments,
+        vec![
+            "user@work.example.com",
+            "dev@work.example.com",
+            "user@personal.example.com"
+        ]
+    );
+
+    // The work key is held by both agents and signed by the first one
+    let work = make_identity(ED25519_KEY_WORK);
+    assert_eq!(
+        sign(&proxy_path, &work, &build_userauth_data("git", &work)).await,
+        MessageType::SignResponse
+    );
+
+    let signed_by = |connections: &Arc<std::sync::Mutex<Vec<Vec<MessageType>>>>| {
+        connections
+            .lock()
+            .unwrap()
+            .iter()
+            .flatten()
+            .filter(|t| **t == MessageType::SignRequest)
+            .count()
+    };
+    assert_eq!(signed_by(&system), 1);
+    assert_eq!(signed_by(&vault), 1);
+}
+
+#[tokio::test]
+async fn test_proxy_fails_over_to_next_agent() {
+    let temp_dir = TempDir::new().unwrap();
+    let locked_path = temp_dir.path().join("locked.sock");
+    let fallback_path = temp_dir.path().join("fallback.sock");
+    let proxy_path = temp_dir.path().join("proxy.sock");
+
+    // A locked password manager lists no keys
+    let locked = start_recording_agent(&locked_path, vec![], 100).await;
+    let fallback =
+        start_recording_agent(&fallback_path, vec![make_identity(ED25519_KEY_WORK)], 100).await;
+
+    let upstream = Upstream::new(&locked_path).with_fallback("fallback", &fallback_path);
+    let proxy = Arc::new(Proxy::new(upstream, FilterEvaluator::default()));
+    start_proxy_server(&proxy_path, proxy).await;
+
+    let work = make_identity(ED25519_KEY_WORK);
+    let mut stream = UnixStream::connect(&proxy_path).await.unwrap();
+    let list = AgentMessage::new(MessageType::RequestIdentities, Bytes::new());
+    let identities = roundtrip(&mut stream, &list)
+        .await
+        .parse_identities()
+        .unwrap();
+    assert_eq!(identities.len(), 1);
+    assert_eq!(
+        roundtrip(
+            &mut stream,
+            &build_sign_request(&work, &build_userauth_data("git", &work))
+        )
+        .await
+        .msg_type,
+        MessageType::SignResponse
+    );
+
+    // The client stays with the agent that listed the key
+    assert_eq!(
+        *locked.lock().unwrap(),
+        vec![vec![MessageType::RequestIdentities]]
+    );
+    assert_eq!(
+        *fallback.lock().unwrap(),
+        vec![vec![
+            MessageType::RequestIdentities,
+            MessageType::SignRequest
+        ]]
+    );
+}
+
+#[tokio::test]
+async fn test_proxy_waits_for_upstream_to_appear() {
+    let temp_dir = TempDir::new().unwrap();
+    let upstream_path = temp_dir.path().join("upstream.sock");
+    let proxy_path = temp_dir.path().join("proxy.sock");
+
+    // The upstream agent does not exist yet
+    let upstream = Upstream::new(upstream_path.to_str().unwrap());
+    let proxy = Arc::new(Proxy::new(upstream, FilterEvaluator::default()));
+    start_proxy_server(&proxy_path, proxy).await;
+
+    let work = make_identity(ED25519_KEY_WORK);
+    let list = AgentMessage::new(MessageType::RequestIdentities, Bytes::new());
+    let mut stream = UnixStream::connect(&proxy_path).await.unwrap();
+    let response = roundtrip(&mut stream, &list).await;
+    assert!(response.parse_identities().unwrap().is_empty());
+    assert_eq!(
+        roundtrip(
+            &mut stream,
+            &build_sign_request(&work, &build_userauth_data("git", &work))
+        )
+        .await
+        .msg_type,
+        MessageType::Failure
+    );
+
+    // Once the agent starts, the same client connection sees its keys
+    start_mock_agent(&upstream_path, vec![work]).await;
+    let response = roundtrip(&mut stream, &list).await;
+    assert_eq!(response.parse_identities().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn test_proxy_reconnects_and_replays_session_bind() {
+    let temp_dir = TempDir::new().unwrap();
+    let upstream_path = temp_dir.path().join("upstream.sock");
+    let proxy_path = temp_dir.path().join("proxy.sock");
+
+    // Upstream drops every connection after two requests
+    let connections =
+        start_recording_agent(&upstream_path, vec![make_identity(ED25519_KEY_WORK)], 2).await;
+
+    let upstream = Upstream::new(upstream_path.to_str().unwrap());
+    let proxy = Arc::new(Proxy::new(upstream, FilterEvaluator::default()));
+    start_proxy_server(&proxy_path, proxy).await;
+
+    let host_key = PrivateKey::random(&mut rand::thread_rng(), Algorithm::Ed25519).unwrap();
+    let list = AgentMessage::new(MessageType::RequestIdentities, Bytes::new());
+    let mut stream = UnixStream::connect(&proxy_path).await.unwrap();
+    assert_eq!(
+        roundtrip(&mut stream, &build_session_bind(&host_key, b"session"))
+            .await
+            .msg_type,
+        MessageType::Success
+    );
+    for _ in 0..2 {
+        assert_eq!(
+            roundtrip(&mut stream, &list).await.msg_type,
+            MessageType::IdentitiesAnswer,
+            "requests should succeed across upstream reconnects"
+        );
+    }
+
+    let connections = connections.lock().unwrap();
+    assert_eq!(connections.len(), 2);
+    assert_eq!(
+        connections[1],
+        vec![MessageType::Extension, MessageType::RequestIdentities],
+        "session binding should be replayed on the new upstream connection"
+    );
+}
+
 /// Start a mock SSH agent that immediately closes connections
 async fn start_disconnecting_agent(socket_path: &std::path::Path) {
     let listener = UnixListener::bind(socket_path).unwrap();
@@ -464,3 +1222,456 @@ async fn test_proxy_handles_malformed_identities_answer() {
         "should return FAILURE when upstream returns malformed response"
     );
 }
+
+#[tokio::test]
+async fn test_proxy_serves_session_over_stdio_like_stream() {
+    let temp_dir = TempDir::new().unwrap();
+    let upstream_path = temp_dir.path().join("upstream.sock");
+    start_mock_agent(
+        &upstream_path,
+        vec![
+            make_identity(ED25519_KEY_WORK),
+            make_identity(ED25519_KEY_PERSONAL),
+        ],
+    )
+    .await;
+
+    let filter = FilterEvaluator::parse(&[vec!["comment=*@work*".to_string()]]).unwrap();
+    let proxy = Arc::new(Proxy::new(Upstream::new(&upstream_path), filter));
+
+    // The stdio command serves a single session over stdin/stdout; any
+    // byte stream works the same way
+    let (mut client, server) = tokio::io::duplex(4096);
+    let session = tokio::spawn({
+        let proxy = proxy.clone();
+        async move { proxy.handle_client(server).await }
+    });
+
+    let request = AgentMessage::new(MessageType::RequestIdentities, Bytes::new());
+    AgentCodec::write(&mut client, &request).await.unwrap();
+    let response = AgentCodec::read(&mut client).await.unwrap().unwrap();
+    let identities = response.parse_identities().unwrap();
+    assert_eq!(identities.len(), 1);
+    assert!(identities[0].comment.contains("@work"));
+
+    // Closing the client ends the session
+    drop(client);
+    session.await.unwrap().unwrap();
+}
+
+/// List identities through a proxy session whose client is this process
+async fn request_identities_as_self(proxy: &Arc<Proxy>, with_peer: bool) -> Vec<Identity> {
+    use authsock_filter::agent::PeerCredentials;
+
+    let (mut client, server) = UnixStream::pair().unwrap();
+    let peer = with_peer.then(|| PeerCredentials::from_stream(&server).unwrap());
+    let session = tokio::spawn({
+        let proxy = proxy.clone();
+        async move { proxy.handle_client_from(server, peer).await }
+    });
+
+    let request = AgentMessage::new(MessageType::RequestIdentities, Bytes::new());
+    let response = roundtrip(&mut client, &request).await;
+    drop(client);
+    session.await.unwrap().unwrap();
+    response.parse_identities().unwrap()
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn test_proxy_filters_by_client_process() {
+    let temp_dir = TempDir::new().unwrap();
+    let upstream_path = temp_dir.path().join("upstream.sock");
+    start_mock_agent(
+        &upstream_path,
+        vec![
+            make_identity(ED25519_KEY_WORK),
+            make_identity(ED25519_KEY_PERSONAL),
+        ],
+    )
+    .await;
+
+    // This test binary is the client; work keys only for it, personal keys
+    // for anything but git
+    let exe = std::env::current_exe().unwrap();
+    let filter = FilterEvaluator::parse(&[
+        vec![
+            format!("client-exe={}", exe.display()),
+            "comment=*@work*".to_string(),
+        ],
+        vec![
+            "not-client-exe=git".to_string(),
+            "comment=*@personal*".to_string(),
+        ],
+    ])
+    .unwrap();
+    let proxy = Arc::new(Proxy::new(Upstream::new(&upstream_path), filter));
+
+    assert_eq!(request_identities_as_self(&proxy, true).await.len(), 2);
+
+    // Without credentials the client is unknown and both terms fail closed
+    assert!(request_identities_as_self(&proxy, false).await.is_empty());
+
+    let filter =
+        FilterEvaluator::parse(&[vec!["client-ancestor=no-such-ide".to_string()]]).unwrap();
+    let proxy = Arc::new(Proxy::new(Upstream::new(&upstream_path), filter));
+    assert!(request_identities_as_self(&proxy, true).await.is_empty());
+}
+
+#[tokio::test]
+async fn test_proxy_signs_unlisted_keys_with_upstream_comment() {
+    let temp_dir = TempDir::new().unwrap();
+    let upstream_path = temp_dir.path().join("upstream.sock");
+    let proxy_path = temp_dir.path().join("proxy.sock");
+
+    let work_key = make_identity(ED25519_KEY_WORK);
+    let personal_key = make_identity(ED25519_KEY_PERSONAL);
+    start_mock_agent(&upstream_path, vec![work_key.clone(), personal_key.clone()]).await;
+
+    let filter = FilterEvaluator::parse(&[vec!["comment=*@work*".to_string()]]).unwrap();
+    let proxy = Arc::new(Proxy::new(Upstream::new(&upstream_path), filter));
+    start_proxy_server(&proxy_path, proxy).await;
+
+    // Client A lists the work key and signs with it on its connection
+    let mut client_a = UnixStream::connect(&proxy_path).await.unwrap();
+    assert_eq!(list_on(&mut client_a).await.len(), 1);
+    let data = build_userauth_data("git", &work_key);
+    assert_eq!(
+        roundtrip(&mut client_a, &build_sign_request(&work_key, &data))
+            .await
+            .msg_type,
+        MessageType::SignResponse
+    );
+
+    // Clients signing on fresh connections (as ssh-keygen -Y sign does)
+    // never listed; the comment is looked up from upstream, so the comment
+    // filter decides as it does when listing
+    assert_eq!(
+        sign(&proxy_path, &work_key, &data).await,
+        MessageType::SignResponse,
+        "work key should be signed without listing"
+    );
+    assert_eq!(
+        sign(
+            &proxy_path,
+            &personal_key,
+            &build_userauth_data("git", &personal_key)
+        )
+        .await,
+        MessageType::Failure,
+        "personal key should be denied without listing"
+    );
+
+    // A's connection is unaffected by other clients
+    assert_eq!(
+        roundtrip(&mut client_a, &build_sign_request(&work_key, &data))
+            .await
+            .msg_type,
+        MessageType::SignResponse
+    );
+}
+
+/// Open a proxy session whose client is this process
+fn open_session_as_self(proxy: &Arc<Proxy>, with_peer: bool) -> UnixStream {
+    use authsock_filter::agent::PeerCredentials;
+
+    let (client, server) = UnixStream::pair().unwrap();
+    let peer = with_peer.then(|| PeerCredentials::from_stream(&server).unwrap());
+    let proxy = proxy.clone();
+    tokio::spawn(async move { proxy.handle_client_from(server, peer).await });
+    client
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn test_proxy_concurrent_listings_do_not_interfere() {
+    let temp_dir = TempDir::new().unwrap();
+    let upstream_path = temp_dir.path().join("upstream.sock");
+
+    let work_key = make_identity(ED25519_KEY_WORK);
+    start_mock_agent(&upstream_path, vec![work_key.clone()]).await;
+
+    // Only this test binary may use the work key
+    let exe = std::env::current_exe().unwrap();
+    let filter = FilterEvaluator::parse(&[vec![
+        format!("client-exe={}", exe.display()),
+        "comment=*@work*".to_string(),
+    ]])
+    .unwrap();
+    let proxy = Arc::new(Proxy::new(Upstream::new(&upstream_path), filter));
+
+    // Client A is identified and gets the key; client B is not and lists
+    // nothing while A's session is still open
+    let mut client_a = open_session_as_self(&proxy, true);
+    let mut client_b = open_session_as_self(&proxy, false);
+    assert_eq!(list_on(&mut client_a).await.len(), 1);
+    assert!(list_on(&mut client_b).await.is_empty());
+
+    // B's (empty) listing neither clears A's allowed keys nor grants B any
+    let data = build_userauth_data("git", &work_key);
+    let request = build_sign_request(&work_key, &data);
+    assert_eq!(
+        roundtrip(&mut client_a, &request).await.msg_type,
+        MessageType::SignResponse
+    );
+    assert_eq!(
+        roundtrip(&mut client_b, &request).await.msg_type,
+        MessageType::Failure
+    );
+}
+
+/// Build an SSH_AGENTC_LOCK or SSH_AGENTC_UNLOCK
+fn build_lock_request(msg_type: MessageType, passphrase: &str) -> AgentMessage {
+    let mut payload = BytesMut::new();
+    put_string(&mut payload, passphrase.as_bytes());
+    AgentMessage::new(msg_type, payload.freeze())
+}
+
+#[tokio::test]
+async fn test_proxy_locks_socket_locally() {
+    let temp_dir = TempDir::new().unwrap();
+    let upstream_path = temp_dir.path().join("upstream.sock");
+    let prod_path = temp_dir.path().join("prod.sock");
+    let dev_path = temp_dir.path().join("dev.sock");
+
+    let work_key = make_identity(ED25519_KEY_WORK);
+    start_mock_agent(&upstream_path, vec![work_key.clone()]).await;
+
+    let upstream = Arc::new(Upstream::new(&upstream_path));
+    let prod = Proxy::new_shared(upstream.clone(), Arc::new(FilterEvaluator::default()))
+        .with_local_lock(true);
+    let dev = Proxy::new_shared(upstream, Arc::new(FilterEvaluator::default()));
+    start_proxy_server(&prod_path, Arc::new(prod)).await;
+    start_proxy_server(&dev_path, Arc::new(dev)).await;
+
+    let lock = |msg_type, passphrase| {
+        let prod_path = prod_path.clone();
+        async move {
+            let mut stream = UnixStream::connect(&prod_path).await.unwrap();
+            roundtrip(&mut stream, &build_lock_request(msg_type, passphrase))
+                .await
+                .msg_type
+        }
+    };
+    let data = build_userauth_data("git", &work_key);
+
+    assert_eq!(lock(MessageType::Lock, "away").await, MessageType::Success);
+    assert_eq!(
+        lock(MessageType::Lock, "again").await,
+        MessageType::Failure,
+        "a locked socket cannot be locked again"
+    );
+
+    // The locked socket lists nothing and refuses to sign, for every client
+    assert!(request_identities(&prod_path).await.is_empty());
+    assert_eq!(
+        sign(&prod_path, &work_key, &data).await,
+        MessageType::Failure
+    );
+
+    // The upstream agent and the other socket are not locked
+    assert_eq!(request_identities(&dev_path).await.len(), 1);
+    assert_eq!(
+        sign(&dev_path, &work_key, &data).await,
+        MessageType::SignResponse
+    );
+
+    assert_eq!(
+        lock(MessageType::Unlock, "wrong").await,
+        MessageType::Failure
+    );
+    assert!(request_identities(&prod_path).await.is_empty());
+
+    // Unlocking is answered by the proxy (the mock agent refuses UNLOCK)
+    assert_eq!(
+        lock(MessageType::Unlock, "away").await,
+        MessageType::Success
+    );
+    assert_eq!(request_identities(&prod_path).await.len(), 1);
+    assert_eq!(
+        sign(&prod_path, &work_key, &data).await,
+        MessageType::SignResponse
+    );
+}
+
+#[tokio::test]
+async fn test_proxy_locks_idle_socket() {
+    use std::time::Duration;
+
+    let temp_dir = TempDir::new().unwrap();
+    let upstream_path = temp_dir.path().join("upstream.sock");
+    let proxy_path = temp_dir.path().join("proxy.sock");
+
+    let work_key = make_identity(ED25519_KEY_WORK);
+    start_mock_agent(&upstream_path, vec![work_key.clone()]).await;
+
+    let proxy = Arc::new(
+        Proxy::new(Upstream::new(&upstream_path), FilterEvaluator::default())
+            .with_idle_lock(Some(Duration::from_millis(500))),
+    );
+    start_proxy_server(&proxy_path, proxy.clone()).await;
+    let data = build_userauth_data("git", &work_key);
+
+    // Each signature restarts the idle timeout
+    for _ in 0..2 {
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(
+            sign(&proxy_path, &work_key, &data).await,
+            MessageType::SignResponse
+        );
+    }
+
+    // Listing is not use; after the timeout the socket is locked
+    tokio::time::sleep(Duration::from_millis(600)).await;
+    assert!(request_identities(&proxy_path).await.is_empty());
+    assert!(proxy.is_locked());
+    assert_eq!(
+        sign(&proxy_path, &work_key, &data).await,
+        MessageType::Failure
+    );
+
+    // A client cannot UNLOCK it (nor is UNLOCK forwarded upstream)
+    let mut stream = UnixStream::connect(&proxy_path).await.unwrap();
+    assert_eq!(
+        roundtrip(&mut stream, &build_lock_request(MessageType::Unlock, ""))
+            .await
+            .msg_type,
+        MessageType::Failure
+    );
+    assert!(list_on(&mut stream).await.is_empty());
+
+    // Only the operator resumes it (SIGUSR1 in the run command)
+    assert!(proxy.resume());
+    assert_eq!(list_on(&mut stream).await.len(), 1);
+    assert_eq!(
+        sign(&proxy_path, &work_key, &data).await,
+        MessageType::SignResponse
+    );
+}
+
+#[tokio::test]
+async fn test_proxy_unlocks_idle_socket_with_passphrase() {
+    use std::time::Duration;
+
+    let temp_dir = TempDir::new().unwrap();
+    let upstream_path = temp_dir.path().join("upstream.sock");
+    let proxy_path = temp_dir.path().join("proxy.sock");
+
+    let work_key = make_identity(ED25519_KEY_WORK);
+    start_mock_agent(&upstream_path, vec![work_key.clone()]).await;
+
+    let proxy = Arc::new(
+        Proxy::new(Upstream::new(&upstream_path), FilterEvaluator::default())
+            .with_idle_lock(Some(Duration::from_millis(100)))
+            .with_idle_passphrase(b"resume")
+            .unwrap(),
+    );
+    start_proxy_server(&proxy_path, proxy.clone()).await;
+
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let mut stream = UnixStream::connect(&proxy_path).await.unwrap();
+    assert!(list_on(&mut stream).await.is_empty());
+    assert!(proxy.is_locked());
+
+    // UNLOCK with the idle passphrase resumes this socket
+    assert_eq!(
+        roundtrip(
+            &mut stream,
+            &build_lock_request(MessageType::Unlock, "wrong")
+        )
+        .await
+        .msg_type,
+        MessageType::Failure
+    );
+    assert_eq!(
+        roundtrip(
+            &mut stream,
+            &build_lock_request(MessageType::Unlock, "resume")
+        )
+        .await
+        .msg_type,
+        MessageType::Success
+    );
+    assert_eq!(list_on(&mut stream).await.len(), 1);
+}
+
+#[tokio::test]
+async fn test_proxy_confirms_sign_requests() {
+    use authsock_filter::agent::Confirm;
+
+    let temp_dir = TempDir::new().unwrap();
+    let upstream_path = temp_dir.path().join("upstream.sock");
+    let proxy_path = temp_dir.path().join("proxy.sock");
+    let asked = temp_dir.path().join("asked");
+
+    let work_key = make_identity(ED25519_KEY_WORK);
+    let personal_key = make_identity(ED25519_KEY_PERSONAL);
+    let dev_key = make_identity(ED25519_KEY_DEV);
+    start_mock_agent(
+        &upstream_path,
+        vec![work_key.clone(), personal_key.clone(), dev_key.clone()],
+    )
+    .await;
+
+    // The "user" approves only the work key, and notes every question
+    let filter = FilterEvaluator::parse(&[vec!["comment=*.example.com".to_string()]]).unwrap();
+    let script = temp_dir.path().join("askpass");
+    std::fs::write(
+        &script,
+        format!(
+            "echo \"$AUTHSOCK_FILTER_COMMENT\" >> {}\n\
+             test \"$AUTHSOCK_FILTER_COMMENT\" = user@work.example.com\n",
+            asked.display()
+        ),
+    )
+    .unwrap();
+    let program = format!("sh {}", script.display());
+    let proxy = Proxy::new(Upstream::new(&upstream_path), filter)
+        .with_confirm(Some(Confirm::with_program(&program).unwrap()));
+    start_proxy_server(&proxy_path, Arc::new(proxy)).await;
+
+    // Listing needs no confirmation
+    assert_eq!(request_identities(&proxy_path).await.len(), 3);
+    assert!(!asked.exists());
+
+    let sign_with = |key: &Identity| {
+        let data = build_userauth_data("git", key);
+        let key = key.clone();
+        let proxy_path = proxy_path.clone();
+        async move { sign(&proxy_path, &key, &data).await }
+    };
+    assert_eq!(sign_with(&work_key).await, MessageType::SignResponse);
+    assert_eq!(
+        sign_with(&personal_key).await,
+        MessageType::Failure,
+        "declined by the user"
+    );
+    // dev@work.example.com passes the filter but is declined as well
+    assert_eq!(sign_with(&dev_key).await, MessageType::Failure);
+
+    // Keys the filter denies are never shown to the user
+    let filter = FilterEvaluator::parse(&[vec!["comment=user@work*".to_string()]]).unwrap();
+    let strict_path = temp_dir.path().join("strict.sock");
+    let proxy = Proxy::new(Upstream::new(&upstream_path), filter)
+        .with_confirm(Some(Confirm::with_program(&program).unwrap()));
+    start_proxy_server(&strict_path, Arc::new(proxy)).await;
+    assert_eq!(
+        sign(
+            &strict_path,
+            &personal_key,
+            &build_userauth_data("git", &personal_key)
+        )
+        .await,
+        MessageType::Failure
+    );
+
+    let asked = std::fs::read_to_string(&asked).unwrap();
+    assert_eq!(
+        asked.lines().collect::<Vec<_>>(),
+        [
+            "user@work.example.com",
+            "user@personal.example.com",
+            "dev@work.example.com"
+        ]
+    );
+}